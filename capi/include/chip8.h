@@ -0,0 +1,102 @@
+// generated from src/lib.rs by tests/header.rs, do not edit
+#ifndef CHIP8_H
+#define CHIP8_H
+
+#include <stdbool.h>
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+// opaque machine
+typedef struct Chip8 Chip8;
+
+// Display width in pixels.
+#define CHIP8_WIDTH 64
+
+// Display height in pixels.
+#define CHIP8_HEIGHT 32
+
+// Size of a saved state in bytes.
+#define CHIP8_STATE_SIZE 6217
+
+// chip8_step_frame status: the frame was executed.
+#define CHIP8_OK 0
+
+// chip8_step_frame status: the machine stopped at an opcode which is no instruction.
+#define CHIP8_UNKNOWN_OPCODE 1
+
+// chip8_step_frame status: the machine stopped at a CALL with the 16 stack levels in use.
+#define CHIP8_STACK_OVERFLOW 2
+
+// chip8_step_frame status: the machine stopped at a RET with an empty stack.
+#define CHIP8_STACK_UNDERFLOW 3
+
+// Quirk flag: `8XY1`, `8XY2`, `8XY3` reset VF to 0.
+#define CHIP8_QUIRK_VF_RESET 1
+
+// Quirk flag: `8XY6`, `8XYE` shift Vx in place instead of storing shifted Vy.
+#define CHIP8_QUIRK_SHIFT_VX 2
+
+// Quirk flag: `FX55`, `FX65` leave I unchanged instead of incrementing it past the last register.
+#define CHIP8_QUIRK_LOAD_STORE_KEEP_I 4
+
+// Quirk flag: `BXNN` jumps to XNN + Vx instead of NNN + V0.
+#define CHIP8_QUIRK_JUMP_VX 8
+
+// Quirks preset: the COSMAC VIP, except that VF is kept by logic operations.
+#define CHIP8_QUIRKS_DEFAULT 0
+
+// Quirks preset: the original COSMAC VIP interpreter.
+#define CHIP8_QUIRKS_COSMAC 1
+
+// Quirks preset: SUPER-CHIP 1.1, which most modern games are written for.
+#define CHIP8_QUIRKS_SCHIP 14
+
+// Creates a machine with CHIP8_QUIRKS_DEFAULT, release it with chip8_free.
+Chip8 *chip8_new(void);
+
+// Creates a machine with the CHIP8_QUIRK_* flags or a CHIP8_QUIRKS_* preset, release it with
+// chip8_free.
+Chip8 *chip8_new_with_quirks(uint32_t quirks);
+
+// Sets the CHIP8_QUIRK_* flags or a CHIP8_QUIRKS_* preset, unknown flags are ignored.
+void chip8_set_quirks(Chip8 *chip8, uint32_t quirks);
+
+// Returns the CHIP8_QUIRK_* flags of the machine.
+uint32_t chip8_quirks(const Chip8 *chip8);
+
+// Releases a machine created by chip8_new.
+void chip8_free(Chip8 *chip8);
+
+// Resets the machine, keeping its quirks, and loads a ROM at 0x200, returns false if it is
+// larger than 0xE00 bytes.
+bool chip8_load_rom(Chip8 *chip8, const uint8_t *rom, size_t len);
+
+// Executes one 60hz frame: instructions_per_frame instructions, then one timer tick.
+//
+// Returns CHIP8_OK, or the CHIP8_* status of the fault which stopped the machine. A stopped
+// machine stays at the faulting instruction until a ROM or state is loaded.
+int32_t chip8_step_frame(Chip8 *chip8, uint32_t instructions_per_frame);
+
+// Presses or releases key 0x0-0xF, other keys are ignored.
+void chip8_set_key(Chip8 *chip8, uint8_t key, bool pressed);
+
+// Returns the CHIP8_WIDTH x CHIP8_HEIGHT display, row-major, true is lit. The pointer stays
+// valid until the machine is freed.
+const bool *chip8_framebuffer(const Chip8 *chip8);
+
+// Writes CHIP8_STATE_SIZE bytes of machine state, returns false if len is too small.
+bool chip8_save_state(const Chip8 *chip8, uint8_t *state, size_t len);
+
+// Restores a state written by chip8_save_state, returns false and leaves the machine unchanged
+// if it is invalid.
+bool chip8_load_state(Chip8 *chip8, const uint8_t *state, size_t len);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif
@@ -0,0 +1,208 @@
+//! C API of the emulator core.
+//!
+//! `include/chip8.h` is generated from this file by `tests/header.rs`: every `pub const` and
+//! `extern "C"` function here is declared there, with its doc comment.
+use chip8_emulator::{Chip8, Fault, Quirks};
+
+/// Display width in pixels.
+pub const CHIP8_WIDTH: usize = 64;
+/// Display height in pixels.
+pub const CHIP8_HEIGHT: usize = 32;
+/// Size of a saved state in bytes.
+pub const CHIP8_STATE_SIZE: usize = 6217;
+
+/// chip8_step_frame status: the frame was executed.
+pub const CHIP8_OK: i32 = 0;
+/// chip8_step_frame status: the machine stopped at an opcode which is no instruction.
+pub const CHIP8_UNKNOWN_OPCODE: i32 = 1;
+/// chip8_step_frame status: the machine stopped at a CALL with the 16 stack levels in use.
+pub const CHIP8_STACK_OVERFLOW: i32 = 2;
+/// chip8_step_frame status: the machine stopped at a RET with an empty stack.
+pub const CHIP8_STACK_UNDERFLOW: i32 = 3;
+
+/// Quirk flag: `8XY1`, `8XY2`, `8XY3` reset VF to 0.
+pub const CHIP8_QUIRK_VF_RESET: u32 = 1;
+/// Quirk flag: `8XY6`, `8XYE` shift Vx in place instead of storing shifted Vy.
+pub const CHIP8_QUIRK_SHIFT_VX: u32 = 2;
+/// Quirk flag: `FX55`, `FX65` leave I unchanged instead of incrementing it past the last register.
+pub const CHIP8_QUIRK_LOAD_STORE_KEEP_I: u32 = 4;
+/// Quirk flag: `BXNN` jumps to XNN + Vx instead of NNN + V0.
+pub const CHIP8_QUIRK_JUMP_VX: u32 = 8;
+/// Quirks preset: the COSMAC VIP, except that VF is kept by logic operations.
+pub const CHIP8_QUIRKS_DEFAULT: u32 = 0;
+/// Quirks preset: the original COSMAC VIP interpreter.
+pub const CHIP8_QUIRKS_COSMAC: u32 = 1;
+/// Quirks preset: SUPER-CHIP 1.1, which most modern games are written for.
+pub const CHIP8_QUIRKS_SCHIP: u32 = 14;
+
+// NOTE: header constants are literals, keep them in sync with the core
+const _: () = assert!(CHIP8_STATE_SIZE == chip8_emulator::STATE_SIZE);
+const _: () = assert!(CHIP8_WIDTH * CHIP8_HEIGHT == 64 * 32);
+
+fn quirks_from_flags(flags: u32) -> Quirks {
+    Quirks {
+        vf_reset: flags & CHIP8_QUIRK_VF_RESET != 0,
+        shift_vx: flags & CHIP8_QUIRK_SHIFT_VX != 0,
+        load_store_keep_i: flags & CHIP8_QUIRK_LOAD_STORE_KEEP_I != 0,
+        jump_vx: flags & CHIP8_QUIRK_JUMP_VX != 0,
+    }
+}
+
+fn quirks_to_flags(quirks: Quirks) -> u32 {
+    let flag = |set: bool, flag: u32| if set { flag } else { 0 };
+    flag(quirks.vf_reset, CHIP8_QUIRK_VF_RESET)
+        | flag(quirks.shift_vx, CHIP8_QUIRK_SHIFT_VX)
+        | flag(quirks.load_store_keep_i, CHIP8_QUIRK_LOAD_STORE_KEEP_I)
+        | flag(quirks.jump_vx, CHIP8_QUIRK_JUMP_VX)
+}
+
+/// Creates a machine with CHIP8_QUIRKS_DEFAULT, release it with chip8_free.
+#[no_mangle]
+pub extern "C" fn chip8_new() -> *mut Chip8 {
+    Box::into_raw(Box::new(Chip8::new()))
+}
+
+/// Creates a machine with the CHIP8_QUIRK_* flags or a CHIP8_QUIRKS_* preset, release it with
+/// chip8_free.
+#[no_mangle]
+pub extern "C" fn chip8_new_with_quirks(quirks: u32) -> *mut Chip8 {
+    let mut chip8 = Chip8::new();
+    chip8.set_quirks(quirks_from_flags(quirks));
+    Box::into_raw(Box::new(chip8))
+}
+
+/// Sets the CHIP8_QUIRK_* flags or a CHIP8_QUIRKS_* preset, unknown flags are ignored.
+///
+/// # Safety
+///
+/// `chip8` must be a valid machine.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_quirks(chip8: *mut Chip8, quirks: u32) {
+    (*chip8).set_quirks(quirks_from_flags(quirks));
+}
+
+/// Returns the CHIP8_QUIRK_* flags of the machine.
+///
+/// # Safety
+///
+/// `chip8` must be a valid machine.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_quirks(chip8: *const Chip8) -> u32 {
+    quirks_to_flags((*chip8).quirks())
+}
+
+/// Releases a machine created by chip8_new.
+///
+/// # Safety
+///
+/// `chip8` must be returned by chip8_new and not freed yet, or NULL.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_free(chip8: *mut Chip8) {
+    if !chip8.is_null() {
+        drop(Box::from_raw(chip8));
+    }
+}
+
+/// Resets the machine, keeping its quirks, and loads a ROM at 0x200, returns false if it is
+/// larger than 0xE00 bytes.
+///
+/// # Safety
+///
+/// `chip8` must be a valid machine and `rom` must point to `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_rom(chip8: *mut Chip8, rom: *const u8, len: usize) -> bool {
+    if len > 0x1000 - 0x200 {
+        return false;
+    }
+    let chip8 = &mut *chip8;
+    let quirks = chip8.quirks();
+    *chip8 = Chip8::new();
+    chip8.set_quirks(quirks);
+    chip8.load_program(std::slice::from_raw_parts(rom, len));
+    true
+}
+
+/// Executes one 60hz frame: instructions_per_frame instructions, then one timer tick.
+///
+/// Returns CHIP8_OK, or the CHIP8_* status of the fault which stopped the machine. A stopped
+/// machine stays at the faulting instruction until a ROM or state is loaded.
+///
+/// # Safety
+///
+/// `chip8` must be a valid machine.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_step_frame(chip8: *mut Chip8, instructions_per_frame: u32) -> i32 {
+    match (*chip8).step_frame(instructions_per_frame as usize) {
+        Ok(()) => CHIP8_OK,
+        Err(Fault::UnknownOpcode(_)) => CHIP8_UNKNOWN_OPCODE,
+        Err(Fault::StackOverflow) => CHIP8_STACK_OVERFLOW,
+        Err(Fault::StackUnderflow) => CHIP8_STACK_UNDERFLOW,
+    }
+}
+
+/// Presses or releases key 0x0-0xF, other keys are ignored.
+///
+/// # Safety
+///
+/// `chip8` must be a valid machine.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_set_key(chip8: *mut Chip8, key: u8, pressed: bool) {
+    if let Some(state) = (*chip8).keyboard.get_mut(key as usize) {
+        *state = pressed;
+    }
+}
+
+/// Returns the CHIP8_WIDTH x CHIP8_HEIGHT display, row-major, true is lit. The pointer stays
+/// valid until the machine is freed.
+///
+/// # Safety
+///
+/// `chip8` must be a valid machine.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_framebuffer(chip8: *const Chip8) -> *const bool {
+    (*chip8).display.as_ptr()
+}
+
+/// Writes CHIP8_STATE_SIZE bytes of machine state, returns false if len is too small.
+///
+/// # Safety
+///
+/// `chip8` must be a valid machine and `state` must point to `len` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_save_state(chip8: *const Chip8, state: *mut u8, len: usize) -> bool {
+    if len < CHIP8_STATE_SIZE {
+        return false;
+    }
+    let state = &mut *(state as *mut [u8; CHIP8_STATE_SIZE]);
+    (*chip8).save_state(state);
+    true
+}
+
+/// Restores a state written by chip8_save_state, returns false and leaves the machine unchanged
+/// if it is invalid.
+///
+/// # Safety
+///
+/// `chip8` must be a valid machine and `state` must point to `len` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn chip8_load_state(chip8: *mut Chip8, state: *const u8, len: usize) -> bool {
+    (*chip8)
+        .load_state(std::slice::from_raw_parts(state, len))
+        .is_ok()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn quirks_presets() {
+        assert_eq!(quirks_from_flags(CHIP8_QUIRKS_DEFAULT), Quirks::default());
+        assert_eq!(quirks_from_flags(CHIP8_QUIRKS_COSMAC), Quirks::cosmac());
+        assert_eq!(quirks_from_flags(CHIP8_QUIRKS_SCHIP), Quirks::schip());
+        for flags in 0..16 {
+            assert_eq!(quirks_to_flags(quirks_from_flags(flags)), flags);
+        }
+        assert_eq!(quirks_from_flags(0xFFFF_FFF0), Quirks::default());
+    }
+}
@@ -0,0 +1,100 @@
+// exercises the C API, exits with 0 on success
+#include <stdio.h>
+#include <stdlib.h>
+
+#include "chip8.h"
+
+#define CHECK(condition)                                                     \
+    do {                                                                     \
+        if (!(condition)) {                                                  \
+            fprintf(stderr, "%s:%d: check failed: %s\n", __FILE__, __LINE__, \
+                    #condition);                                             \
+            exit(1);                                                         \
+        }                                                                    \
+    } while (0)
+
+static int lit_pixels(const Chip8 *chip8) {
+    const bool *framebuffer = chip8_framebuffer(chip8);
+    int count = 0;
+    for (int i = 0; i < CHIP8_WIDTH * CHIP8_HEIGHT; i++) {
+        count += framebuffer[i];
+    }
+    return count;
+}
+
+int main(void) {
+    // LD V0, K; LD F, V0; DRW V1, V1, 5; JP 0x206
+    const uint8_t rom[] = {0xF0, 0x0A, 0xF0, 0x29, 0xD1, 0x15, 0x12, 0x06};
+    static uint8_t too_large[0x1000];
+    static uint8_t state[CHIP8_STATE_SIZE];
+
+    Chip8 *chip8 = chip8_new();
+    CHECK(chip8 != NULL);
+    CHECK(!chip8_load_rom(chip8, too_large, sizeof(too_large)));
+    CHECK(chip8_load_rom(chip8, rom, sizeof(rom)));
+
+    // waiting for a key
+    CHECK(chip8_step_frame(chip8, 10) == CHIP8_OK);
+    CHECK(lit_pixels(chip8) == 0);
+    chip8_set_key(chip8, 0x10, true);
+    CHECK(chip8_step_frame(chip8, 10) == CHIP8_OK);
+    CHECK(lit_pixels(chip8) == 0);
+
+    // glyph "8" is drawn at the top left
+    chip8_set_key(chip8, 0x8, true);
+    CHECK(chip8_step_frame(chip8, 10) == CHIP8_OK);
+    const bool *framebuffer = chip8_framebuffer(chip8);
+    CHECK(framebuffer[0] && framebuffer[3] && !framebuffer[4]);
+    CHECK(framebuffer[CHIP8_WIDTH] && !framebuffer[CHIP8_WIDTH + 1]);
+    CHECK(lit_pixels(chip8) == 16);
+
+    // states restore the display
+    CHECK(!chip8_save_state(chip8, state, CHIP8_STATE_SIZE - 1));
+    CHECK(chip8_save_state(chip8, state, sizeof(state)));
+    CHECK(chip8_load_rom(chip8, rom, sizeof(rom)));
+    CHECK(lit_pixels(chip8) == 0);
+    CHECK(!chip8_load_state(chip8, state, sizeof(state) - 1));
+    CHECK(chip8_load_state(chip8, state, sizeof(state)));
+    CHECK(lit_pixels(chip8) == 16);
+
+    // bad ROMs stop the machine instead of aborting the process
+    const uint8_t invalid[] = {0x60, 0x01, 0xFF, 0xFF};
+    CHECK(chip8_load_rom(chip8, invalid, sizeof(invalid)));
+    CHECK(chip8_step_frame(chip8, 10) == CHIP8_UNKNOWN_OPCODE);
+    CHECK(chip8_step_frame(chip8, 10) == CHIP8_UNKNOWN_OPCODE);
+    const uint8_t ret[] = {0x00, 0xEE};
+    CHECK(chip8_load_rom(chip8, ret, sizeof(ret)));
+    CHECK(chip8_step_frame(chip8, 10) == CHIP8_STACK_UNDERFLOW);
+    const uint8_t call[] = {0x22, 0x00};
+    CHECK(chip8_load_rom(chip8, call, sizeof(call)));
+    CHECK(chip8_step_frame(chip8, 20) == CHIP8_STACK_OVERFLOW);
+    CHECK(chip8_load_rom(chip8, rom, sizeof(rom)));
+    CHECK(chip8_step_frame(chip8, 10) == CHIP8_OK);
+
+    chip8_free(chip8);
+
+    // LD V0, 0x01; LD V1, 0x04; SHR V0, V1; LD F, V0; DRW V2, V2, 5; JP 0x20A
+    // draws "2" (V0 = V1 >> 1) by default and "0" (V0 >>= 1) with CHIP8_QUIRK_SHIFT_VX
+    const uint8_t shift[] = {0x60, 0x01, 0x61, 0x04, 0x80, 0x16,
+                             0xF0, 0x29, 0xD2, 0x25, 0x12, 0x0A};
+    chip8 = chip8_new();
+    CHECK(chip8_quirks(chip8) == CHIP8_QUIRKS_DEFAULT);
+    CHECK(chip8_load_rom(chip8, shift, sizeof(shift)));
+    CHECK(chip8_step_frame(chip8, 10) == CHIP8_OK);
+    CHECK(!chip8_framebuffer(chip8)[CHIP8_WIDTH]);
+    chip8_set_quirks(chip8, CHIP8_QUIRK_SHIFT_VX | CHIP8_QUIRK_JUMP_VX);
+    CHECK(chip8_quirks(chip8) == (CHIP8_QUIRK_SHIFT_VX | CHIP8_QUIRK_JUMP_VX));
+    chip8_free(chip8);
+
+    // loading a ROM keeps the quirks
+    chip8 = chip8_new_with_quirks(CHIP8_QUIRKS_SCHIP);
+    CHECK(chip8_quirks(chip8) == CHIP8_QUIRKS_SCHIP);
+    CHECK(chip8_load_rom(chip8, shift, sizeof(shift)));
+    CHECK(chip8_quirks(chip8) == CHIP8_QUIRKS_SCHIP);
+    CHECK(chip8_step_frame(chip8, 10) == CHIP8_OK);
+    CHECK(chip8_framebuffer(chip8)[CHIP8_WIDTH]);
+    chip8_free(chip8);
+    chip8_free(NULL);
+    printf("ok\n");
+    return 0;
+}
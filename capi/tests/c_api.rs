@@ -0,0 +1,49 @@
+// compiles tests/api_test.c with the system C compiler against the shared library and runs it
+use std::path::PathBuf;
+use std::process::Command;
+
+#[test]
+fn c_program() {
+    let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    // NOTE: the libraries are built into target/<profile>/deps, next to the test binary
+    let exe = std::env::current_exe().unwrap();
+    let lib_dir = exe.parent().unwrap();
+    let program = lib_dir.join("api_test");
+
+    let compiler = std::env::var("CC").unwrap_or_else(|_| "cc".to_string());
+    let status = Command::new(&compiler)
+        .arg("-std=c99")
+        .arg("-Wall")
+        .arg("-Werror")
+        .arg("-I")
+        .arg(manifest_dir.join("include"))
+        .arg(manifest_dir.join("tests").join("api_test.c"))
+        .arg("-L")
+        .arg(lib_dir)
+        .arg(format!("-Wl,-rpath,{}", lib_dir.display()))
+        .arg("-lchip8")
+        .arg("-o")
+        .arg(&program)
+        .status()
+        .unwrap_or_else(|e| panic!("failed to run {}: {}", compiler, e));
+    assert!(status.success(), "failed to compile api_test.c");
+
+    // NOTE: cargo puts target/<profile> on LD_LIBRARY_PATH, which wins over the rpath and may
+    // hold an older libchip8.so
+    let mut library_path = vec![lib_dir.to_path_buf()];
+    if let Some(path) = std::env::var_os("LD_LIBRARY_PATH") {
+        library_path.extend(std::env::split_paths(&path));
+    }
+    let output = Command::new(&program)
+        .env(
+            "LD_LIBRARY_PATH",
+            std::env::join_paths(library_path).unwrap(),
+        )
+        .output()
+        .unwrap();
+    assert!(
+        output.status.success(),
+        "api_test failed: {}",
+        String::from_utf8_lossy(&output.stderr)
+    );
+}
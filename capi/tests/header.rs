@@ -0,0 +1,186 @@
+// checks that include/chip8.h matches the `pub const` items and `extern "C"` functions of
+// src/lib.rs, run with CHIP8_UPDATE_HEADER=1 to regenerate it
+//
+// NOTE: only the types used by the API are supported, an unknown type fails the test
+use std::fs;
+use std::path::PathBuf;
+
+const HEADER_PATH: &str = "include/chip8.h";
+
+fn c_type(rust_type: &str) -> String {
+    let rust_type = rust_type.trim();
+    if let Some(pointee) = rust_type.strip_prefix("*const ") {
+        return format!("const {} *", c_type(pointee));
+    }
+    if let Some(pointee) = rust_type.strip_prefix("*mut ") {
+        return format!("{} *", c_type(pointee));
+    }
+    match rust_type {
+        "Chip8" => "Chip8",
+        "bool" => "bool",
+        "u8" => "uint8_t",
+        "u16" => "uint16_t",
+        "u32" => "uint32_t",
+        "usize" => "size_t",
+        "i32" => "int32_t",
+        _ => panic!("{}: unsupported type in C API: {}", HEADER_PATH, rust_type),
+    }
+    .to_string()
+}
+
+// "name: Type" -> "Type name", pointers keep the star next to the name
+fn c_parameter(parameter: &str) -> String {
+    let mut split = parameter.splitn(2, ':');
+    let name = split.next().unwrap().trim();
+    let c_type = c_type(split.next().expect("parameter without type"));
+    if c_type.ends_with('*') {
+        format!("{}{}", c_type, name)
+    } else {
+        format!("{} {}", c_type, name)
+    }
+}
+
+// "fn name(a: T, b: U) -> R" -> "R name(T a, U b);"
+fn c_function(signature: &str) -> String {
+    let signature = &signature[(signature.find("fn ").unwrap() + 3)..];
+    let open = signature.find('(').unwrap();
+    let close = signature.rfind(')').unwrap();
+    let name = &signature[..open];
+    let parameters: Vec<String> = signature[(open + 1)..close]
+        .split(',')
+        .map(str::trim)
+        .filter(|p| !p.is_empty())
+        .map(c_parameter)
+        .collect();
+    let return_type = match signature[(close + 1)..].trim().strip_prefix("->") {
+        Some(rust_type) => c_type(rust_type),
+        None => "void".to_string(),
+    };
+    let parameters = if parameters.is_empty() {
+        "void".to_string()
+    } else {
+        parameters.join(", ")
+    };
+    if return_type.ends_with('*') {
+        format!("{}{}({});", return_type, name, parameters)
+    } else {
+        format!("{} {}({});", return_type, name, parameters)
+    }
+}
+
+fn generate(source: &str) -> String {
+    let mut declarations = Vec::new();
+    let mut docs: Vec<String> = Vec::new();
+    let mut exported = 0;
+    let mut functions = 0;
+    let mut lines = source.lines();
+    while let Some(line) = lines.next() {
+        let line = line.trim();
+        if let Some(doc) = line.strip_prefix("///") {
+            docs.push(format!("//{}", doc).trim_end().to_string());
+            continue;
+        }
+        if line.starts_with("#[") {
+            if line == "#[no_mangle]" {
+                exported += 1;
+            }
+            continue;
+        }
+
+        let declaration = if let Some(constant) = line.strip_prefix("pub const ") {
+            // "NAME: type = value;"
+            let name = constant.split(':').next().unwrap().trim();
+            let value = constant
+                .split('=')
+                .nth(1)
+                .unwrap()
+                .trim_end_matches(';')
+                .trim();
+            Some(format!("#define {} {}", name, value))
+        } else if line.starts_with("pub extern \"C\" fn")
+            || line.starts_with("pub unsafe extern \"C\" fn")
+        {
+            // NOTE: rustfmt may wrap long signatures
+            let mut signature = line.to_string();
+            while !signature.contains('{') {
+                signature.push(' ');
+                signature.push_str(lines.next().expect("unterminated signature").trim());
+            }
+            let signature = signature
+                .split('{')
+                .next()
+                .unwrap()
+                .replace("( ", "(")
+                .replace(", )", ")");
+            functions += 1;
+            Some(c_function(&signature))
+        } else {
+            None
+        };
+
+        if let Some(declaration) = declaration {
+            // NOTE: safety sections are for Rust callers
+            if let Some(safety) = docs.iter().position(|doc| doc == "// # Safety") {
+                docs.truncate(safety);
+            }
+            while docs.last().map(|doc| doc == "//") == Some(true) {
+                docs.pop();
+            }
+            let mut text = docs.join("\n");
+            if !text.is_empty() {
+                text.push('\n');
+            }
+            text.push_str(&declaration);
+            declarations.push(text);
+        }
+        docs.clear();
+    }
+    // NOTE: fails instead of leaving out a function which is not declared as expected
+    assert_eq!(
+        functions, exported,
+        "src/lib.rs: every #[no_mangle] function must be `pub extern \"C\" fn` or `pub unsafe extern \"C\" fn`"
+    );
+
+    format!(
+        "// generated from src/lib.rs by tests/header.rs, do not edit\n\
+         #ifndef CHIP8_H\n\
+         #define CHIP8_H\n\
+         \n\
+         #include <stdbool.h>\n\
+         #include <stddef.h>\n\
+         #include <stdint.h>\n\
+         \n\
+         #ifdef __cplusplus\n\
+         extern \"C\" {{\n\
+         #endif\n\
+         \n\
+         // opaque machine\n\
+         typedef struct Chip8 Chip8;\n\
+         \n\
+         {}\n\
+         \n\
+         #ifdef __cplusplus\n\
+         }}\n\
+         #endif\n\
+         \n\
+         #endif\n",
+        declarations.join("\n\n")
+    )
+}
+
+#[test]
+fn header_is_up_to_date() {
+    let manifest_dir = PathBuf::from(env!("CARGO_MANIFEST_DIR"));
+    let source = fs::read_to_string(manifest_dir.join("src").join("lib.rs")).unwrap();
+    let header = generate(&source);
+    let path = manifest_dir.join(HEADER_PATH);
+    if std::env::var_os("CHIP8_UPDATE_HEADER").is_some() {
+        fs::write(&path, &header).unwrap();
+    }
+    let current = fs::read_to_string(&path).unwrap_or_default();
+    assert!(
+        current == header,
+        "{} is out of date, regenerate it with CHIP8_UPDATE_HEADER=1 cargo test -p chip8-capi",
+        HEADER_PATH
+    );
+}
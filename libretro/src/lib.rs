@@ -0,0 +1,615 @@
+//! libretro core, runs the emulator in any libretro frontend.
+//!
+//! Content is a ROM (`.ch8`, `.c8`) or Octo source (`.8o`), compiled on load. Core options
+//! select the quirks preset, the palette and the number of instructions per frame. The keypad
+//! is mapped to the keyboard like the SDL frontend (`1234`/`QWER`/`ASDF`/`ZXCV`) and partly to
+//! the joypad, see [`JOYPAD_KEYS`].
+mod ffi;
+
+use chip8_emulator::capture::Palette;
+use chip8_emulator::{octo, Chip8, Quirks, STATE_SIZE};
+use ffi::*;
+use std::ffi::{CStr, CString};
+use std::os::raw::{c_char, c_uint, c_void};
+use std::sync::Mutex;
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+const MAX_ROM_SIZE: usize = 0x1000 - 0x200;
+
+const FPS: f64 = 60.0;
+const SAMPLE_RATE: f64 = 44100.0;
+const SAMPLES_PER_FRAME: usize = 735;
+// NOTE: 441hz square wave
+const BEEP_HALF_PERIOD: usize = 50;
+const BEEP_AMPLITUDE: i16 = 0x1000;
+
+macro_rules! c_str {
+    ($s:expr) => {
+        concat!($s, "\0").as_ptr() as *const c_char
+    };
+}
+
+// NOTE: keys are passed to the frontend as C strings
+const QUIRKS_KEY: &str = "chip8_quirks\0";
+const PALETTE_KEY: &str = "chip8_palette\0";
+const SPEED_KEY: &str = "chip8_instructions_per_frame\0";
+
+// named palettes of the palette option, first is the default
+const PALETTES: [(&str, Palette); 4] = [
+    (
+        "white",
+        Palette {
+            foreground: [0xFF, 0xFF, 0xFF],
+            background: [0x00, 0x00, 0x00],
+        },
+    ),
+    (
+        "green",
+        Palette {
+            foreground: [0x33, 0xFF, 0x66],
+            background: [0x00, 0x1A, 0x00],
+        },
+    ),
+    (
+        "amber",
+        Palette {
+            foreground: [0xFF, 0xB0, 0x00],
+            background: [0x1A, 0x10, 0x00],
+        },
+    ),
+    (
+        "lcd",
+        Palette {
+            foreground: [0x0F, 0x38, 0x0F],
+            background: [0x9B, 0xBC, 0x0F],
+        },
+    ),
+];
+
+const DEFAULT_INSTRUCTIONS_PER_FRAME: usize = 10;
+// NOTE: 3 seconds
+const MESSAGE_FRAMES: c_uint = 180;
+
+/// Joypad buttons of port 0 and the keys they press: the directions on 2/4/6/8, which most
+/// games use for movement, and the rest on the remaining common keys.
+pub const JOYPAD_KEYS: [(c_uint, u8); 12] = [
+    (RETRO_DEVICE_ID_JOYPAD_UP, 0x2),
+    (RETRO_DEVICE_ID_JOYPAD_DOWN, 0x8),
+    (RETRO_DEVICE_ID_JOYPAD_LEFT, 0x4),
+    (RETRO_DEVICE_ID_JOYPAD_RIGHT, 0x6),
+    (RETRO_DEVICE_ID_JOYPAD_A, 0x5),
+    (RETRO_DEVICE_ID_JOYPAD_B, 0x0),
+    (RETRO_DEVICE_ID_JOYPAD_X, 0x1),
+    (RETRO_DEVICE_ID_JOYPAD_Y, 0x3),
+    (RETRO_DEVICE_ID_JOYPAD_L, 0x7),
+    (RETRO_DEVICE_ID_JOYPAD_R, 0x9),
+    (RETRO_DEVICE_ID_JOYPAD_SELECT, 0xE),
+    (RETRO_DEVICE_ID_JOYPAD_START, 0xF),
+];
+
+// RETROK codes (ASCII for letters and digits) of the keypad layout
+const KEYBOARD_KEYS: [(u8, u8); 16] = [
+    (b'1', 0x1),
+    (b'2', 0x2),
+    (b'3', 0x3),
+    (b'4', 0xC),
+    (b'q', 0x4),
+    (b'w', 0x5),
+    (b'e', 0x6),
+    (b'r', 0xD),
+    (b'a', 0x7),
+    (b's', 0x8),
+    (b'd', 0x9),
+    (b'f', 0xE),
+    (b'z', 0xA),
+    (b'x', 0x0),
+    (b'c', 0xB),
+    (b'v', 0xF),
+];
+
+#[derive(Clone, Copy)]
+struct Callbacks {
+    environment: Option<RetroEnvironment>,
+    video_refresh: Option<RetroVideoRefresh>,
+    audio_sample_batch: Option<RetroAudioSampleBatch>,
+    input_poll: Option<RetroInputPoll>,
+    input_state: Option<RetroInputState>,
+    log: Option<RetroLogPrintf>,
+}
+
+static CALLBACKS: Mutex<Callbacks> = Mutex::new(Callbacks {
+    environment: None,
+    video_refresh: None,
+    audio_sample_batch: None,
+    input_poll: None,
+    input_state: None,
+    log: None,
+});
+
+// the loaded game
+static CORE: Mutex<Option<Core>> = Mutex::new(None);
+
+struct Core {
+    chip8: Chip8,
+    rom: Vec<u8>,
+    quirks: Quirks,
+    palette: Palette,
+    instructions_per_frame: usize,
+    frame: Vec<u32>,
+    audio: Vec<i16>,
+    beep_phase: usize,
+}
+
+impl Core {
+    fn new(rom: Vec<u8>, environment: RetroEnvironment) -> Core {
+        let mut core = Core {
+            chip8: Chip8::new(),
+            rom,
+            quirks: Quirks::default(),
+            palette: PALETTES[0].1,
+            instructions_per_frame: DEFAULT_INSTRUCTIONS_PER_FRAME,
+            frame: vec![0; WIDTH * HEIGHT],
+            audio: vec![0; SAMPLES_PER_FRAME * 2],
+            beep_phase: 0,
+        };
+        core.apply_options(environment);
+        core.reset();
+        core
+    }
+
+    fn reset(&mut self) {
+        self.chip8 = Chip8::new();
+        self.chip8.set_quirks(self.quirks);
+        self.chip8.load_program(&self.rom);
+    }
+
+    // NOTE: unknown values keep the defaults
+    fn apply_options(&mut self, environment: RetroEnvironment) {
+        self.quirks = variable(environment, QUIRKS_KEY)
+            .and_then(|value| value.parse().ok())
+            .unwrap_or_default();
+        self.chip8.set_quirks(self.quirks);
+        self.palette = variable(environment, PALETTE_KEY)
+            .and_then(|value| PALETTES.iter().find(|(name, _)| *name == value))
+            .map_or(PALETTES[0].1, |(_, palette)| *palette);
+        self.instructions_per_frame = variable(environment, SPEED_KEY)
+            .and_then(|value| value.parse().ok())
+            .unwrap_or(DEFAULT_INSTRUCTIONS_PER_FRAME);
+    }
+
+    fn read_input(&mut self, input_state: RetroInputState) {
+        self.chip8.keyboard = [false; 16];
+        for &(button, key) in JOYPAD_KEYS.iter() {
+            if input_state(0, RETRO_DEVICE_JOYPAD, 0, button) != 0 {
+                self.chip8.keyboard[key as usize] = true;
+            }
+        }
+        for &(keycode, key) in KEYBOARD_KEYS.iter() {
+            if input_state(0, RETRO_DEVICE_KEYBOARD, 0, keycode as c_uint) != 0 {
+                self.chip8.keyboard[key as usize] = true;
+            }
+        }
+    }
+
+    // XRGB8888
+    fn render(&mut self) {
+        for (pixel, &lit) in self.frame.iter_mut().zip(self.chip8.display.iter()) {
+            let [r, g, b] = self.palette.color(lit);
+            *pixel = u32::from_be_bytes([0, r, g, b]);
+        }
+    }
+
+    // stereo square wave while the sound timer runs
+    // NOTE: timers stop with a faulted machine, so does the beeper
+    fn mix_audio(&mut self) {
+        let beeping = self.chip8.sound_timer() > 0 && self.chip8.fault().is_none();
+        for frame in self.audio.chunks_mut(2) {
+            let sample = if !beeping {
+                0
+            } else if self.beep_phase < BEEP_HALF_PERIOD {
+                BEEP_AMPLITUDE
+            } else {
+                -BEEP_AMPLITUDE
+            };
+            self.beep_phase = (self.beep_phase + 1) % (BEEP_HALF_PERIOD * 2);
+            frame[0] = sample;
+            frame[1] = sample;
+        }
+    }
+}
+
+fn callbacks() -> Callbacks {
+    *CALLBACKS.lock().unwrap()
+}
+
+fn variable(environment: RetroEnvironment, key: &str) -> Option<String> {
+    let mut variable = RetroVariable {
+        key: key.as_ptr() as *const c_char,
+        value: std::ptr::null(),
+    };
+    let found = environment(
+        RETRO_ENVIRONMENT_GET_VARIABLE,
+        &mut variable as *mut RetroVariable as *mut c_void,
+    );
+    if !found || variable.value.is_null() {
+        return None;
+    }
+    let value = unsafe { CStr::from_ptr(variable.value) };
+    Some(value.to_string_lossy().into_owned())
+}
+
+// logs an error and shows it on screen, falls back to stderr without a log interface
+fn report_error(callbacks: Callbacks, message: &str) {
+    let message = CString::new(message).unwrap_or_default();
+    match callbacks.log {
+        Some(log) => unsafe { log(RETRO_LOG_ERROR, c_str!("%s\n"), message.as_ptr()) },
+        None => eprintln!("error: {}", message.to_string_lossy()),
+    }
+    if let Some(environment) = callbacks.environment {
+        let mut retro_message = RetroMessage {
+            msg: message.as_ptr(),
+            frames: MESSAGE_FRAMES,
+        };
+        environment(
+            RETRO_ENVIRONMENT_SET_MESSAGE,
+            &mut retro_message as *mut RetroMessage as *mut c_void,
+        );
+    }
+}
+
+fn set_variables(environment: RetroEnvironment) {
+    let mut variables = [
+        RetroVariable {
+            key: QUIRKS_KEY.as_ptr() as *const c_char,
+            value: c_str!("Quirks; default|cosmac|schip"),
+        },
+        RetroVariable {
+            key: PALETTE_KEY.as_ptr() as *const c_char,
+            value: c_str!("Palette; white|green|amber|lcd"),
+        },
+        RetroVariable {
+            key: SPEED_KEY.as_ptr() as *const c_char,
+            value: c_str!("Instructions per frame; 10|15|20|30|50|100|500|1000"),
+        },
+        RetroVariable {
+            key: std::ptr::null(),
+            value: std::ptr::null(),
+        },
+    ];
+    environment(
+        RETRO_ENVIRONMENT_SET_VARIABLES,
+        variables.as_mut_ptr() as *mut c_void,
+    );
+}
+
+fn set_input_descriptors(environment: RetroEnvironment) {
+    const NAMES: [*const c_char; 16] = [
+        c_str!("Key 0"),
+        c_str!("Key 1"),
+        c_str!("Key 2"),
+        c_str!("Key 3"),
+        c_str!("Key 4"),
+        c_str!("Key 5"),
+        c_str!("Key 6"),
+        c_str!("Key 7"),
+        c_str!("Key 8"),
+        c_str!("Key 9"),
+        c_str!("Key A"),
+        c_str!("Key B"),
+        c_str!("Key C"),
+        c_str!("Key D"),
+        c_str!("Key E"),
+        c_str!("Key F"),
+    ];
+    let mut descriptors: Vec<RetroInputDescriptor> = JOYPAD_KEYS
+        .iter()
+        .map(|&(button, key)| RetroInputDescriptor {
+            port: 0,
+            device: RETRO_DEVICE_JOYPAD,
+            index: 0,
+            id: button,
+            description: NAMES[key as usize],
+        })
+        .collect();
+    descriptors.push(RetroInputDescriptor {
+        port: 0,
+        device: 0,
+        index: 0,
+        id: 0,
+        description: std::ptr::null(),
+    });
+    environment(
+        RETRO_ENVIRONMENT_SET_INPUT_DESCRIPTORS,
+        descriptors.as_mut_ptr() as *mut c_void,
+    );
+}
+
+// ROM bytes, or the compiled ROM of Octo source
+fn read_game(path: Option<&str>, data: &[u8]) -> Result<Vec<u8>, String> {
+    let rom = match path {
+        Some(path) if path.ends_with(".8o") => {
+            let source =
+                std::str::from_utf8(data).map_err(|_| format!("{}: not UTF-8 text", path))?;
+            octo::compile(source)
+                .map_err(|e| format!("{}: {}", path, e))?
+                .rom
+        }
+        _ => data.to_vec(),
+    };
+    if rom.len() > MAX_ROM_SIZE {
+        return Err(format!(
+            "ROM too large ({} bytes, max {})",
+            rom.len(),
+            MAX_ROM_SIZE
+        ));
+    }
+    Ok(rom)
+}
+
+#[no_mangle]
+pub extern "C" fn retro_api_version() -> c_uint {
+    RETRO_API_VERSION
+}
+
+/// Declares the core options, libretro calls it before retro_init.
+#[no_mangle]
+pub extern "C" fn retro_set_environment(callback: RetroEnvironment) {
+    let mut log = RetroLogCallback { log: None };
+    let supported = callback(
+        RETRO_ENVIRONMENT_GET_LOG_INTERFACE,
+        &mut log as *mut RetroLogCallback as *mut c_void,
+    );
+    let mut callbacks = CALLBACKS.lock().unwrap();
+    callbacks.environment = Some(callback);
+    callbacks.log = if supported { log.log } else { None };
+    drop(callbacks);
+    set_variables(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_video_refresh(callback: RetroVideoRefresh) {
+    CALLBACKS.lock().unwrap().video_refresh = Some(callback);
+}
+
+// NOTE: audio is sent in batches
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample(_callback: RetroAudioSample) {}
+
+#[no_mangle]
+pub extern "C" fn retro_set_audio_sample_batch(callback: RetroAudioSampleBatch) {
+    CALLBACKS.lock().unwrap().audio_sample_batch = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_poll(callback: RetroInputPoll) {
+    CALLBACKS.lock().unwrap().input_poll = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_input_state(callback: RetroInputState) {
+    CALLBACKS.lock().unwrap().input_state = Some(callback);
+}
+
+#[no_mangle]
+pub extern "C" fn retro_init() {}
+
+#[no_mangle]
+pub extern "C" fn retro_deinit() {
+    *CORE.lock().unwrap() = None;
+}
+
+/// # Safety
+///
+/// `info` must point to a writable retro_system_info.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_info(info: *mut RetroSystemInfo) {
+    *info = RetroSystemInfo {
+        library_name: c_str!("CHIP-8"),
+        library_version: c_str!(env!("CARGO_PKG_VERSION")),
+        valid_extensions: c_str!("ch8|c8|8o"),
+        need_fullpath: false,
+        block_extract: false,
+    };
+}
+
+/// # Safety
+///
+/// `info` must point to a writable retro_system_av_info.
+#[no_mangle]
+pub unsafe extern "C" fn retro_get_system_av_info(info: *mut RetroSystemAvInfo) {
+    *info = RetroSystemAvInfo {
+        geometry: RetroGameGeometry {
+            base_width: WIDTH as c_uint,
+            base_height: HEIGHT as c_uint,
+            max_width: WIDTH as c_uint,
+            max_height: HEIGHT as c_uint,
+            aspect_ratio: WIDTH as f32 / HEIGHT as f32,
+        },
+        timing: RetroSystemTiming {
+            fps: FPS,
+            sample_rate: SAMPLE_RATE,
+        },
+    };
+}
+
+#[no_mangle]
+pub extern "C" fn retro_set_controller_port_device(_port: c_uint, _device: c_uint) {}
+
+#[no_mangle]
+pub extern "C" fn retro_reset() {
+    if let Some(core) = CORE.lock().unwrap().as_mut() {
+        core.reset();
+    }
+}
+
+/// Runs one frame: polls input, executes the instructions of a frame, ticks the timers, then
+/// sends the frame and 1/60s of audio. A ROM which faults stops emulation and is reported
+/// through the log interface and an on-screen message.
+#[no_mangle]
+pub extern "C" fn retro_run() {
+    let callbacks = callbacks();
+    let mut core = CORE.lock().unwrap();
+    let core = match core.as_mut() {
+        Some(core) => core,
+        None => return,
+    };
+
+    if let Some(environment) = callbacks.environment {
+        let mut updated = false;
+        let supported = environment(
+            RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE,
+            &mut updated as *mut bool as *mut c_void,
+        );
+        if supported && updated {
+            core.apply_options(environment);
+        }
+    }
+    if let Some(input_poll) = callbacks.input_poll {
+        input_poll();
+    }
+    if let Some(input_state) = callbacks.input_state {
+        core.read_input(input_state);
+    }
+
+    // NOTE: a faulted machine stops until reset or a state is loaded, reported once
+    let faulted = core.chip8.fault().is_some();
+    if let Err(fault) = core.chip8.step_frame(core.instructions_per_frame) {
+        if !faulted {
+            let message = format!(
+                "CHIP-8: ROM stopped at 0x{:03X}: {}",
+                core.chip8.program_counter(),
+                fault
+            );
+            report_error(callbacks, &message);
+        }
+    }
+
+    core.render();
+    if let Some(video_refresh) = callbacks.video_refresh {
+        video_refresh(
+            core.frame.as_ptr() as *const c_void,
+            WIDTH as c_uint,
+            HEIGHT as c_uint,
+            WIDTH * 4,
+        );
+    }
+    core.mix_audio();
+    if let Some(audio_sample_batch) = callbacks.audio_sample_batch {
+        audio_sample_batch(core.audio.as_ptr(), SAMPLES_PER_FRAME);
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_serialize_size() -> usize {
+    STATE_SIZE
+}
+
+/// # Safety
+///
+/// `data` must point to `size` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_serialize(data: *mut c_void, size: usize) -> bool {
+    let core = CORE.lock().unwrap();
+    match core.as_ref() {
+        Some(core) if size >= STATE_SIZE => {
+            core.chip8.save_state(&mut *(data as *mut [u8; STATE_SIZE]));
+            true
+        }
+        _ => false,
+    }
+}
+
+/// # Safety
+///
+/// `data` must point to `size` bytes.
+#[no_mangle]
+pub unsafe extern "C" fn retro_unserialize(data: *const c_void, size: usize) -> bool {
+    let mut core = CORE.lock().unwrap();
+    match core.as_mut() {
+        Some(core) => core
+            .chip8
+            .load_state(std::slice::from_raw_parts(data as *const u8, size))
+            .is_ok(),
+        None => false,
+    }
+}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_reset() {}
+
+#[no_mangle]
+pub extern "C" fn retro_cheat_set(_index: c_uint, _enabled: bool, _code: *const c_char) {}
+
+/// Loads a ROM or Octo source, fails if it does not fit in memory or the frontend can not
+/// display XRGB8888. Errors in the game are reported through the log interface.
+///
+/// # Safety
+///
+/// `game` must be NULL or point to a valid retro_game_info.
+#[no_mangle]
+pub unsafe extern "C" fn retro_load_game(game: *const RetroGameInfo) -> bool {
+    if game.is_null() || (*game).data.is_null() {
+        return false;
+    }
+    let game = &*game;
+    let path = if game.path.is_null() {
+        None
+    } else {
+        CStr::from_ptr(game.path).to_str().ok()
+    };
+    let data = std::slice::from_raw_parts(game.data as *const u8, game.size);
+    let rom = match read_game(path, data) {
+        Ok(rom) => rom,
+        Err(message) => {
+            report_error(callbacks(), &message);
+            return false;
+        }
+    };
+
+    let environment = match callbacks().environment {
+        Some(environment) => environment,
+        None => return false,
+    };
+    let mut pixel_format = RETRO_PIXEL_FORMAT_XRGB8888;
+    if !environment(
+        RETRO_ENVIRONMENT_SET_PIXEL_FORMAT,
+        &mut pixel_format as *mut c_uint as *mut c_void,
+    ) {
+        return false;
+    }
+    set_input_descriptors(environment);
+
+    *CORE.lock().unwrap() = Some(Core::new(rom, environment));
+    true
+}
+
+#[no_mangle]
+pub extern "C" fn retro_load_game_special(
+    _game_type: c_uint,
+    _info: *const RetroGameInfo,
+    _num_info: usize,
+) -> bool {
+    false
+}
+
+#[no_mangle]
+pub extern "C" fn retro_unload_game() {
+    *CORE.lock().unwrap() = None;
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_region() -> c_uint {
+    RETRO_REGION_NTSC
+}
+
+// NOTE: memory is not exposed, achievements and cheats are not supported
+#[no_mangle]
+pub extern "C" fn retro_get_memory_data(_id: c_uint) -> *mut c_void {
+    std::ptr::null_mut()
+}
+
+#[no_mangle]
+pub extern "C" fn retro_get_memory_size(_id: c_uint) -> usize {
+    0
+}
@@ -0,0 +1,431 @@
+// minimal libretro frontend: loads the core as a shared library, feeds input and checks what
+// it sends to the video and audio callbacks
+//
+// NOTE: the core keeps global state, so everything runs in a single test
+use libloading::{Library, Symbol};
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_uint, c_void};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Mutex;
+
+const RETRO_DEVICE_JOYPAD: c_uint = 1;
+const RETRO_DEVICE_ID_JOYPAD_A: c_uint = 8;
+
+const RETRO_ENVIRONMENT_SET_MESSAGE: c_uint = 6;
+const RETRO_ENVIRONMENT_SET_PIXEL_FORMAT: c_uint = 10;
+const RETRO_ENVIRONMENT_SET_INPUT_DESCRIPTORS: c_uint = 11;
+const RETRO_ENVIRONMENT_GET_VARIABLE: c_uint = 15;
+const RETRO_ENVIRONMENT_SET_VARIABLES: c_uint = 16;
+const RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE: c_uint = 17;
+const RETRO_ENVIRONMENT_GET_LOG_INTERFACE: c_uint = 27;
+
+const RETRO_LOG_ERROR: c_uint = 3;
+
+const RETRO_PIXEL_FORMAT_XRGB8888: c_uint = 1;
+
+#[repr(C)]
+struct RetroGameGeometry {
+    base_width: c_uint,
+    base_height: c_uint,
+    max_width: c_uint,
+    max_height: c_uint,
+    aspect_ratio: f32,
+}
+
+#[repr(C)]
+struct RetroSystemTiming {
+    fps: f64,
+    sample_rate: f64,
+}
+
+#[repr(C)]
+struct RetroSystemAvInfo {
+    geometry: RetroGameGeometry,
+    timing: RetroSystemTiming,
+}
+
+#[repr(C)]
+struct RetroGameInfo {
+    path: *const c_char,
+    data: *const c_void,
+    size: usize,
+    meta: *const c_char,
+}
+
+#[repr(C)]
+struct RetroVariable {
+    key: *const c_char,
+    value: *const c_char,
+}
+
+#[repr(C)]
+struct RetroLogCallback {
+    log: *const c_void,
+}
+
+#[repr(C)]
+struct RetroMessage {
+    msg: *const c_char,
+    frames: c_uint,
+}
+
+#[repr(C)]
+struct RetroInputDescriptor {
+    port: c_uint,
+    device: c_uint,
+    index: c_uint,
+    id: c_uint,
+    description: *const c_char,
+}
+
+// LD V0, 5; wait until key 5 is held; draw it at (0, 0); LD ST, 30; loop
+const ROM: [u8; 16] = [
+    0x60, 0x05, 0xE0, 0x9E, 0x12, 0x02, 0xF0, 0x29, 0xD1, 0x15, 0x61, 0x1E, 0xF1, 0x18, 0x12, 0x0E,
+];
+
+const GREEN: u32 = 0x0033_FF66;
+const GREEN_BACKGROUND: u32 = 0x0000_1A00;
+
+static PIXEL_FORMAT: AtomicUsize = AtomicUsize::new(usize::MAX);
+static INPUT_DESCRIPTORS: AtomicUsize = AtomicUsize::new(0);
+static VARIABLES: Mutex<Vec<(String, String)>> = Mutex::new(Vec::new());
+// pixels, width, height, pitch of the last frame
+type Frame = (Vec<u32>, c_uint, c_uint, usize);
+
+static VIDEO: Mutex<Option<Frame>> = Mutex::new(None);
+static AUDIO: Mutex<Vec<i16>> = Mutex::new(Vec::new());
+static POLLED: AtomicBool = AtomicBool::new(false);
+static BUTTON_A: AtomicBool = AtomicBool::new(false);
+// (level, message) of the log interface and the on-screen messages
+static LOG: Mutex<Vec<(c_uint, String)>> = Mutex::new(Vec::new());
+static MESSAGES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+extern "C" fn environment(cmd: c_uint, data: *mut c_void) -> bool {
+    unsafe {
+        match cmd {
+            RETRO_ENVIRONMENT_SET_PIXEL_FORMAT => {
+                PIXEL_FORMAT.store(*(data as *const c_uint) as usize, Ordering::SeqCst);
+                true
+            }
+            RETRO_ENVIRONMENT_SET_INPUT_DESCRIPTORS => {
+                let mut descriptor = data as *const RetroInputDescriptor;
+                let mut count = 0;
+                while !(*descriptor).description.is_null() {
+                    assert_eq!((*descriptor).device, RETRO_DEVICE_JOYPAD);
+                    count += 1;
+                    descriptor = descriptor.add(1);
+                }
+                INPUT_DESCRIPTORS.store(count, Ordering::SeqCst);
+                true
+            }
+            RETRO_ENVIRONMENT_SET_VARIABLES => {
+                let mut variable = data as *const RetroVariable;
+                let mut variables = VARIABLES.lock().unwrap();
+                while !(*variable).key.is_null() {
+                    let key = CStr::from_ptr((*variable).key).to_str().unwrap();
+                    let value = CStr::from_ptr((*variable).value).to_str().unwrap();
+                    variables.push((key.to_string(), value.to_string()));
+                    variable = variable.add(1);
+                }
+                true
+            }
+            RETRO_ENVIRONMENT_GET_VARIABLE => {
+                let variable = &mut *(data as *mut RetroVariable);
+                let key = CStr::from_ptr(variable.key).to_str().unwrap();
+                if key == "chip8_palette" {
+                    variable.value = "green\0".as_ptr() as *const c_char;
+                    true
+                } else {
+                    false
+                }
+            }
+            RETRO_ENVIRONMENT_GET_VARIABLE_UPDATE => {
+                *(data as *mut bool) = false;
+                true
+            }
+            RETRO_ENVIRONMENT_GET_LOG_INTERFACE => {
+                (*(data as *mut RetroLogCallback)).log = log as *const c_void;
+                true
+            }
+            RETRO_ENVIRONMENT_SET_MESSAGE => {
+                let message = &*(data as *const RetroMessage);
+                assert!(message.frames > 0);
+                let text = CStr::from_ptr(message.msg).to_str().unwrap();
+                MESSAGES.lock().unwrap().push(text.to_string());
+                true
+            }
+            _ => false,
+        }
+    }
+}
+
+// NOTE: retro_log_printf is variadic, the core always logs a single string with "%s\n"
+extern "C" fn log(level: c_uint, fmt: *const c_char, message: *const c_char) {
+    unsafe {
+        assert_eq!(CStr::from_ptr(fmt).to_str().unwrap(), "%s\n");
+        let message = CStr::from_ptr(message).to_str().unwrap();
+        LOG.lock().unwrap().push((level, message.to_string()));
+    }
+}
+
+extern "C" fn video_refresh(data: *const c_void, width: c_uint, height: c_uint, pitch: usize) {
+    let pixels =
+        unsafe { std::slice::from_raw_parts(data as *const u32, (pitch / 4) * height as usize) };
+    *VIDEO.lock().unwrap() = Some((pixels.to_vec(), width, height, pitch));
+}
+
+extern "C" fn audio_sample(_left: i16, _right: i16) {}
+
+extern "C" fn audio_sample_batch(data: *const i16, frames: usize) -> usize {
+    let samples = unsafe { std::slice::from_raw_parts(data, frames * 2) };
+    *AUDIO.lock().unwrap() = samples.to_vec();
+    frames
+}
+
+extern "C" fn input_poll() {
+    POLLED.store(true, Ordering::SeqCst);
+}
+
+extern "C" fn input_state(port: c_uint, device: c_uint, _index: c_uint, id: c_uint) -> i16 {
+    let pressed = port == 0
+        && device == RETRO_DEVICE_JOYPAD
+        && id == RETRO_DEVICE_ID_JOYPAD_A
+        && BUTTON_A.load(Ordering::SeqCst);
+    pressed as i16
+}
+
+fn frame() -> Frame {
+    VIDEO.lock().unwrap().take().expect("no video frame")
+}
+
+#[test]
+fn stub_frontend() {
+    // NOTE: the shared library is built into target/<profile>/deps, next to the test binary
+    let exe = std::env::current_exe().unwrap();
+    let path = exe
+        .parent()
+        .unwrap()
+        .join(libloading::library_filename("chip8_libretro"));
+    let library = unsafe { Library::new(&path) }
+        .unwrap_or_else(|e| panic!("failed to load {}: {}", path.display(), e));
+
+    unsafe {
+        let api_version: Symbol<extern "C" fn() -> c_uint> =
+            library.get(b"retro_api_version").unwrap();
+        assert_eq!(api_version(), 1);
+
+        let set_environment: Symbol<extern "C" fn(extern "C" fn(c_uint, *mut c_void) -> bool)> =
+            library.get(b"retro_set_environment").unwrap();
+        set_environment(environment);
+        let keys: Vec<String> = VARIABLES
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|(key, _)| key.clone())
+            .collect();
+        assert_eq!(
+            keys,
+            [
+                "chip8_quirks",
+                "chip8_palette",
+                "chip8_instructions_per_frame"
+            ]
+        );
+
+        let set_video_refresh: Symbol<
+            extern "C" fn(extern "C" fn(*const c_void, c_uint, c_uint, usize)),
+        > = library.get(b"retro_set_video_refresh").unwrap();
+        set_video_refresh(video_refresh);
+        let set_audio_sample: Symbol<extern "C" fn(extern "C" fn(i16, i16))> =
+            library.get(b"retro_set_audio_sample").unwrap();
+        set_audio_sample(audio_sample);
+        let set_audio_sample_batch: Symbol<
+            extern "C" fn(extern "C" fn(*const i16, usize) -> usize),
+        > = library.get(b"retro_set_audio_sample_batch").unwrap();
+        set_audio_sample_batch(audio_sample_batch);
+        let set_input_poll: Symbol<extern "C" fn(extern "C" fn())> =
+            library.get(b"retro_set_input_poll").unwrap();
+        set_input_poll(input_poll);
+        let set_input_state: Symbol<
+            extern "C" fn(extern "C" fn(c_uint, c_uint, c_uint, c_uint) -> i16),
+        > = library.get(b"retro_set_input_state").unwrap();
+        set_input_state(input_state);
+
+        let init: Symbol<extern "C" fn()> = library.get(b"retro_init").unwrap();
+        init();
+
+        let load_game: Symbol<extern "C" fn(*const RetroGameInfo) -> bool> =
+            library.get(b"retro_load_game").unwrap();
+        let game = RetroGameInfo {
+            path: "game.ch8\0".as_ptr() as *const c_char,
+            data: ROM.as_ptr() as *const c_void,
+            size: ROM.len(),
+            meta: std::ptr::null(),
+        };
+        assert!(load_game(&game));
+        assert_eq!(
+            PIXEL_FORMAT.load(Ordering::SeqCst),
+            RETRO_PIXEL_FORMAT_XRGB8888 as usize
+        );
+        assert_eq!(INPUT_DESCRIPTORS.load(Ordering::SeqCst), 12);
+
+        let get_system_av_info: Symbol<extern "C" fn(*mut RetroSystemAvInfo)> =
+            library.get(b"retro_get_system_av_info").unwrap();
+        let mut av_info = std::mem::zeroed::<RetroSystemAvInfo>();
+        get_system_av_info(&mut av_info);
+        assert_eq!(av_info.geometry.base_width, 64);
+        assert_eq!(av_info.geometry.base_height, 32);
+        assert_eq!(av_info.geometry.max_width, 64);
+        assert_eq!(av_info.geometry.max_height, 32);
+        assert_eq!(av_info.geometry.aspect_ratio, 2.0);
+        assert_eq!(av_info.timing.fps, 60.0);
+        assert_eq!(av_info.timing.sample_rate, 44100.0);
+
+        // waiting for key 5: blank screen in the palette from the core options, no sound
+        let run: Symbol<extern "C" fn()> = library.get(b"retro_run").unwrap();
+        run();
+        assert!(POLLED.load(Ordering::SeqCst));
+        let (pixels, width, height, pitch) = frame();
+        assert_eq!((width, height, pitch), (64, 32, 64 * 4));
+        assert!(pixels.iter().all(|&pixel| pixel == GREEN_BACKGROUND));
+        let audio = AUDIO.lock().unwrap().clone();
+        assert_eq!(audio.len(), 735 * 2);
+        assert!(audio.iter().all(|&sample| sample == 0));
+
+        // A presses key 5: glyph 5 is drawn and the beeper starts
+        BUTTON_A.store(true, Ordering::SeqCst);
+        run();
+        BUTTON_A.store(false, Ordering::SeqCst);
+        let (drawn, _, _, _) = frame();
+        assert_eq!(
+            &drawn[0..5],
+            &[GREEN, GREEN, GREEN, GREEN, GREEN_BACKGROUND]
+        );
+        assert_eq!(&drawn[64..66], &[GREEN, GREEN_BACKGROUND]);
+        assert!(AUDIO.lock().unwrap().iter().any(|&sample| sample != 0));
+
+        // save, reset, restore
+        let serialize_size: Symbol<extern "C" fn() -> usize> =
+            library.get(b"retro_serialize_size").unwrap();
+        let serialize: Symbol<extern "C" fn(*mut c_void, usize) -> bool> =
+            library.get(b"retro_serialize").unwrap();
+        let unserialize: Symbol<extern "C" fn(*const c_void, usize) -> bool> =
+            library.get(b"retro_unserialize").unwrap();
+        let mut state = vec![0u8; serialize_size()];
+        assert!(!serialize(
+            state.as_mut_ptr() as *mut c_void,
+            state.len() - 1
+        ));
+        assert!(serialize(state.as_mut_ptr() as *mut c_void, state.len()));
+
+        let reset: Symbol<extern "C" fn()> = library.get(b"retro_reset").unwrap();
+        reset();
+        run();
+        assert!(frame().0.iter().all(|&pixel| pixel == GREEN_BACKGROUND));
+
+        assert!(!unserialize(state.as_ptr() as *const c_void, 16));
+        assert!(unserialize(state.as_ptr() as *const c_void, state.len()));
+        run();
+        assert_eq!(frame().0, drawn);
+
+        let unload_game: Symbol<extern "C" fn()> = library.get(b"retro_unload_game").unwrap();
+        unload_game();
+        run();
+        assert!(VIDEO.lock().unwrap().is_none());
+
+        // Octo source is compiled, errors fail the load
+        let source = b": main\n  v0 := 5\n  loop again\n";
+        let game = RetroGameInfo {
+            path: "game.8o\0".as_ptr() as *const c_char,
+            data: source.as_ptr() as *const c_void,
+            size: source.len(),
+            meta: std::ptr::null(),
+        };
+        assert!(load_game(&game));
+        unload_game();
+        let source = b": main\n  v0 := \n";
+        let game = RetroGameInfo {
+            size: source.len(),
+            data: source.as_ptr() as *const c_void,
+            ..game
+        };
+        assert!(!load_game(&game));
+        let message = "game.8o: line 2: unexpected end of file";
+        assert_eq!(
+            LOG.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            [(RETRO_LOG_ERROR, message.to_string())]
+        );
+        assert_eq!(
+            MESSAGES.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            [message]
+        );
+
+        // ROMs which do not fit in memory are reported too
+        let too_large = [0u8; 0xE01];
+        let game = RetroGameInfo {
+            path: "too_large.ch8\0".as_ptr() as *const c_char,
+            data: too_large.as_ptr() as *const c_void,
+            size: too_large.len(),
+            meta: std::ptr::null(),
+        };
+        assert!(!load_game(&game));
+        let message = "ROM too large (3585 bytes, max 3584)";
+        assert_eq!(
+            LOG.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            [(RETRO_LOG_ERROR, message.to_string())]
+        );
+        assert_eq!(
+            MESSAGES.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            [message]
+        );
+
+        // a faulting ROM stops emulation and is reported once, instead of aborting the frontend
+        // LD V0, 1; invalid opcode
+        let invalid: [u8; 4] = [0x60, 0x01, 0xFF, 0xFF];
+        let game = RetroGameInfo {
+            path: "invalid.ch8\0".as_ptr() as *const c_char,
+            data: invalid.as_ptr() as *const c_void,
+            size: invalid.len(),
+            meta: std::ptr::null(),
+        };
+        assert!(load_game(&game));
+        run();
+        run();
+        assert!(frame().0.iter().all(|&pixel| pixel == GREEN_BACKGROUND));
+        let message = "CHIP-8: ROM stopped at 0x202: unknown opcode FFFF";
+        assert_eq!(
+            LOG.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            [(RETRO_LOG_ERROR, message.to_string())]
+        );
+        assert_eq!(
+            MESSAGES.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            [message]
+        );
+        // reset runs it again
+        reset();
+        run();
+        assert_eq!(LOG.lock().unwrap().drain(..).count(), 1);
+        unload_game();
+
+        // RET with an empty stack
+        let unbalanced: [u8; 2] = [0x00, 0xEE];
+        let game = RetroGameInfo {
+            data: unbalanced.as_ptr() as *const c_void,
+            size: unbalanced.len(),
+            ..game
+        };
+        assert!(load_game(&game));
+        run();
+        assert_eq!(
+            LOG.lock().unwrap().drain(..).collect::<Vec<_>>(),
+            [(
+                RETRO_LOG_ERROR,
+                "CHIP-8: ROM stopped at 0x200: return with an empty stack".to_string()
+            )]
+        );
+        unload_game();
+
+        let deinit: Symbol<extern "C" fn()> = library.get(b"retro_deinit").unwrap();
+        deinit();
+    }
+}
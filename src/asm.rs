@@ -0,0 +1,631 @@
+//! Two-pass assembler for Cowgod's syntax.
+//!
+//! ```text
+//! ; comment
+//! SPRITE_HEIGHT EQU 3
+//! start:  LD I, sprite
+//!         DRW V0, V1, SPRITE_HEIGHT
+//!         JP start
+//! sprite: DB 0x3C, %01000010, $81
+//!         DW 0x1234, start
+//!         INCLUDE "other.asm"
+//! ```
+//!
+//! Numbers can be decimal, hex (`0x1F`, `$1F`) or binary (`0b0101`, `%0101`). Operands can be
+//! simple expressions of numbers, labels and constants joined by `+` and `-`.
+use std::collections::HashMap;
+use std::fmt;
+use std::path::{Path, PathBuf};
+
+const PROGRAM_START: u16 = 0x200;
+const MEMORY_SIZE: usize = 0x1000;
+
+// NOTE: protects against recursive includes
+const MAX_INCLUDE_DEPTH: usize = 16;
+
+/// Error at a line of a source file.
+#[derive(Debug, PartialEq)]
+pub struct AsmError {
+    /// Path of the file, as included.
+    pub file: String,
+    /// 1-based line number.
+    pub line: usize,
+    /// What is wrong.
+    pub message: String,
+}
+
+impl fmt::Display for AsmError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}:{}: {}", self.file, self.line, self.message)
+    }
+}
+
+impl std::error::Error for AsmError {}
+
+enum Statement {
+    Instruction(String, Vec<String>),
+    Db(Vec<String>),
+    Dw(Vec<String>),
+    Equ(String, String),
+}
+
+struct Line {
+    file: String,
+    number: usize,
+    label: Option<String>,
+    statement: Option<Statement>,
+}
+
+impl Line {
+    fn error(&self, message: String) -> AsmError {
+        AsmError {
+            file: self.file.clone(),
+            line: self.number,
+            message,
+        }
+    }
+}
+
+/// Assembles a source file, includes are resolved relative to the including file.
+pub fn assemble_file(path: &str) -> Result<Vec<u8>, AsmError> {
+    let source = std::fs::read_to_string(path).map_err(|e| AsmError {
+        file: path.to_string(),
+        line: 0,
+        message: e.to_string(),
+    })?;
+    assemble_with_loader(&source, path, &mut |path: &Path| {
+        std::fs::read_to_string(path)
+    })
+}
+
+/// Assembles source, `load` is used to read included files.
+pub fn assemble_with_loader<F>(
+    source: &str,
+    filename: &str,
+    load: &mut F,
+) -> Result<Vec<u8>, AsmError>
+where
+    F: FnMut(&Path) -> std::io::Result<String>,
+{
+    let mut lines = Vec::new();
+    parse_source(source, filename, load, 0, &mut lines)?;
+
+    let symbols = resolve_symbols(&lines)?;
+
+    // second pass: encode
+    let mut rom = Vec::new();
+    for line in lines.iter() {
+        match &line.statement {
+            Some(Statement::Instruction(mnemonic, operands)) => {
+                let opcode = encode(mnemonic, operands, &symbols).map_err(|e| line.error(e))?;
+                rom.extend_from_slice(&opcode.to_be_bytes());
+            }
+            Some(Statement::Db(values)) => {
+                for value in values.iter() {
+                    match parse_string(value) {
+                        Some(s) => rom.extend_from_slice(s.as_bytes()),
+                        None => {
+                            let v = evaluate(value, &symbols).map_err(|e| line.error(e))?;
+                            rom.push(check_byte(v).map_err(|e| line.error(e))?);
+                        }
+                    }
+                }
+            }
+            Some(Statement::Dw(values)) => {
+                for value in values.iter() {
+                    let v = evaluate(value, &symbols).map_err(|e| line.error(e))?;
+                    if !(-0x8000..=0xFFFF).contains(&v) {
+                        return Err(line.error(format!("word out of range: {}", value)));
+                    }
+                    rom.extend_from_slice(&(v as u16).to_be_bytes());
+                }
+            }
+            Some(Statement::Equ(..)) | None => (),
+        }
+    }
+
+    if PROGRAM_START as usize + rom.len() > MEMORY_SIZE {
+        let line = lines.last().unwrap();
+        return Err(line.error(format!("program too large: {} bytes", rom.len())));
+    }
+    Ok(rom)
+}
+
+// split source into lines, expanding includes
+fn parse_source<F>(
+    source: &str,
+    filename: &str,
+    load: &mut F,
+    depth: usize,
+    lines: &mut Vec<Line>,
+) -> Result<(), AsmError>
+where
+    F: FnMut(&Path) -> std::io::Result<String>,
+{
+    for (index, text) in source.lines().enumerate() {
+        let mut line = parse_line(text, filename, index + 1)?;
+
+        // INCLUDE "file"
+        if let Some(Statement::Instruction(mnemonic, operands)) = &line.statement {
+            if mnemonic == "INCLUDE" {
+                let path = match operands.as_slice() {
+                    [operand] => parse_string(operand),
+                    _ => None,
+                }
+                .ok_or_else(|| line.error("usage: INCLUDE \"file\"".to_string()))?;
+                if depth >= MAX_INCLUDE_DEPTH {
+                    return Err(line.error("includes nested too deeply".to_string()));
+                }
+
+                let path = include_path(filename, &path);
+                let included =
+                    load(&path).map_err(|e| line.error(format!("{}: {}", path.display(), e)))?;
+
+                // keep label of the include line
+                line.statement = None;
+                lines.push(line);
+                parse_source(&included, &path.to_string_lossy(), load, depth + 1, lines)?;
+                continue;
+            }
+        }
+        lines.push(line);
+    }
+    Ok(())
+}
+
+fn include_path(filename: &str, path: &str) -> PathBuf {
+    match Path::new(filename).parent() {
+        Some(dir) => dir.join(path),
+        None => PathBuf::from(path),
+    }
+}
+
+fn parse_line(text: &str, filename: &str, number: usize) -> Result<Line, AsmError> {
+    let mut line = Line {
+        file: filename.to_string(),
+        number,
+        label: None,
+        statement: None,
+    };
+
+    let mut rest = strip_comment(text).trim();
+
+    // label
+    if let Some(colon) = rest.find(':') {
+        let label = &rest[..colon];
+        if is_identifier(label) {
+            line.label = Some(label.to_string());
+            rest = rest[(colon + 1)..].trim();
+        }
+    }
+    if rest.is_empty() {
+        return Ok(line);
+    }
+
+    let (first, operands) = match rest.find(char::is_whitespace) {
+        Some(i) => (&rest[..i], rest[i..].trim()),
+        None => (rest, ""),
+    };
+
+    // NAME EQU value
+    if let Some(value) = strip_keyword(operands, "EQU") {
+        if !is_identifier(first) {
+            return Err(line.error(format!("invalid constant name: {}", first)));
+        }
+        line.statement = Some(Statement::Equ(first.to_string(), value.trim().to_string()));
+        return Ok(line);
+    }
+
+    let operands = split_operands(operands);
+    if operands.iter().any(|o| o.is_empty()) {
+        return Err(line.error("empty operand".to_string()));
+    }
+
+    let mnemonic = first.to_uppercase();
+    line.statement = Some(match mnemonic.as_str() {
+        "DB" => Statement::Db(operands),
+        "DW" => Statement::Dw(operands),
+        _ => Statement::Instruction(mnemonic, operands),
+    });
+    Ok(line)
+}
+
+// strip comment starting with ';', ignoring ';' in quotes
+fn strip_comment(text: &str) -> &str {
+    let mut quoted = false;
+    for (i, c) in text.char_indices() {
+        match c {
+            '"' => quoted = !quoted,
+            ';' if !quoted => return &text[..i],
+            _ => (),
+        }
+    }
+    text
+}
+
+fn strip_keyword<'a>(text: &'a str, keyword: &str) -> Option<&'a str> {
+    let word = text.split_whitespace().next()?;
+    if word.eq_ignore_ascii_case(keyword) {
+        Some(&text[word.len()..])
+    } else {
+        None
+    }
+}
+
+// split by ',', ignoring ',' in quotes
+fn split_operands(text: &str) -> Vec<String> {
+    if text.is_empty() {
+        return Vec::new();
+    }
+    let mut operands = Vec::new();
+    let mut current = String::new();
+    let mut quoted = false;
+    for c in text.chars() {
+        match c {
+            '"' => {
+                quoted = !quoted;
+                current.push(c);
+            }
+            ',' if !quoted => {
+                operands.push(current.trim().to_string());
+                current.clear();
+            }
+            _ => current.push(c),
+        }
+    }
+    operands.push(current.trim().to_string());
+    operands
+}
+
+fn is_identifier(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '.')
+}
+
+fn parse_string(s: &str) -> Option<String> {
+    if s.len() >= 2 && s.starts_with('"') && s.ends_with('"') {
+        Some(s[1..(s.len() - 1)].to_string())
+    } else {
+        None
+    }
+}
+
+// first pass: compute label addresses and constants
+fn resolve_symbols(lines: &[Line]) -> Result<HashMap<String, i64>, AsmError> {
+    let mut symbols = HashMap::new();
+    let mut address = PROGRAM_START as i64;
+
+    for line in lines.iter() {
+        if let Some(label) = &line.label {
+            if register_name(label).is_some() {
+                return Err(line.error(format!("reserved name: {}", label)));
+            }
+            if symbols.insert(label.clone(), address).is_some() {
+                return Err(line.error(format!("duplicate symbol: {}", label)));
+            }
+        }
+
+        match &line.statement {
+            Some(Statement::Instruction(..)) => address += 2,
+            Some(Statement::Db(values)) => {
+                for value in values.iter() {
+                    address += match parse_string(value) {
+                        Some(s) => s.len() as i64,
+                        None => 1,
+                    };
+                }
+            }
+            Some(Statement::Dw(values)) => address += 2 * values.len() as i64,
+            Some(Statement::Equ(name, value)) => {
+                // NOTE: constants can only refer to symbols defined above
+                let v = evaluate(value, &symbols).map_err(|e| line.error(e))?;
+                if symbols.insert(name.clone(), v).is_some() {
+                    return Err(line.error(format!("duplicate symbol: {}", name)));
+                }
+            }
+            None => (),
+        }
+    }
+    Ok(symbols)
+}
+
+fn parse_number(s: &str) -> Option<i64> {
+    let lower = s.to_lowercase();
+    if let Some(hex) = lower.strip_prefix("0x").or_else(|| lower.strip_prefix('$')) {
+        i64::from_str_radix(hex, 16).ok()
+    } else if let Some(bin) = lower.strip_prefix("0b").or_else(|| lower.strip_prefix('%')) {
+        i64::from_str_radix(bin, 2).ok()
+    } else {
+        lower.parse::<i64>().ok()
+    }
+}
+
+// evaluate terms joined by '+' and '-'
+fn evaluate(expression: &str, symbols: &HashMap<String, i64>) -> Result<i64, String> {
+    let mut result = 0;
+    let mut sign = 1;
+    let mut term = String::new();
+    let mut expect_term = true;
+
+    let apply = |term: &str, sign: i64| -> Result<i64, String> {
+        let term = term.trim();
+        let value = match parse_number(term) {
+            Some(v) => v,
+            None => match symbols.get(term) {
+                Some(v) => *v,
+                None if is_identifier(term) => return Err(format!("undefined symbol: {}", term)),
+                None => return Err(format!("invalid expression: {}", expression)),
+            },
+        };
+        sign.checked_mul(value)
+            .ok_or_else(|| format!("expression overflows: {}", expression))
+    };
+    let add = |result: i64, value: i64| -> Result<i64, String> {
+        result
+            .checked_add(value)
+            .ok_or_else(|| format!("expression overflows: {}", expression))
+    };
+
+    for c in expression.chars() {
+        match c {
+            '+' | '-' if expect_term && term.trim().is_empty() => {
+                // unary sign
+                if c == '-' {
+                    sign = -sign;
+                }
+            }
+            '+' | '-' => {
+                result = add(result, apply(&term, sign)?)?;
+                term.clear();
+                sign = if c == '-' { -1 } else { 1 };
+                expect_term = true;
+            }
+            _ => {
+                term.push(c);
+                if !c.is_whitespace() {
+                    expect_term = false;
+                }
+            }
+        }
+    }
+    if term.trim().is_empty() {
+        return Err(format!("invalid expression: {}", expression));
+    }
+    add(result, apply(&term, sign)?)
+}
+
+fn register_name(s: &str) -> Option<u8> {
+    let upper = s.to_uppercase();
+    let digits = upper.strip_prefix('V')?;
+    if digits.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digits, 16).ok()
+}
+
+fn check_byte(v: i64) -> Result<u8, String> {
+    if (-0x80..=0xFF).contains(&v) {
+        Ok(v as u8)
+    } else {
+        Err(format!("byte out of range: {}", v))
+    }
+}
+
+#[derive(PartialEq)]
+enum Operand {
+    Register(u8),
+    I,
+    IndirectI,
+    Dt,
+    St,
+    K,
+    F,
+    B,
+    Value(i64),
+}
+
+fn parse_operand(s: &str, symbols: &HashMap<String, i64>) -> Result<Operand, String> {
+    if let Some(x) = register_name(s) {
+        return Ok(Operand::Register(x));
+    }
+    Ok(match s.to_uppercase().as_str() {
+        "I" => Operand::I,
+        "[I]" => Operand::IndirectI,
+        "DT" => Operand::Dt,
+        "ST" => Operand::St,
+        "K" => Operand::K,
+        "F" => Operand::F,
+        "B" => Operand::B,
+        _ => Operand::Value(evaluate(s, symbols)?),
+    })
+}
+
+fn encode(
+    mnemonic: &str,
+    operands: &[String],
+    symbols: &HashMap<String, i64>,
+) -> Result<u16, String> {
+    let operands = operands
+        .iter()
+        .map(|o| parse_operand(o, symbols))
+        .collect::<Result<Vec<Operand>, String>>()?;
+
+    let addr = |v: i64| -> Result<u16, String> {
+        if (0..MEMORY_SIZE as i64).contains(&v) {
+            Ok(v as u16)
+        } else {
+            Err(format!("address out of range: 0x{:X}", v))
+        }
+    };
+    let byte = |v: i64| -> Result<u16, String> { check_byte(v).map(|b| b as u16) };
+    let nibble = |v: i64| -> Result<u16, String> {
+        if (0..=0xF).contains(&v) {
+            Ok(v as u16)
+        } else {
+            Err(format!("nibble out of range: {}", v))
+        }
+    };
+    let xy = |x: u8, y: u8| ((x as u16) << 8) | ((y as u16) << 4);
+
+    use Operand::*;
+    let opcode = match (mnemonic, operands.as_slice()) {
+        ("CLS", []) => 0x00E0,
+        ("RET", []) => 0x00EE,
+        ("SYS", [Value(v)]) => addr(*v)?,
+        ("JP", [Value(v)]) => 0x1000 | addr(*v)?,
+        ("JP", [Register(0), Value(v)]) => 0xB000 | addr(*v)?,
+        ("CALL", [Value(v)]) => 0x2000 | addr(*v)?,
+        ("SE", [Register(x), Value(v)]) => 0x3000 | xy(*x, 0) | byte(*v)?,
+        ("SNE", [Register(x), Value(v)]) => 0x4000 | xy(*x, 0) | byte(*v)?,
+        ("SE", [Register(x), Register(y)]) => 0x5000 | xy(*x, *y),
+        ("LD", [Register(x), Value(v)]) => 0x6000 | xy(*x, 0) | byte(*v)?,
+        ("ADD", [Register(x), Value(v)]) => 0x7000 | xy(*x, 0) | byte(*v)?,
+        ("LD", [Register(x), Register(y)]) => 0x8000 | xy(*x, *y),
+        ("OR", [Register(x), Register(y)]) => 0x8001 | xy(*x, *y),
+        ("AND", [Register(x), Register(y)]) => 0x8002 | xy(*x, *y),
+        ("XOR", [Register(x), Register(y)]) => 0x8003 | xy(*x, *y),
+        ("ADD", [Register(x), Register(y)]) => 0x8004 | xy(*x, *y),
+        ("SUB", [Register(x), Register(y)]) => 0x8005 | xy(*x, *y),
+        ("SHR", [Register(x)]) => 0x8006 | xy(*x, *x),
+        ("SHR", [Register(x), Register(y)]) => 0x8006 | xy(*x, *y),
+        ("SUBN", [Register(x), Register(y)]) => 0x8007 | xy(*x, *y),
+        ("SHL", [Register(x)]) => 0x800E | xy(*x, *x),
+        ("SHL", [Register(x), Register(y)]) => 0x800E | xy(*x, *y),
+        ("SNE", [Register(x), Register(y)]) => 0x9000 | xy(*x, *y),
+        ("LD", [I, Value(v)]) => 0xA000 | addr(*v)?,
+        ("RND", [Register(x), Value(v)]) => 0xC000 | xy(*x, 0) | byte(*v)?,
+        ("DRW", [Register(x), Register(y), Value(v)]) => 0xD000 | xy(*x, *y) | nibble(*v)?,
+        ("SKP", [Register(x)]) => 0xE09E | xy(*x, 0),
+        ("SKNP", [Register(x)]) => 0xE0A1 | xy(*x, 0),
+        ("LD", [Register(x), Dt]) => 0xF007 | xy(*x, 0),
+        ("LD", [Register(x), K]) => 0xF00A | xy(*x, 0),
+        ("LD", [Dt, Register(x)]) => 0xF015 | xy(*x, 0),
+        ("LD", [St, Register(x)]) => 0xF018 | xy(*x, 0),
+        ("ADD", [I, Register(x)]) => 0xF01E | xy(*x, 0),
+        ("LD", [F, Register(x)]) => 0xF029 | xy(*x, 0),
+        ("LD", [B, Register(x)]) => 0xF033 | xy(*x, 0),
+        ("LD", [IndirectI, Register(x)]) => 0xF055 | xy(*x, 0),
+        ("LD", [Register(x), IndirectI]) => 0xF065 | xy(*x, 0),
+        _ => {
+            return Err(match mnemonic {
+                "CLS" | "RET" | "SYS" | "JP" | "CALL" | "SE" | "SNE" | "LD" | "ADD" | "OR"
+                | "AND" | "XOR" | "SUB" | "SHR" | "SUBN" | "SHL" | "RND" | "DRW" | "SKP"
+                | "SKNP" => format!("invalid operands for {}", mnemonic),
+                _ => format!("unknown mnemonic: {}", mnemonic),
+            })
+        }
+    };
+    Ok(opcode)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::disassemble;
+
+    fn assemble(source: &str) -> Result<Vec<u8>, AsmError> {
+        assemble_with_loader(source, "test.asm", &mut |path: &Path| {
+            Err(std::io::Error::new(
+                std::io::ErrorKind::NotFound,
+                format!("{}", path.display()),
+            ))
+        })
+    }
+
+    #[test]
+    fn test_roundtrip_every_opcode() {
+        let opcodes = [
+            0x00E0, 0x00EE, 0x0123, 0x1234, 0x2345, 0x3A12, 0x4B34, 0x5120, 0x6F01, 0x7001, 0x8120,
+            0x8121, 0x8122, 0x8123, 0x8124, 0x8125, 0x8126, 0x8127, 0x812E, 0x9120, 0xA2F0, 0xB300,
+            0xC10F, 0xD125, 0xE39E, 0xE3A1, 0xF407, 0xF40A, 0xF415, 0xF418, 0xF41E, 0xF429, 0xF433,
+            0xF455, 0xF465,
+        ];
+        for opcode in opcodes.iter() {
+            let rom = assemble(&disassemble(*opcode)).unwrap();
+            assert_eq!(rom, opcode.to_be_bytes(), "{}", disassemble(*opcode));
+        }
+    }
+
+    #[test]
+    fn test_labels_and_constants() {
+        let source = "
+            HEIGHT EQU 3
+            start:  LD I, sprite   ; forward reference
+                    DRW V0, V1, HEIGHT
+                    jp start
+            sprite: DB 0x3C, %01000010, $81
+                    DW start + 2, -1
+        ";
+        assert_eq!(
+            assemble(source).unwrap(),
+            vec![0xA2, 0x06, 0xD0, 0x13, 0x12, 0x00, 0x3C, 0x42, 0x81, 0x02, 0x02, 0xFF, 0xFF]
+        );
+    }
+
+    #[test]
+    fn test_db_string() {
+        assert_eq!(
+            assemble("DB \"a;b\", 0").unwrap(),
+            vec![b'a', b';', b'b', 0]
+        );
+    }
+
+    #[test]
+    fn test_shift_with_single_operand() {
+        assert_eq!(
+            assemble("SHR V3\nSHL V4").unwrap(),
+            vec![0x83, 0x36, 0x84, 0x4E]
+        );
+    }
+
+    #[test]
+    fn test_include() {
+        let mut load = |path: &Path| {
+            assert_eq!(path, Path::new("dir/sprites.asm"));
+            Ok("sprite: DB 0xFF\n".to_string())
+        };
+        let rom = assemble_with_loader(
+            "LD I, sprite\nINCLUDE \"sprites.asm\"",
+            "dir/main.asm",
+            &mut load,
+        )
+        .unwrap();
+        assert_eq!(rom, vec![0xA2, 0x02, 0xFF]);
+    }
+
+    #[test]
+    fn test_errors_have_line_numbers() {
+        let error = |source| assemble(source).unwrap_err();
+        assert_eq!(
+            error("CLS\nLD V0, 0x100"),
+            AsmError {
+                file: "test.asm".to_string(),
+                line: 2,
+                message: "byte out of range: 256".to_string()
+            }
+        );
+        assert_eq!(error("\n\nJP nowhere").line, 3);
+        assert_eq!(error("\n\nJP nowhere").message, "undefined symbol: nowhere");
+        assert_eq!(error("FOO V0").message, "unknown mnemonic: FOO");
+        assert_eq!(error("DRW V0, V1").message, "invalid operands for DRW");
+        assert_eq!(error("DRW V0, V1, 16").message, "nibble out of range: 16");
+        assert_eq!(error("a:\na:").message, "duplicate symbol: a");
+        assert_eq!(
+            error("CLS\nJP 0x7FFFFFFFFFFFFFFF + 1"),
+            AsmError {
+                file: "test.asm".to_string(),
+                line: 2,
+                message: "expression overflows: 0x7FFFFFFFFFFFFFFF + 1".to_string(),
+            }
+        );
+        assert_eq!(
+            error("LD V0, -0x7FFFFFFFFFFFFFFF - 2").message,
+            "expression overflows: -0x7FFFFFFFFFFFFFFF - 2"
+        );
+        assert_eq!(error("INCLUDE \"missing.asm\"").line, 1);
+    }
+}
@@ -0,0 +1,162 @@
+//! Hooks on the memory accesses of executing instructions.
+//!
+//! Opcode fetches, the sprite reads of `DXYN`, the writes of `FX33` and `FX55` and the reads of
+//! `FX65` go through the [`Bus`] of the machine, which can log them, redirect them to
+//! memory-mapped peripherals or refuse writes. Inspecting and patching memory from the outside
+//! ([`Chip8::read_memory`](crate::Chip8::read_memory), the debuggers) bypasses it.
+//!
+//! The default bus, [`Ram`], is plain memory and compiles down to direct array accesses.
+
+/// Size of the address space, addresses passed to a bus are below it.
+pub const MEMORY_SIZE: usize = 0x1000;
+
+/// Memory accesses of the CPU. The default methods access `memory` directly.
+pub trait Bus {
+    /// Fetches the big-endian opcode at `address`.
+    #[inline(always)]
+    fn fetch(&mut self, memory: &[u8; MEMORY_SIZE], address: u16) -> u16 {
+        let hi = memory[address as usize % MEMORY_SIZE];
+        let low = memory[(address as usize + 1) % MEMORY_SIZE];
+        u16::from_be_bytes([hi, low])
+    }
+
+    /// Reads a data byte.
+    #[inline(always)]
+    fn read(&mut self, memory: &[u8; MEMORY_SIZE], address: u16) -> u8 {
+        memory[address as usize % MEMORY_SIZE]
+    }
+
+    /// Writes a data byte, a bus may drop the write.
+    #[inline(always)]
+    fn write(&mut self, memory: &mut [u8; MEMORY_SIZE], address: u16, value: u8) {
+        memory[address as usize % MEMORY_SIZE] = value;
+    }
+}
+
+/// Plain memory without hooks.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Ram;
+
+impl Bus for Ram {}
+
+/// Drops writes to the interpreter area 0x000-0x1FF, which holds the font, and counts them.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct WriteProtect {
+    /// Writes dropped so far.
+    pub blocked_writes: u32,
+    /// Address of the last dropped write.
+    pub last_blocked: Option<u16>,
+}
+
+impl Bus for WriteProtect {
+    fn write(&mut self, memory: &mut [u8; MEMORY_SIZE], address: u16, value: u8) {
+        if address < 0x200 {
+            self.blocked_writes += 1;
+            self.last_blocked = Some(address);
+        } else {
+            memory[address as usize % MEMORY_SIZE] = value;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::XorShiftRandom;
+    use crate::Chip8;
+
+    fn machine<B: Bus>(bus: B, opcodes: &[u16]) -> Chip8<XorShiftRandom, B> {
+        let program: Vec<u8> = opcodes.iter().flat_map(|op| op.to_be_bytes()).collect();
+        let mut chip8 = Chip8::with_random_and_bus(XorShiftRandom::default(), bus);
+        chip8.load_program(&program);
+        chip8
+    }
+
+    #[derive(Default)]
+    struct Log {
+        accesses: Vec<(char, u16)>,
+    }
+
+    impl Bus for Log {
+        fn fetch(&mut self, memory: &[u8; MEMORY_SIZE], address: u16) -> u16 {
+            self.accesses.push(('x', address));
+            Ram.fetch(memory, address)
+        }
+
+        fn read(&mut self, memory: &[u8; MEMORY_SIZE], address: u16) -> u8 {
+            self.accesses.push(('r', address));
+            memory[address as usize]
+        }
+
+        fn write(&mut self, memory: &mut [u8; MEMORY_SIZE], address: u16, value: u8) {
+            self.accesses.push(('w', address));
+            memory[address as usize] = value;
+        }
+    }
+
+    #[test]
+    fn accesses_go_through_the_bus() {
+        // LD I, 0x300; LD B, V0; LD V0, [I]
+        let mut chip8 = machine(Log::default(), &[0xA300, 0xF033, 0xF065]);
+        for _ in 0..3 {
+            chip8.step();
+        }
+        assert_eq!(
+            chip8.bus().accesses,
+            [
+                ('x', 0x200),
+                ('x', 0x202),
+                ('w', 0x300),
+                ('w', 0x301),
+                ('w', 0x302),
+                ('x', 0x204),
+                ('r', 0x300),
+            ]
+        );
+        // NOTE: inspection does not go through the bus
+        chip8.read_memory(0x300);
+        assert_eq!(chip8.bus().accesses.len(), 7);
+    }
+
+    #[test]
+    fn write_protect() {
+        // LD V0, 0xAB; LD I, 0x000; LD [I], V0; LD I, 0x300; LD [I], V0
+        let mut chip8 = machine(
+            WriteProtect::default(),
+            &[0x60AB, 0xA000, 0xF055, 0xA300, 0xF055],
+        );
+        for _ in 0..5 {
+            chip8.step();
+        }
+        assert_eq!(chip8.read_memory(0x000), 0xF0);
+        assert_eq!(chip8.read_memory(0x300), 0xAB);
+        assert_eq!(chip8.bus().blocked_writes, 1);
+        assert_eq!(chip8.bus().last_blocked, Some(0x000));
+    }
+
+    // a peripheral mapped at 0xFFF, which counts its reads
+    struct Counter(u8);
+
+    impl Bus for Counter {
+        fn read(&mut self, memory: &[u8; MEMORY_SIZE], address: u16) -> u8 {
+            if address == 0xFFF {
+                self.0 += 1;
+                self.0
+            } else {
+                memory[address as usize]
+            }
+        }
+    }
+
+    #[test]
+    fn memory_mapped_peripheral() {
+        // LD I, 0xFFF; LD V0, [I]; LD I, 0xFFF; LD V0, [I]
+        let mut chip8 = machine(Counter(0), &[0xAFFF, 0xF065, 0xAFFF, 0xF065]);
+        chip8.step();
+        chip8.step();
+        assert_eq!(chip8.read_register(0), 1);
+        chip8.step();
+        chip8.step();
+        assert_eq!(chip8.read_register(0), 2);
+    }
+}
@@ -0,0 +1,347 @@
+//! Screenshots (PNG) and gameplay recording (animated GIF or raw RGB24 frames).
+use std::convert::TryFrom;
+use std::error::Error;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::str::FromStr;
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+
+/// Largest scale, a 4096x2048 image. Anything larger only wastes memory, a frame at
+/// `u16::MAX / WIDTH` (the GIF limit) would take gigabytes.
+pub const MAX_SCALE: usize = 64;
+
+fn check_scale(scale: usize) -> Result<usize, String> {
+    if (1..=MAX_SCALE).contains(&scale) {
+        Ok(scale)
+    } else {
+        Err(format!(
+            "scale must be between 1 and {}: {}",
+            MAX_SCALE, scale
+        ))
+    }
+}
+
+/// Parses a `--scale` argument, see [`MAX_SCALE`].
+pub fn parse_scale(s: &str) -> Result<usize, String> {
+    let scale = s.parse().map_err(|_| format!("invalid scale: {}", s))?;
+    check_scale(scale)
+}
+
+/// Colors of lit and unlit pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Palette {
+    /// RGB of lit pixels.
+    pub foreground: [u8; 3],
+    /// RGB of unlit pixels.
+    pub background: [u8; 3],
+}
+
+impl Palette {
+    /// Color of a pixel, `true` is lit.
+    pub fn color(&self, b: bool) -> [u8; 3] {
+        match b {
+            true => self.foreground,
+            false => self.background,
+        }
+    }
+}
+
+impl Default for Palette {
+    fn default() -> Palette {
+        Palette {
+            foreground: [0xFF, 0xFF, 0xFF],
+            background: [0x00, 0x00, 0x00],
+        }
+    }
+}
+
+fn parse_rgb(s: &str) -> Result<[u8; 3], String> {
+    let s = s.trim_start_matches('#');
+    if s.len() != 6 || !s.chars().all(|c| c.is_ascii_hexdigit()) {
+        return Err(format!("invalid color: {}", s));
+    }
+    let v = u32::from_str_radix(s, 16).map_err(|_| format!("invalid color: {}", s))?;
+    Ok([(v >> 16) as u8, (v >> 8) as u8, v as u8])
+}
+
+/// Parses `RRGGBB,RRGGBB` (foreground, background), each color may start with `#`.
+impl FromStr for Palette {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Palette, String> {
+        let mut colors = s.split(',');
+        match (colors.next(), colors.next(), colors.next()) {
+            (Some(fg), Some(bg), None) => Ok(Palette {
+                foreground: parse_rgb(fg)?,
+                background: parse_rgb(bg)?,
+            }),
+            _ => Err(format!("palette must be FOREGROUND,BACKGROUND: {}", s)),
+        }
+    }
+}
+
+/// Converts the display into RGB24 pixels, each CHIP-8 pixel becomes a `scale` x `scale` block.
+pub fn render(display: &[bool], palette: &Palette, scale: usize) -> Vec<u8> {
+    let mut pixels = Vec::with_capacity(WIDTH * HEIGHT * scale * scale * 3);
+    for j in 0..(HEIGHT * scale) {
+        for i in 0..(WIDTH * scale) {
+            let b = display[WIDTH * (j / scale) + i / scale];
+            pixels.extend_from_slice(&palette.color(b));
+        }
+    }
+    pixels
+}
+
+/// Writes the display as a PNG file.
+pub fn save_png(
+    path: &str,
+    display: &[bool],
+    palette: &Palette,
+    scale: usize,
+) -> Result<(), Box<dyn Error>> {
+    let scale = check_scale(scale)?;
+    let f = BufWriter::new(File::create(path)?);
+    let mut encoder = png::Encoder::new(f, (WIDTH * scale) as u32, (HEIGHT * scale) as u32);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_depth(png::BitDepth::Eight);
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&render(display, palette, scale))?;
+    Ok(())
+}
+
+/// Animated GIF, identical consecutive frames are merged into one.
+pub struct GifRecorder {
+    encoder: gif::Encoder<BufWriter<File>>,
+    scale: usize,
+    width: u16,
+    height: u16,
+    pending: Option<Vec<u8>>,
+    pending_frames: u64,
+    written_frames: u64,
+}
+
+impl GifRecorder {
+    fn push_frame(&mut self, display: &[bool]) -> Result<(), Box<dyn Error>> {
+        let scale = self.scale;
+        let mut indices = Vec::with_capacity(WIDTH * HEIGHT * scale * scale);
+        for j in 0..(HEIGHT * scale) {
+            for i in 0..(WIDTH * scale) {
+                indices.push(display[WIDTH * (j / scale) + i / scale] as u8);
+            }
+        }
+
+        if self.pending.as_ref() == Some(&indices) {
+            self.pending_frames += 1;
+        } else {
+            self.flush_frame()?;
+            self.pending = Some(indices);
+            self.pending_frames = 1;
+        }
+        Ok(())
+    }
+
+    // write the pending frame with a delay covering all merged frames
+    fn flush_frame(&mut self) -> Result<(), Box<dyn Error>> {
+        if let Some(indices) = self.pending.take() {
+            let mut frame =
+                gif::Frame::from_indexed_pixels(self.width, self.height, &indices, None);
+            frame.delay = frame_delay(self.written_frames, self.pending_frames);
+            self.written_frames += self.pending_frames;
+            self.encoder.write_frame(&frame)?;
+        }
+        Ok(())
+    }
+}
+
+// GIF delay is in 1/100 sec, round on the accumulated time to avoid drift
+fn frame_delay(written_frames: u64, frames: u64) -> u16 {
+    let start = (written_frames * 100 + 30) / 60;
+    let end = ((written_frames + frames) * 100 + 30) / 60;
+    (end - start).min(u16::MAX as u64) as u16
+}
+
+/// Headerless RGB24 frames, suitable for `ffmpeg -f rawvideo -pixel_format rgb24`.
+pub struct RawRecorder {
+    writer: Box<dyn Write>,
+    palette: Palette,
+    scale: usize,
+}
+
+/// Gameplay recording, frames are pushed at 60hz.
+pub enum Recorder {
+    /// Animated GIF.
+    Gif(GifRecorder),
+    /// Raw RGB24 frames.
+    Raw(RawRecorder),
+}
+
+impl Recorder {
+    /// Starts recording into `path`: `*.gif` records a GIF, anything else raw frames (`-` means
+    /// stdout).
+    pub fn create(path: &str, palette: &Palette, scale: usize) -> Result<Recorder, Box<dyn Error>> {
+        let scale = check_scale(scale)?;
+        if path.ends_with(".gif") {
+            let width = u16::try_from(WIDTH * scale)?;
+            let height = u16::try_from(HEIGHT * scale)?;
+            let f = BufWriter::new(File::create(path)?);
+            let global_palette = [palette.background, palette.foreground].concat();
+            let mut encoder = gif::Encoder::new(f, width, height, &global_palette)?;
+            encoder.set_repeat(gif::Repeat::Infinite)?;
+            Ok(Recorder::Gif(GifRecorder {
+                encoder,
+                scale,
+                width,
+                height,
+                pending: None,
+                pending_frames: 0,
+                written_frames: 0,
+            }))
+        } else {
+            let writer: Box<dyn Write> = if path == "-" {
+                Box::new(BufWriter::new(std::io::stdout()))
+            } else {
+                Box::new(BufWriter::new(File::create(path)?))
+            };
+            Ok(Recorder::Raw(RawRecorder {
+                writer,
+                palette: *palette,
+                scale,
+            }))
+        }
+    }
+
+    /// Adds the display as the next frame.
+    pub fn push_frame(&mut self, display: &[bool]) -> Result<(), Box<dyn Error>> {
+        match self {
+            Recorder::Gif(recorder) => recorder.push_frame(display)?,
+            Recorder::Raw(recorder) => {
+                recorder
+                    .writer
+                    .write_all(&render(display, &recorder.palette, recorder.scale))?
+            }
+        }
+        Ok(())
+    }
+
+    /// Writes the pending frames and flushes the output.
+    pub fn finish(self) -> Result<(), Box<dyn Error>> {
+        match self {
+            Recorder::Gif(mut recorder) => {
+                recorder.flush_frame()?;
+                recorder.encoder.into_inner()?.flush()?;
+            }
+            Recorder::Raw(mut recorder) => {
+                recorder.writer.flush()?;
+            }
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_palette() {
+        assert_eq!(parse_rgb("#12AbEF"), Ok([0x12, 0xAB, 0xEF]));
+        assert_eq!(parse_rgb("000000"), Ok([0x00, 0x00, 0x00]));
+        assert_eq!(parse_rgb("FFF"), Err("invalid color: FFF".to_string()));
+        assert_eq!(
+            parse_rgb("GGGGGG"),
+            Err("invalid color: GGGGGG".to_string())
+        );
+        assert_eq!(
+            parse_rgb("+12345"),
+            Err("invalid color: +12345".to_string())
+        );
+
+        assert_eq!(
+            "FF8000,#000010".parse::<Palette>(),
+            Ok(Palette {
+                foreground: [0xFF, 0x80, 0x00],
+                background: [0x00, 0x00, 0x10],
+            })
+        );
+        assert_eq!(
+            "FFFFFF".parse::<Palette>(),
+            Err("palette must be FOREGROUND,BACKGROUND: FFFFFF".to_string())
+        );
+        assert_eq!(
+            "FFFFFF,000000,FF0000".parse::<Palette>(),
+            Err("palette must be FOREGROUND,BACKGROUND: FFFFFF,000000,FF0000".to_string())
+        );
+        assert_eq!(
+            "FFFFFF,00".parse::<Palette>(),
+            Err("invalid color: 00".to_string())
+        );
+    }
+
+    #[test]
+    fn test_render() {
+        let palette = Palette {
+            foreground: [1, 2, 3],
+            background: [4, 5, 6],
+        };
+        let mut display = [false; WIDTH * HEIGHT];
+        display[0] = true;
+        display[WIDTH * HEIGHT - 1] = true;
+
+        let pixels = render(&display, &palette, 1);
+        assert_eq!(pixels.len(), WIDTH * HEIGHT * 3);
+        assert_eq!(pixels[..6], [1, 2, 3, 4, 5, 6]);
+        assert_eq!(pixels[pixels.len() - 6..], [4, 5, 6, 1, 2, 3]);
+
+        // each pixel becomes a 2x2 block
+        let pixels = render(&display, &palette, 2);
+        let row = WIDTH * 2 * 3;
+        assert_eq!(pixels.len(), WIDTH * HEIGHT * 4 * 3);
+        assert_eq!(pixels[..9], [1, 2, 3, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(pixels[row..row + 9], [1, 2, 3, 1, 2, 3, 4, 5, 6]);
+        assert_eq!(pixels[2 * row..2 * row + 3], [4, 5, 6]);
+        assert_eq!(pixels[pixels.len() - 9..], [4, 5, 6, 1, 2, 3, 1, 2, 3]);
+    }
+
+    #[test]
+    fn test_frame_delay() {
+        // 60 single frames add up to exactly one second without drift
+        let delays: Vec<u16> = (0..60).map(|n| frame_delay(n, 1)).collect();
+        assert_eq!(delays[..6], [2, 1, 2, 2, 1, 2]);
+        assert_eq!(delays.iter().map(|d| *d as u64).sum::<u64>(), 100);
+
+        assert_eq!(frame_delay(0, 60), 100);
+        assert_eq!(frame_delay(3, 6), 10);
+        assert_eq!(frame_delay(0, u64::MAX / 1000), u16::MAX);
+    }
+
+    #[test]
+    fn test_scale() {
+        assert_eq!(parse_scale("1"), Ok(1));
+        assert_eq!(parse_scale("64"), Ok(64));
+        assert_eq!(
+            parse_scale("0"),
+            Err("scale must be between 1 and 64: 0".to_string())
+        );
+        assert_eq!(
+            parse_scale("65"),
+            Err("scale must be between 1 and 64: 65".to_string())
+        );
+        assert_eq!(parse_scale("-1"), Err("invalid scale: -1".to_string()));
+
+        // rejected before the output file is created
+        let path = "no/such/directory/capture.gif";
+        for scale in [0, MAX_SCALE + 1].iter() {
+            let error = Recorder::create(path, &Palette::default(), *scale)
+                .err()
+                .unwrap();
+            assert!(error.to_string().starts_with("scale must be between"));
+            let error = save_png(path, &[false; WIDTH * HEIGHT], &Palette::default(), *scale)
+                .err()
+                .unwrap();
+            assert!(error.to_string().starts_with("scale must be between"));
+        }
+    }
+}
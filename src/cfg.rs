@@ -0,0 +1,302 @@
+//! Static control-flow graph recovery.
+//!
+//! Splits the code found by following control flow from 0x200 (see
+//! [`disasm::trace_code`]) into basic blocks: straight-line runs of
+//! instructions entered only at the top, ended by JP, CALL, RET, a skip, or the next block. Edges
+//! are jumps, calls, fall-throughs (including the return address after a CALL) and taken skips.
+//!
+//! Computed jumps (`JP V0`) cannot be followed statically, their blocks are flagged as
+//! unresolved.
+use crate::disasm::{self, Syntax};
+use crate::instruction::{decode, Instruction};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt::Write;
+
+/// How control reaches the target of an edge.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum EdgeKind {
+    /// The next instruction, also the return address after a CALL.
+    FallThrough,
+    /// JP.
+    Jump,
+    /// CALL.
+    Call,
+    /// The skip is taken, it falls through otherwise.
+    Skip,
+}
+
+/// Control-flow edge leaving a block.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Edge {
+    /// Address control goes to.
+    pub target: u16,
+    /// How control gets there.
+    pub kind: EdgeKind,
+}
+
+/// Basic block: instructions which always execute in sequence.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Block {
+    /// Address of the first instruction.
+    pub start: u16,
+    /// `(address, opcode)` of the instructions, in order.
+    pub instructions: Vec<(u16, u16)>,
+    /// Where control goes after the last instruction.
+    pub edges: Vec<Edge>,
+    /// Ends with a computed jump, whose targets are unknown.
+    pub unresolved: bool,
+}
+
+impl Block {
+    /// Address of the last instruction.
+    pub fn last(&self) -> u16 {
+        self.instructions.last().unwrap().0
+    }
+
+    /// Does the block end with RET?
+    pub fn returns(&self) -> bool {
+        decode(self.instructions.last().unwrap().1) == Instruction::Ret
+    }
+}
+
+/// Control-flow graph of a ROM.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Cfg {
+    /// Basic blocks by start address.
+    pub blocks: BTreeMap<u16, Block>,
+    /// Labels of jump and call targets, as the disassembler generates them.
+    pub labels: BTreeMap<u16, String>,
+}
+
+// does the instruction end a basic block?
+fn ends_block(instruction: Instruction) -> bool {
+    matches!(
+        instruction,
+        Instruction::Jp(_) | Instruction::Call(_) | Instruction::Ret | Instruction::JpV0(_)
+    ) || instruction.is_skip()
+}
+
+fn edges(instruction: Instruction, address: u16) -> Vec<Edge> {
+    let edge = |target, kind| Edge { target, kind };
+    // NOTE: no edge past the end of the address space
+    let fall_through = address
+        .checked_add(2)
+        .map(|next| edge(next, EdgeKind::FallThrough));
+    match instruction {
+        Instruction::Jp(nnn) => vec![edge(nnn, EdgeKind::Jump)],
+        Instruction::Call(nnn) => Some(edge(nnn, EdgeKind::Call))
+            .into_iter()
+            .chain(fall_through)
+            .collect(),
+        Instruction::Ret | Instruction::JpV0(_) => vec![],
+        _ if instruction.is_skip() => fall_through
+            .into_iter()
+            .chain(
+                address
+                    .checked_add(4)
+                    .map(|skip| edge(skip, EdgeKind::Skip)),
+            )
+            .collect(),
+        _ => fall_through.into_iter().collect(),
+    }
+}
+
+/// Recovers the basic blocks of `rom`, loaded at 0x200.
+pub fn recover(rom: &[u8]) -> Cfg {
+    let code = disasm::trace_code(rom);
+    let opcode = |address: u16| disasm::read_opcode(rom, address).unwrap();
+
+    // NOTE: addresses reached other than by falling through start a block
+    let mut leaders = BTreeSet::new();
+    for address in code.iter() {
+        let instruction = decode(opcode(*address));
+        if ends_block(instruction) {
+            leaders.extend(disasm::successors(instruction, *address));
+        }
+    }
+    for address in code.iter() {
+        if !code.contains(&address.wrapping_sub(2)) {
+            leaders.insert(*address);
+        }
+    }
+
+    let mut blocks = BTreeMap::new();
+    for start in leaders.iter().filter(|address| code.contains(address)) {
+        let mut block = Block {
+            start: *start,
+            instructions: Vec::new(),
+            edges: Vec::new(),
+            unresolved: false,
+        };
+        let mut address = *start;
+        loop {
+            let instruction = decode(opcode(address));
+            block.instructions.push((address, opcode(address)));
+            let next = address + 2;
+            if ends_block(instruction) || leaders.contains(&next) || !code.contains(&next) {
+                block.edges = edges(instruction, address);
+                block.unresolved = matches!(instruction, Instruction::JpV0(_));
+                break;
+            }
+            address = next;
+        }
+        blocks.insert(*start, block);
+    }
+
+    Cfg {
+        blocks,
+        labels: disasm::make_labels(rom, &code),
+    }
+}
+
+impl Cfg {
+    /// Graphviz DOT, one node per block listing its instructions. Edges to addresses outside the
+    /// recovered code end in plain text nodes.
+    pub fn to_dot(&self, syntax: Syntax) -> String {
+        let mut dot = String::new();
+        writeln!(dot, "digraph cfg {{").unwrap();
+        writeln!(dot, "    node [shape=box, fontname=\"monospace\"];").unwrap();
+
+        for block in self.blocks.values() {
+            let mut label = String::new();
+            if let Some(name) = self.labels.get(&block.start) {
+                write!(label, "{}:\\l", name).unwrap();
+            }
+            for (address, opcode) in block.instructions.iter() {
+                let mnemonic = disasm::format_instruction(decode(*opcode), syntax, &self.labels);
+                write!(label, "0x{:03X}: {}\\l", address, mnemonic).unwrap();
+            }
+            if block.unresolved {
+                write!(label, "(unresolved computed jump)\\l").unwrap();
+                writeln!(
+                    dot,
+                    "    \"0x{:03X}\" [label=\"{}\", color=red];",
+                    block.start, label
+                )
+                .unwrap();
+            } else {
+                writeln!(dot, "    \"0x{:03X}\" [label=\"{}\"];", block.start, label).unwrap();
+            }
+        }
+
+        let outside: BTreeSet<u16> = self
+            .blocks
+            .values()
+            .flat_map(|block| block.edges.iter())
+            .map(|edge| edge.target)
+            .filter(|target| !self.blocks.contains_key(target))
+            .collect();
+        for target in outside {
+            writeln!(dot, "    \"0x{:03X}\" [shape=plaintext];", target).unwrap();
+        }
+
+        for block in self.blocks.values() {
+            for edge in block.edges.iter() {
+                let attributes = match edge.kind {
+                    EdgeKind::FallThrough => "",
+                    EdgeKind::Jump => " [style=bold]",
+                    EdgeKind::Call => " [style=dotted, label=\"call\"]",
+                    EdgeKind::Skip => " [style=dashed, label=\"skip\"]",
+                };
+                writeln!(
+                    dot,
+                    "    \"0x{:03X}\" -> \"0x{:03X}\"{};",
+                    block.start, edge.target, attributes
+                )
+                .unwrap();
+            }
+        }
+        writeln!(dot, "}}").unwrap();
+        dot
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // 0x200: LD V0, 0; loop: CALL sub; SE V0, 5; JP loop; JP V0, 0x300
+    // 0x20A: sub: ADD V0, 1; RET
+    const ROM: [u8; 14] = [
+        0x60, 0x00, 0x22, 0x0A, 0x30, 0x05, 0x12, 0x02, 0xB3, 0x00, 0x70, 0x01, 0x00, 0xEE,
+    ];
+
+    #[test]
+    fn blocks_and_edges() {
+        let cfg = recover(&ROM);
+        let starts: Vec<u16> = cfg.blocks.keys().copied().collect();
+        assert_eq!(starts, [0x200, 0x202, 0x204, 0x206, 0x208, 0x20A]);
+
+        let entry = &cfg.blocks[&0x200];
+        assert_eq!(entry.instructions, [(0x200, 0x6000)]);
+        assert_eq!(
+            entry.edges,
+            [Edge {
+                target: 0x202,
+                kind: EdgeKind::FallThrough
+            }]
+        );
+        let kinds = |start: u16| -> Vec<(u16, EdgeKind)> {
+            cfg.blocks[&start]
+                .edges
+                .iter()
+                .map(|edge| (edge.target, edge.kind))
+                .collect()
+        };
+        assert_eq!(
+            kinds(0x202),
+            [(0x20A, EdgeKind::Call), (0x204, EdgeKind::FallThrough)]
+        );
+        assert_eq!(
+            kinds(0x204),
+            [(0x206, EdgeKind::FallThrough), (0x208, EdgeKind::Skip)]
+        );
+        assert_eq!(kinds(0x206), [(0x202, EdgeKind::Jump)]);
+
+        assert!(cfg.blocks[&0x208].unresolved);
+        assert!(cfg.blocks[&0x208].edges.is_empty());
+        let sub = &cfg.blocks[&0x20A];
+        assert_eq!(sub.instructions.len(), 2);
+        assert_eq!(sub.last(), 0x20C);
+        assert!(sub.returns());
+    }
+
+    #[test]
+    fn large_rom() {
+        // straight-line code up to the end of memory, the rest of the ROM does not fit
+        let rom: Vec<u8> = [0x60, 0x00].iter().copied().cycle().take(0x10000).collect();
+        let cfg = recover(&rom);
+        assert_eq!(cfg.blocks.len(), 1);
+        assert_eq!(cfg.blocks[&0x200].instructions.len(), 0x700);
+        assert_eq!(cfg.blocks[&0x200].last(), 0xFFE);
+
+        assert!(edges(decode(0x6000), 0xFFFE).is_empty());
+        assert_eq!(
+            edges(decode(0x3000), 0xFFFC),
+            [Edge {
+                target: 0xFFFE,
+                kind: EdgeKind::FallThrough
+            }]
+        );
+    }
+
+    #[test]
+    fn dot() {
+        let dot = recover(&ROM).to_dot(Syntax::Cowgod);
+        assert!(dot.starts_with("digraph cfg {\n"));
+        assert!(dot.contains(
+            "    \"0x20A\" [label=\"sub_20A:\\l0x20A: ADD V0, 0x01\\l0x20C: RET\\l\"];\n"
+        ));
+        assert!(dot.contains("(unresolved computed jump)\\l\", color=red];\n"));
+        assert!(dot.contains("    \"0x202\" -> \"0x20A\" [style=dotted, label=\"call\"];\n"));
+        assert!(dot.contains("    \"0x204\" -> \"0x208\" [style=dashed, label=\"skip\"];\n"));
+        assert!(dot.contains("    \"0x206\" -> \"0x202\" [style=bold];\n"));
+        assert!(dot.ends_with("}\n"));
+
+        // falls off the end of the ROM
+        let dot = recover(&[0x60, 0x00]).to_dot(Syntax::Octo);
+        assert!(dot.contains("    \"0x202\" [shape=plaintext];\n"));
+        assert!(dot.contains("0x200: v0 := 0x00\\l"));
+    }
+}
@@ -1,28 +1,162 @@
+//! The CHIP-8 interpreter, [`Chip8`], and its saved states.
+use crate::bus::{Bus, Ram};
+#[cfg(feature = "std")]
+use crate::disasm;
+#[cfg(feature = "std")]
+use crate::host::SystemClock;
+use crate::host::{Clock, DefaultRandom, Random};
+
+use core::fmt;
+use core::ops::Range;
 use log::info;
-use rand::Rng;
-
-lazy_static! {
-    static ref CHIP8_FONTS: Vec<u8> = vec![
-        0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
-        0x20, 0x60, 0x20, 0x20, 0x70, // 1
-        0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
-        0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
-        0x90, 0x90, 0xF0, 0x10, 0x10, // 4
-        0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
-        0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
-        0xF0, 0x10, 0x20, 0x40, 0x40, // 7
-        0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
-        0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
-        0xF0, 0x90, 0xF0, 0x90, 0x90, // A
-        0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
-        0xF0, 0x80, 0x80, 0x80, 0xF0, // C
-        0xE0, 0x90, 0x90, 0x90, 0xE0, // D
-        0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
-        0xF0, 0x80, 0xF0, 0x80, 0x80, // F
-    ];
+
+#[cfg(feature = "std")]
+use std::str::FromStr;
+
+const CHIP8_FONTS: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+/// Behaviors which differ between interpreters.
+///
+/// The default is the original COSMAC VIP behavior, except that VF is kept by logic operations.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Quirks {
+    /// `8XY1`, `8XY2`, `8XY3` reset VF to 0.
+    pub vf_reset: bool,
+    /// `8XY6`, `8XYE` shift Vx in place instead of storing shifted Vy.
+    pub shift_vx: bool,
+    /// `FX55`, `FX65` leave I unchanged instead of incrementing it past the last register.
+    pub load_store_keep_i: bool,
+    /// `BXNN` jumps to XNN + Vx instead of NNN + V0.
+    pub jump_vx: bool,
+}
+
+impl Quirks {
+    /// Original COSMAC VIP interpreter.
+    pub fn cosmac() -> Quirks {
+        Quirks {
+            vf_reset: true,
+            ..Quirks::default()
+        }
+    }
+
+    /// SUPER-CHIP 1.1, which most modern games are written for.
+    pub fn schip() -> Quirks {
+        Quirks {
+            vf_reset: false,
+            shift_vx: true,
+            load_store_keep_i: true,
+            jump_vx: true,
+        }
+    }
+}
+
+/// Parses a preset name: `default`, `cosmac` or `schip`.
+#[cfg(feature = "std")]
+impl FromStr for Quirks {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Quirks, String> {
+        match s {
+            "default" => Ok(Quirks::default()),
+            "cosmac" => Ok(Quirks::cosmac()),
+            "schip" => Ok(Quirks::schip()),
+            _ => Err(format!("unknown quirks preset: {}", s)),
+        }
+    }
+}
+
+/// Size of a state saved by [`Chip8::save_state`].
+pub const STATE_SIZE: usize = 1 + 16 + 2 + 2 + 32 + 1 + 1 + 1 + 0x1000 + 64 * 32 + 16 + 1;
+
+// NOTE: bumped whenever the state layout changes
+const STATE_VERSION: u8 = 1;
+
+/// Why [`Chip8::load_state`] rejected a state.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StateError {
+    /// The state is not [`STATE_SIZE`] bytes long.
+    Size,
+    /// The state was saved by an incompatible version.
+    Version,
+}
+
+impl fmt::Display for StateError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StateError::Size => write!(f, "state must be {} bytes", STATE_SIZE),
+            StateError::Version => write!(f, "state has an incompatible version"),
+        }
+    }
+}
+
+/// Why a machine stopped executing, see [`Chip8::fault`].
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Fault {
+    /// The opcode is no CHIP-8 instruction.
+    UnknownOpcode(u16),
+    /// `CALL` with all 16 stack levels in use.
+    StackOverflow,
+    /// `RET` with an empty stack.
+    StackUnderflow,
+}
+
+impl fmt::Display for Fault {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Fault::UnknownOpcode(opcode) => write!(f, "unknown opcode {:04X}", opcode),
+            Fault::StackOverflow => write!(f, "stack overflow"),
+            Fault::StackUnderflow => write!(f, "return with an empty stack"),
+        }
+    }
+}
+
+/// Snapshot of the CPU state, see [`Chip8::cpu_state`].
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct CpuState {
+    /// V0-VF.
+    pub registers: [u8; 16],
+    /// I.
+    pub index_register: u16,
+    /// Address of the next instruction.
+    pub program_counter: u16,
+    /// Return addresses, the first `stack_pointer` entries are in use.
+    pub stack: [u16; 16],
+    /// Number of return addresses on the stack.
+    pub stack_pointer: u8,
+    /// DT, counts down at 60hz.
+    pub delay_timer: u8,
+    /// ST, counts down at 60hz, the beeper sounds while it is non-zero.
+    pub sound_timer: u8,
+}
+
+impl CpuState {
+    /// Return addresses on the stack, oldest first.
+    pub fn call_stack(&self) -> &[u16] {
+        &self.stack[0..(self.stack_pointer as usize).min(self.stack.len())]
+    }
 }
 
-pub struct Chip8 {
+/// CHIP-8 interpreter, `R` supplies `CXKK` random numbers and instructions access memory
+/// through `B`.
+#[derive(Clone, PartialEq)]
+pub struct Chip8<R: Random = DefaultRandom, B: Bus = Ram> {
     register: [u8; 16],
     index_register: u16,
     program_counter: u16,
@@ -32,16 +166,87 @@ pub struct Chip8 {
 
     memory: [u8; 0x1000],
 
+    /// 64x32 monochrome display, row-major, `true` is lit.
     pub display: [bool; 64 * 32],
 
     delay_timer: u8,
     sound_timer: u8,
 
+    /// Hex keypad state, indexed by key 0x0-0xF, `true` is pressed.
     pub keyboard: [bool; 16],
+
+    quirks: Quirks,
+
+    fault: Option<Fault>,
+
+    random: R,
+
+    bus: B,
+}
+
+// NOTE: memory and display are left out, they would drown the registers
+impl<R: Random + fmt::Debug, B: Bus + fmt::Debug> fmt::Debug for Chip8<R, B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Chip8")
+            .field("cpu", &self.cpu_state())
+            .field("keyboard", &self.keyboard)
+            .field("quirks", &self.quirks)
+            .field("fault", &self.fault)
+            .field("random", &self.random)
+            .field("bus", &self.bus)
+            .finish_non_exhaustive()
+    }
+}
+
+impl Default for Chip8 {
+    fn default() -> Chip8 {
+        Chip8::new()
+    }
 }
 
 impl Chip8 {
+    /// Creates a machine with cleared memory and default quirks, see [`Chip8::load_program`].
     pub fn new() -> Chip8 {
+        Chip8::with_random(DefaultRandom::default())
+    }
+}
+
+// NOTE: state setters for the tools, which are not built without std
+#[cfg_attr(not(feature = "std"), allow(dead_code))]
+impl<R: Random, B: Bus> Chip8<R, B> {
+    pub(crate) fn set_index_register(&mut self, value: u16) {
+        self.index_register = value;
+    }
+
+    pub(crate) fn set_program_counter(&mut self, value: u16) {
+        self.program_counter = value;
+    }
+
+    // NOTE: stack pointer is clamped to stack size
+    pub(crate) fn set_stack_pointer(&mut self, value: u8) {
+        self.stack_pointer = value.min(self.stack.len() as u8);
+    }
+
+    pub(crate) fn set_delay_timer(&mut self, value: u8) {
+        self.delay_timer = value;
+    }
+
+    pub(crate) fn set_sound_timer(&mut self, value: u8) {
+        self.sound_timer = value;
+    }
+}
+
+impl<R: Random> Chip8<R> {
+    /// Creates a machine which takes random numbers from `random`.
+    pub fn with_random(random: R) -> Chip8<R> {
+        Chip8::with_random_and_bus(random, Ram)
+    }
+}
+
+impl<R: Random, B: Bus> Chip8<R, B> {
+    /// Creates a machine which takes random numbers from `random` and accesses memory through
+    /// `bus`.
+    pub fn with_random_and_bus(random: R, bus: B) -> Chip8<R, B> {
         Chip8 {
             register: [0; 16],
             index_register: 0,
@@ -53,46 +258,160 @@ impl Chip8 {
             delay_timer: 0,
             sound_timer: 0,
             keyboard: [false; 16],
+            quirks: Quirks::default(),
+            fault: None,
+            random,
+            bus,
         }
     }
 
-    fn push(&mut self, value: u16) {
-        self.stack[self.stack_pointer as usize] = value;
+    /// The memory bus, e.g. to read what it recorded.
+    pub fn bus(&self) -> &B {
+        &self.bus
+    }
+
+    /// The memory bus, mutable.
+    pub fn bus_mut(&mut self) -> &mut B {
+        &mut self.bus
+    }
+
+    // NOTE: accesses of instructions, the other memory accessors bypass the bus
+    #[inline(always)]
+    fn bus_read(&mut self, address: u16) -> u8 {
+        self.bus.read(&self.memory, address % 0x1000)
+    }
+
+    #[inline(always)]
+    fn bus_write(&mut self, address: u16, value: u8) {
+        self.bus.write(&mut self.memory, address % 0x1000, value);
+    }
+
+    fn push(&mut self, value: u16) -> Result<(), Fault> {
+        let slot = self
+            .stack
+            .get_mut(self.stack_pointer as usize)
+            .ok_or(Fault::StackOverflow)?;
+        *slot = value;
         self.stack_pointer += 1;
+        Ok(())
     }
 
-    fn pop(&mut self) -> u16 {
-        self.stack_pointer -= 1;
-        self.stack[self.stack_pointer as usize]
+    fn pop(&mut self) -> Result<u16, Fault> {
+        self.stack_pointer = self
+            .stack_pointer
+            .checked_sub(1)
+            .ok_or(Fault::StackUnderflow)?;
+        Ok(self.stack[self.stack_pointer as usize])
     }
 
-    fn read_register(&self, register_index: u8) -> u8 {
+    /// Selects interpreter behaviors, takes effect from the next instruction.
+    pub fn set_quirks(&mut self, quirks: Quirks) {
+        self.quirks = quirks;
+    }
+
+    // count both timers down by one 60hz tick
+    pub(crate) fn tick_timers(&mut self) {
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+    }
+
+    /// Snapshot of the registers, stack and timers.
+    pub fn cpu_state(&self) -> CpuState {
+        CpuState {
+            registers: self.register,
+            index_register: self.index_register,
+            program_counter: self.program_counter,
+            stack: self.stack,
+            stack_pointer: self.stack_pointer,
+            delay_timer: self.delay_timer,
+            sound_timer: self.sound_timer,
+        }
+    }
+
+    /// Value of Vx, panics if `register_index` is not 0x0-0xF.
+    pub fn read_register(&self, register_index: u8) -> u8 {
         self.register[register_index as usize]
     }
 
-    fn write_register(&mut self, register_index: u8, value: u8) {
+    /// Value of I.
+    pub fn index_register(&self) -> u16 {
+        self.index_register
+    }
+
+    /// Address of the next instruction.
+    pub fn program_counter(&self) -> u16 {
+        self.program_counter
+    }
+
+    /// Return addresses currently on the stack, oldest first.
+    pub fn stack(&self) -> &[u16] {
+        &self.stack[0..(self.stack_pointer as usize)]
+    }
+
+    /// Number of return addresses on the stack.
+    pub fn stack_pointer(&self) -> u8 {
+        self.stack_pointer
+    }
+
+    /// Current delay timer.
+    pub fn delay_timer(&self) -> u8 {
+        self.delay_timer
+    }
+
+    /// Current sound timer, the beeper sounds while it is non-zero.
+    pub fn sound_timer(&self) -> u8 {
+        self.sound_timer
+    }
+
+    /// Interpreter behaviors in effect, see [`Chip8::set_quirks`].
+    pub fn quirks(&self) -> Quirks {
+        self.quirks
+    }
+
+    /// Why the machine stopped, `None` while it runs. A faulted machine ignores [`Chip8::step`]
+    /// until a program or state is loaded, the program counter stays at the faulting instruction.
+    pub fn fault(&self) -> Option<Fault> {
+        self.fault
+    }
+
+    /// The whole 4KB of memory.
+    pub fn memory(&self) -> &[u8; 0x1000] {
+        &self.memory
+    }
+
+    /// Memory in `range`, `None` if it is not within 0x000-0xFFF.
+    pub fn memory_range(&self, range: Range<u16>) -> Option<&[u8]> {
+        self.memory
+            .get((range.start as usize)..(range.end as usize))
+    }
+
+    pub(crate) fn write_register(&mut self, register_index: u8, value: u8) {
         self.register[register_index as usize] = value;
     }
 
-    fn read_memory(&self, address: u16) -> u8 {
-        self.memory[address as usize]
+    /// Byte at `address`, addresses wrap around at 4KB.
+    pub fn read_memory(&self, address: u16) -> u8 {
+        self.memory[address as usize % self.memory.len()]
     }
 
-    fn read_memory_u16(&self, address: u16) -> u16 {
+    /// Big-endian word at `address`, like an opcode.
+    pub fn read_memory_u16(&self, address: u16) -> u16 {
         let hi = self.read_memory(address) as u16;
-        let low = self.read_memory(address + 1) as u16;
+        let low = self.read_memory(address.wrapping_add(1)) as u16;
         (hi << 8) | low
     }
 
-    fn write_memory(&mut self, address: u16, value: u8) {
-        self.memory[address as usize] = value;
+    pub(crate) fn write_memory(&mut self, address: u16, value: u8) {
+        let len = self.memory.len();
+        self.memory[address as usize % len] = value;
     }
 
+    #[allow(dead_code)]
     fn write_memory_u16(&mut self, address: u16, value: u16) {
         let hi = (value >> 8) as u8;
         let low = (value & 0xff) as u8;
         self.write_memory(address, hi);
-        self.write_memory(address + 1, low);
+        self.write_memory(address.wrapping_add(1), low);
     }
 
     fn read_keyboard(&self, keyboard_index: u8) -> bool {
@@ -115,441 +434,998 @@ impl Chip8 {
         self.display[64 * j_warped + i_warped] = next_value;
 
         // is erased?
-        !next_value
+        prev_value && !next_value
     }
 
-    pub fn load_program(&mut self, program: Vec<u8>) {
+    /// Loads the font at 0x000 and the program at 0x200, and starts execution at 0x200.
+    ///
+    /// Panics if the program does not fit into memory (0xE00 bytes).
+    pub fn load_program(&mut self, program: &[u8]) {
         // load fonts
-        self.memory[0..CHIP8_FONTS.len()].copy_from_slice(&CHIP8_FONTS[..]);
+        self.memory[0..CHIP8_FONTS.len()].copy_from_slice(&CHIP8_FONTS);
 
         // load program
-        self.memory[0x200..(0x200 + program.len())].copy_from_slice(&program[..]);
+        self.memory[0x200..(0x200 + program.len())].copy_from_slice(program);
         self.program_counter = 0x200;
+        self.fault = None;
+    }
+
+    /// Serializes the whole machine except the random source.
+    pub fn save_state(&self, state: &mut [u8; STATE_SIZE]) {
+        let mut writer = StateWriter { state, offset: 0 };
+        writer.write(&[STATE_VERSION]);
+        writer.write(&self.register);
+        writer.write(&self.index_register.to_be_bytes());
+        writer.write(&self.program_counter.to_be_bytes());
+        for value in self.stack.iter() {
+            writer.write(&value.to_be_bytes());
+        }
+        writer.write(&[self.stack_pointer, self.delay_timer, self.sound_timer]);
+        writer.write(&self.memory);
+        for pixel in self.display.iter() {
+            writer.write(&[*pixel as u8]);
+        }
+        for key in self.keyboard.iter() {
+            writer.write(&[*key as u8]);
+        }
+        let quirks = self.quirks.vf_reset as u8
+            | (self.quirks.shift_vx as u8) << 1
+            | (self.quirks.load_store_keep_i as u8) << 2
+            | (self.quirks.jump_vx as u8) << 3;
+        writer.write(&[quirks]);
     }
 
+    /// Restores a state saved by [`Chip8::save_state`], the machine is unchanged on error.
+    pub fn load_state(&mut self, state: &[u8]) -> Result<(), StateError> {
+        if state.len() != STATE_SIZE {
+            return Err(StateError::Size);
+        }
+        if state[0] != STATE_VERSION {
+            return Err(StateError::Version);
+        }
+
+        // NOTE: states come from outside, addresses are masked to 12 bits. instructions address
+        // memory modulo 4KB, so this does not change execution
+        let mut reader = StateReader { state, offset: 1 };
+        self.register.copy_from_slice(reader.read(16));
+        self.index_register = reader.read_u16() & 0xFFF;
+        self.program_counter = reader.read_u16() & 0xFFF;
+        for value in self.stack.iter_mut() {
+            *value = reader.read_u16() & 0xFFF;
+        }
+        // NOTE: stack pointer is clamped to stack size
+        self.stack_pointer = reader.read(1)[0].min(self.stack.len() as u8);
+        self.delay_timer = reader.read(1)[0];
+        self.sound_timer = reader.read(1)[0];
+        self.memory.copy_from_slice(reader.read(0x1000));
+        for (pixel, value) in self.display.iter_mut().zip(reader.read(64 * 32)) {
+            *pixel = *value != 0;
+        }
+        for (key, value) in self.keyboard.iter_mut().zip(reader.read(16)) {
+            *key = *value != 0;
+        }
+        let quirks = reader.read(1)[0];
+        self.quirks = Quirks {
+            vf_reset: quirks & 1 != 0,
+            shift_vx: quirks & 2 != 0,
+            load_store_keep_i: quirks & 4 != 0,
+            jump_vx: quirks & 8 != 0,
+        };
+        self.fault = None;
+        Ok(())
+    }
+
+    /// Executes forever, see [`Chip8::run_with_callback`].
+    #[cfg(feature = "std")]
     pub fn run(&mut self) {
         self.run_with_callback(|_| {});
     }
 
-    pub fn run_with_callback<F>(&mut self, mut callback: F)
+    /// Executes forever as fast as possible, calling `callback` before each instruction.
+    ///
+    /// Timers count down at 60hz of wall-clock time.
+    #[cfg(feature = "std")]
+    pub fn run_with_callback<F>(&mut self, callback: F)
     where
-        F: FnMut(&mut Chip8),
+        F: FnMut(&mut Chip8<R, B>),
     {
-        // initialize RNG
-        let mut rng = rand::thread_rng();
+        self.run_with_clock(&mut SystemClock::default(), callback);
+    }
+
+    /// Executes forever as fast as possible, calling `callback` before each instruction.
+    ///
+    /// Timers count down at 60hz of `clock` time.
+    pub fn run_with_clock<C, F>(&mut self, clock: &mut C, mut callback: F)
+    where
+        C: Clock,
+        F: FnMut(&mut Chip8<R, B>),
+    {
+        const TIMER_PERIOD_MICROS: u64 = 1_000_000 / 60;
 
         // NOTE: for processing timers
-        let mut delay_timer_counter = std::time::Instant::now();
-        let mut sound_timer_counter = std::time::Instant::now();
+        let mut delay_timer_counter = clock.now_micros();
+        let mut sound_timer_counter = clock.now_micros();
 
         loop {
             callback(self);
 
-            // fetch opcode
-            let program_index = self.program_counter;
-            let opcode = self.read_memory_u16(self.program_counter);
-            self.program_counter += 2;
+            self.step();
 
-            // process opcode
-            match opcode & 0xF000 {
-                0x0000 => match opcode {
-                    0x00E0 => {
-                        // CLS
-                        info!("{:X}, CLS", program_index);
+            let now = clock.now_micros();
 
-                        for v in self.display.iter_mut() {
-                            *v = false;
-                        }
-                    }
-                    0x00EE => {
-                        // RET
-                        info!("{:X}, RET", program_index);
+            // process delay timer
+            // NOTE: timer running at 60hz
+            if self.delay_timer == 0 {
+                delay_timer_counter = now;
+            }
 
-                        self.program_counter = self.pop();
-                    }
-                    _ => {
-                        todo!("SYS");
-                    }
-                },
-                0x1000 => {
-                    // JP addr
-                    let nnn = opcode & 0x0FFF;
-                    info!("{:X}: JP, {:X}", program_index, nnn);
+            if self.delay_timer > 0 && now - delay_timer_counter >= TIMER_PERIOD_MICROS {
+                self.delay_timer -= 1;
+                delay_timer_counter = now;
+            }
 
-                    self.program_counter = nnn;
+            // process sound timer
+            // NOTE: timer running at 60hz
+            if self.sound_timer == 0 {
+                sound_timer_counter = now;
+            }
+            if self.sound_timer > 0 {
+                // todo!("beep");
+                if now - sound_timer_counter >= TIMER_PERIOD_MICROS {
+                    self.sound_timer -= 1;
+                    sound_timer_counter = now;
                 }
-                0x2000 => {
-                    // CALL addr
-                    let nnn = opcode & 0x0FFF;
-                    info!("{:X}: CALL, {:X}", program_index, nnn);
+            }
+        }
+    }
 
-                    self.push(self.program_counter);
-                    self.program_counter = nnn;
+    /// Executes one 60hz frame without a frontend: `instructions_per_frame` instructions, then one
+    /// timer tick. Deterministic apart from `RND`.
+    ///
+    /// Stops early and returns the fault if the machine faults, see [`Chip8::fault`].
+    pub fn step_frame(&mut self, instructions_per_frame: usize) -> Result<(), Fault> {
+        for _ in 0..instructions_per_frame {
+            self.step();
+            if let Some(fault) = self.fault {
+                return Err(fault);
+            }
+        }
+        self.tick_timers();
+        Ok(())
+    }
+
+    /// Executes a single instruction. Timers are not updated.
+    ///
+    /// Does nothing once the machine faulted, see [`Chip8::fault`].
+    pub fn step(&mut self) {
+        if self.fault.is_some() {
+            return;
+        }
+
+        // fetch opcode
+        let program_index = self.program_counter;
+        let opcode = self.bus.fetch(&self.memory, self.program_counter % 0x1000);
+        // NOTE: wraps, the program counter may be set to any value by the tools
+        self.program_counter = self.program_counter.wrapping_add(2);
+
+        #[cfg(feature = "std")]
+        info!(
+            "{:03X}: {:04X} {}",
+            program_index,
+            opcode,
+            disasm::disassemble(opcode)
+        );
+        #[cfg(not(feature = "std"))]
+        info!("{:03X}: {:04X}", program_index, opcode);
+
+        if let Err(fault) = self.execute(opcode) {
+            self.program_counter = program_index;
+            self.fault = Some(fault);
+        }
+    }
+
+    // process opcode, the program counter already points at the next instruction
+    fn execute(&mut self, opcode: u16) -> Result<(), Fault> {
+        match opcode & 0xF000 {
+            0x0000 => match opcode {
+                0x00E0 => {
+                    // CLS
+                    for v in self.display.iter_mut() {
+                        *v = false;
+                    }
+                }
+                0x00EE => {
+                    // RET
+                    self.program_counter = self.pop()?;
+                }
+                _ => {
+                    // SYS addr
+                    // NOTE: machine code routines are not supported, ignored like most interpreters
+                }
+            },
+            0x1000 => {
+                // JP addr
+                let nnn = opcode & 0x0FFF;
+                self.program_counter = nnn;
+            }
+            0x2000 => {
+                // CALL addr
+                let nnn = opcode & 0x0FFF;
+                self.push(self.program_counter)?;
+                self.program_counter = nnn;
+            }
+            0x3000 => {
+                // SE Vx, byte
+                let x = ((opcode & 0x0F00) >> 8) as u8;
+                let kk = (opcode & 0x00FF) as u8;
+                if self.read_register(x) == kk {
+                    self.program_counter = self.program_counter.wrapping_add(2);
+                }
+            }
+            0x4000 => {
+                // SNE Vx, byte
+                let x = ((opcode & 0x0F00) >> 8) as u8;
+                let kk = (opcode & 0x00FF) as u8;
+                if self.read_register(x) != kk {
+                    self.program_counter = self.program_counter.wrapping_add(2);
                 }
-                0x3000 => {
-                    // SE Vx, byte
+            }
+            0x5000 => {
+                match opcode & 0xF00F {
+                    0x5000 => {
+                        // SE Vx, Vy
+                        let x = ((opcode & 0x0F00) >> 8) as u8;
+                        let y = ((opcode & 0x00F0) >> 4) as u8;
+                        if self.read_register(x) == self.read_register(y) {
+                            self.program_counter = self.program_counter.wrapping_add(2);
+                        }
+                    }
+                    _ => return Err(Fault::UnknownOpcode(opcode)),
+                }
+            }
+            0x6000 => {
+                // LD Vx, byte
+                let x = ((opcode & 0x0F00) >> 8) as u8;
+                let kk = (opcode & 0x00FF) as u8;
+                self.write_register(x, kk);
+            }
+            0x7000 => {
+                // ADD Vx, byte
+                let x = ((opcode & 0x0F00) >> 8) as u8;
+                let kk = (opcode & 0x00FF) as u8;
+                self.write_register(x, self.read_register(x).wrapping_add(kk));
+            }
+            0x8000 => match opcode & 0xF00F {
+                0x8000 => {
+                    // LD Vx, Vy
                     let x = ((opcode & 0x0F00) >> 8) as u8;
-                    let kk = (opcode & 0x00FF) as u8;
-                    info!("{:X}: SE, V{} {:X}", program_index, x, kk);
-
-                    if self.read_register(x) == kk {
-                        self.program_counter += 2;
+                    let y = ((opcode & 0x00F0) >> 4) as u8;
+                    self.write_register(x, self.read_register(y));
+                }
+                0x8001 => {
+                    // OR Vx, Vy
+                    let x = ((opcode & 0x0F00) >> 8) as u8;
+                    let y = ((opcode & 0x00F0) >> 4) as u8;
+                    self.write_register(x, self.read_register(x) | self.read_register(y));
+                    if self.quirks.vf_reset {
+                        self.write_register(0xF, 0);
                     }
                 }
-                0x4000 => {
-                    // SNE Vx, byte
+                0x8002 => {
+                    // AND Vx, Vy
                     let x = ((opcode & 0x0F00) >> 8) as u8;
-                    let kk = (opcode & 0x00FF) as u8;
-                    info!("{:X}: SNE, V{} {:X}", program_index, x, kk);
-
-                    if self.read_register(x) != kk {
-                        self.program_counter += 2;
+                    let y = ((opcode & 0x00F0) >> 4) as u8;
+                    self.write_register(x, self.read_register(x) & self.read_register(y));
+                    if self.quirks.vf_reset {
+                        self.write_register(0xF, 0);
                     }
                 }
-                0x5000 => {
-                    match opcode & 0xF00F {
-                        0x5000 => {
-                            // SE Vx, Vy
-                            let x = ((opcode & 0x0F00) >> 8) as u8;
-                            let y = ((opcode & 0x00F0) >> 4) as u8;
-                            info!("{:X}: SE, V{} V{}", program_index, x, y);
-
-                            if self.read_register(x) == self.read_register(y) {
-                                self.program_counter += 2;
-                            }
-                        }
-                        _ => panic!("unknown opcode: {:X}", opcode),
+                0x8003 => {
+                    // XOR Vx, Vy
+                    let x = ((opcode & 0x0F00) >> 8) as u8;
+                    let y = ((opcode & 0x00F0) >> 4) as u8;
+                    self.write_register(x, self.read_register(x) ^ self.read_register(y));
+                    if self.quirks.vf_reset {
+                        self.write_register(0xF, 0);
                     }
                 }
-                0x6000 => {
-                    // LD Vx, byte
+                0x8004 => {
+                    // ADD Vx, Vy
                     let x = ((opcode & 0x0F00) >> 8) as u8;
-                    let kk = (opcode & 0x00FF) as u8;
-                    info!("{:X}: LD, V{}, {:X}", program_index, x, kk);
-
-                    self.write_register(x, kk);
+                    let y = ((opcode & 0x00F0) >> 4) as u8;
+                    let (result, carry) =
+                        self.read_register(x).overflowing_add(self.read_register(y));
+                    // NOTE: VF is written last, so it holds the flag when x is F
+                    self.write_register(x, result);
+                    self.write_register(0xF, if carry { 1 } else { 0 });
                 }
-                0x7000 => {
-                    // ADD Vx, byte
+                0x8005 => {
+                    // SUB Vx, Vy
                     let x = ((opcode & 0x0F00) >> 8) as u8;
-                    let kk = (opcode & 0x00FF) as u8;
-                    info!("{:X}: ADD, V{}, {:X}", program_index, x, kk);
-
-                    self.write_register(x, self.read_register(x).wrapping_add(kk));
+                    let y = ((opcode & 0x00F0) >> 4) as u8;
+                    let vx = self.read_register(x);
+                    let vy = self.read_register(y);
+                    // VF is NOT borrow
+                    self.write_register(x, vx.wrapping_sub(vy));
+                    self.write_register(0xF, if vx >= vy { 1 } else { 0 });
+                }
+                0x8006 => {
+                    // SHR Vx, Vy
+                    let x = ((opcode & 0x0F00) >> 8) as u8;
+                    let y = ((opcode & 0x00F0) >> 4) as u8;
+                    let source = if self.quirks.shift_vx { x } else { y };
+                    let value = self.read_register(source);
+                    self.write_register(x, value >> 1);
+                    self.write_register(0xF, value & 0b0000_0001);
                 }
-                0x8000 => match opcode & 0xF00F {
-                    0x8000 => {
-                        // LD Vx, Vy
+                0x8007 => {
+                    // SUBN Vx, Vy
+                    let x = ((opcode & 0x0F00) >> 8) as u8;
+                    let y = ((opcode & 0x00F0) >> 4) as u8;
+                    let vx = self.read_register(x);
+                    let vy = self.read_register(y);
+                    self.write_register(x, vy.wrapping_sub(vx));
+                    self.write_register(0xF, if vy >= vx { 1 } else { 0 });
+                }
+                0x800E => {
+                    // SHL Vx, Vy
+                    let x = ((opcode & 0x0F00) >> 8) as u8;
+                    let y = ((opcode & 0x00F0) >> 4) as u8;
+                    let source = if self.quirks.shift_vx { x } else { y };
+                    let value = self.read_register(source);
+                    self.write_register(x, value << 1);
+                    self.write_register(0xF, value >> 7);
+                }
+                _ => return Err(Fault::UnknownOpcode(opcode)),
+            },
+            0x9000 => {
+                match opcode & 0xF00F {
+                    0x9000 => {
+                        // SNE Vx, Vy
                         let x = ((opcode & 0x0F00) >> 8) as u8;
                         let y = ((opcode & 0x00F0) >> 4) as u8;
-                        info!("{:X}: LD, V{}, V{}", program_index, x, y);
-
-                        self.write_register(x, self.read_register(y));
+                        if self.read_register(x) != self.read_register(y) {
+                            self.program_counter = self.program_counter.wrapping_add(2);
+                        }
                     }
-                    0x8001 => {
-                        // OR Vx, Vy
-                        let x = ((opcode & 0x0F00) >> 8) as u8;
-                        let y = ((opcode & 0x00F0) >> 4) as u8;
-                        info!("{:X}: OR, V{}, V{}", program_index, x, y);
-
-                        self.write_register(x, self.read_register(x) | self.read_register(y));
+                    _ => return Err(Fault::UnknownOpcode(opcode)),
+                }
+            }
+            0xA000 => {
+                // LD I, addr
+                let nnn = opcode & 0x0FFF;
+                self.index_register = nnn;
+            }
+            0xB000 => {
+                // JP V0, addr
+                let nnn = opcode & 0x0FFF;
+                let x = if self.quirks.jump_vx {
+                    ((opcode & 0x0F00) >> 8) as u8
+                } else {
+                    0
+                };
+                self.program_counter = (self.read_register(x) as u16) + nnn;
+            }
+            0xC000 => {
+                // RND Vx, byte
+                let x = ((opcode & 0x0F00) >> 8) as u8;
+                let kk = (opcode & 0x00FF) as u8;
+                let rnd = self.random.random_byte();
+                self.write_register(x, rnd & kk);
+            }
+            0xD000 => {
+                // DRW Vx, Vy, nibble
+                let x = ((opcode & 0x0F00) >> 8) as u8;
+                let y = ((opcode & 0x00F0) >> 4) as u8;
+                let n = (opcode & 0x000F) as u8;
+                let vx = self.read_register(x);
+                let vy = self.read_register(y);
+
+                // draw
+                // NOTE: coordinates wrap around the screen
+                let mut erased = false;
+                for i in 0..n {
+                    let v = self.bus_read(self.index_register.wrapping_add(i as u16));
+                    let row = vy.wrapping_add(i);
+                    for bit in 0..8 {
+                        let column = vx.wrapping_add(bit);
+                        erased |= self.write_display(column, row, (v & (0b1000_0000 >> bit)) != 0);
                     }
-                    0x8002 => {
-                        // AND Vx, Vy
-                        let x = ((opcode & 0x0F00) >> 8) as u8;
-                        let y = ((opcode & 0x00F0) >> 4) as u8;
-                        info!("{:X}: AND, V{}, V{}", program_index, x, y);
+                }
 
-                        self.write_register(x, self.read_register(x) & self.read_register(y));
+                // set VF
+                self.write_register(0xF, if erased { 1 } else { 0 });
+            }
+            0xE000 => match opcode & 0xF0FF {
+                0xE09E => {
+                    // SKP Vx
+                    let x = ((opcode & 0x0F00) >> 8) as u8;
+                    let vx = self.read_register(x);
+                    if self.read_keyboard(vx) {
+                        self.program_counter = self.program_counter.wrapping_add(2);
                     }
-                    0x8003 => {
-                        // XOR Vx, Vy
-                        let x = ((opcode & 0x0F00) >> 8) as u8;
-                        let y = ((opcode & 0x00F0) >> 4) as u8;
-                        info!("{:X}: XOR, V{}, V{}", program_index, x, y);
-
-                        self.write_register(x, self.read_register(x) ^ self.read_register(y));
+                }
+                0xE0A1 => {
+                    // SKNP Vx
+                    let x = ((opcode & 0x0F00) >> 8) as u8;
+                    let vx = self.read_register(x);
+                    if !self.read_keyboard(vx) {
+                        self.program_counter = self.program_counter.wrapping_add(2);
+                    }
+                }
+                _ => return Err(Fault::UnknownOpcode(opcode)),
+            },
+            0xF000 => match opcode & 0xF0FF {
+                0xF007 => {
+                    // LD Vx, DT
+                    let x = ((opcode & 0x0F00) >> 8) as u8;
+                    self.write_register(x, self.delay_timer);
+                }
+                0xF00A => {
+                    // LD Vx, K
+                    let x = ((opcode & 0x0F00) >> 8) as u8;
+                    let mut key_index = 0;
+                    let mut key_pressed = false;
+                    for (index, value) in self.keyboard.iter().enumerate() {
+                        if *value {
+                            key_index = index;
+                            key_pressed = true;
+                        }
                     }
-                    0x8004 => {
-                        // ADD Vx, Vy
-                        let x = ((opcode & 0x0F00) >> 8) as u8;
-                        let y = ((opcode & 0x00F0) >> 4) as u8;
-                        info!("{:X}: ADD, V{}, V{}", program_index, x, y);
 
-                        self.write_register(
-                            x,
-                            self.read_register(x).wrapping_add(self.read_register(y)),
-                        );
+                    if key_pressed {
+                        self.write_register(x, key_index as u8);
+                    } else {
+                        // wait until any key pressed
+                        // NOTE: since keyboard input is handled outside this struct, we simulate waiting behavior by moving program counter back
+                        self.program_counter = self.program_counter.wrapping_sub(2);
                     }
-                    0x8005 => {
-                        // SUB Vx, Vy
-                        let x = ((opcode & 0x0F00) >> 8) as u8;
-                        let y = ((opcode & 0x00F0) >> 4) as u8;
-                        info!("{:X}: SUB, V{}, V{}", program_index, x, y);
+                }
+                0xF015 => {
+                    // LD DT, Vx
+                    let x = ((opcode & 0x0F00) >> 8) as u8;
+                    self.delay_timer = self.read_register(x);
+                }
+                0xF018 => {
+                    // LD ST, Vx
+                    let x = ((opcode & 0x0F00) >> 8) as u8;
+                    self.sound_timer = self.read_register(x);
+                }
+                0xF01E => {
+                    // ADD I, Vx
+                    let x = ((opcode & 0x0F00) >> 8) as u8;
+                    self.index_register = self
+                        .index_register
+                        .wrapping_add(self.read_register(x) as u16);
+                }
+                0xF029 => {
+                    let x = ((opcode & 0x0F00) >> 8) as u8;
+                    let vx = self.read_register(x) as u16;
+                    self.index_register = 5 * vx;
+                }
+                0xF033 => {
+                    // LD B, Vx
+                    let x = ((opcode & 0x0F00) >> 8) as u8;
+                    let vx = self.read_register(x);
+                    let hundred = (vx / 100) % 10;
+                    let ten = (vx / 10) % 10;
+                    let one = vx % 10;
 
-                        self.write_register(
-                            x,
-                            self.read_register(x).wrapping_sub(self.read_register(y)),
+                    self.bus_write(self.index_register, hundred);
+                    self.bus_write(self.index_register.wrapping_add(1), ten);
+                    self.bus_write(self.index_register.wrapping_add(2), one);
+                }
+                0xF055 => {
+                    // LD [I], Vx
+                    let x = ((opcode & 0x0F00) >> 8) as u8;
+                    // set register values on memory
+                    for i in 0..(x + 1) {
+                        self.bus_write(
+                            self.index_register.wrapping_add(i as u16),
+                            self.read_register(i),
                         );
                     }
-                    0x8006 => {
-                        // SHR Vx, Vy
-                        let x = ((opcode & 0x0F00) >> 8) as u8;
-                        let y = ((opcode & 0x00F0) >> 4) as u8;
-                        info!("{:X}: SHR, V{}, V{}", program_index, x, y);
 
-                        self.write_register(0xF, x & 0b0000_0001);
-                        self.write_register(x, self.read_register(y) >> 1);
+                    // set IP
+                    if !self.quirks.load_store_keep_i {
+                        self.index_register = self.index_register.wrapping_add((x + 1) as u16);
                     }
-                    0x8007 => {
-                        // SUBN Vx, Vy
-                        let x = ((opcode & 0x0F00) >> 8) as u8;
-                        let y = ((opcode & 0x00F0) >> 4) as u8;
-                        let vx = self.read_register(x);
-                        let vy = self.read_register(y);
-                        info!("{:X}: SUBN, V{}, V{}", program_index, x, y);
-
-                        self.write_register(0xF, if vy > vx { 1 } else { 0 });
-                        self.write_register(x, vy.wrapping_sub(vx));
+                }
+                0xF065 => {
+                    // LD Vx, [I]
+                    let x = ((opcode & 0x0F00) >> 8) as u8;
+                    // load values into registers
+                    for i in 0..(x + 1) {
+                        let value = self.bus_read(self.index_register.wrapping_add(i as u16));
+                        self.write_register(i, value);
                     }
-                    0x800E => {
-                        // SHL Vx, Vy
-                        let x = ((opcode & 0x0F00) >> 8) as u8;
-                        let y = ((opcode & 0x00F0) >> 4) as u8;
-                        info!("{:X}: SHL, V{}, V{}", program_index, x, y);
 
-                        self.write_register(0xF, x & 0b1000_0000);
-                        self.write_register(x, self.read_register(y) << 1);
-                    }
-                    _ => panic!("unknown opcode: {:X}", opcode),
-                },
-                0x9000 => {
-                    match opcode & 0xF00F {
-                        0x9000 => {
-                            // SNE Vx, Vy
-                            let x = ((opcode & 0x0F00) >> 8) as u8;
-                            let y = ((opcode & 0x00F0) >> 4) as u8;
-                            info!("{:X}: SNE, V{}, V{}", program_index, x, y);
-
-                            if self.read_register(x) != self.read_register(y) {
-                                self.program_counter += 2;
-                            }
-                        }
-                        _ => panic!("unknown opcode: {:X}", opcode),
+                    // set IP
+                    if !self.quirks.load_store_keep_i {
+                        self.index_register = self.index_register.wrapping_add((x + 1) as u16);
                     }
                 }
-                0xA000 => {
-                    // LD I, addr
-                    let nnn = opcode & 0x0FFF;
-                    info!("{:X}: LD, I, {:X}", program_index, nnn);
+                _ => return Err(Fault::UnknownOpcode(opcode)),
+            },
+            _ => return Err(Fault::UnknownOpcode(opcode)),
+        }
+        Ok(())
+    }
+}
 
-                    self.index_register = nnn;
-                }
-                0xB000 => {
-                    // JP V0, addr
-                    let nnn = opcode & 0x0FFF;
-                    info!("{:X}: JP, V0, {:X}", program_index, nnn);
+struct StateWriter<'a> {
+    state: &'a mut [u8; STATE_SIZE],
+    offset: usize,
+}
 
-                    self.program_counter = (self.read_register(0) as u16) + nnn;
-                }
-                0xC000 => {
-                    // RND Vx, byte
-                    let x = ((opcode & 0x0F00) >> 8) as u8;
-                    let kk = (opcode & 0x00FF) as u8;
-                    info!("{:X}: RND, V{}, {:X}", program_index, x, kk);
+impl StateWriter<'_> {
+    fn write(&mut self, data: &[u8]) {
+        self.state[self.offset..(self.offset + data.len())].copy_from_slice(data);
+        self.offset += data.len();
+    }
+}
 
-                    let rnd: u8 = rng.gen_range(0..255);
-                    self.write_register(x, rnd & kk);
-                }
-                0xD000 => {
-                    // DRW Vx, Vy, nibble
-                    let x = ((opcode & 0x0F00) >> 8) as u8;
-                    let y = ((opcode & 0x00F0) >> 4) as u8;
-                    let n = (opcode & 0x000F) as u8;
-                    info!("{:X}: DRW, V{}, V{}, {:b}", program_index, x, y, n);
+struct StateReader<'a> {
+    state: &'a [u8],
+    offset: usize,
+}
 
-                    let vx = self.read_register(x);
-                    let vy = self.read_register(y);
+impl<'a> StateReader<'a> {
+    fn read(&mut self, len: usize) -> &'a [u8] {
+        let data = &self.state[self.offset..(self.offset + len)];
+        self.offset += len;
+        data
+    }
 
-                    // draw
-                    let mut erased = false;
-                    for i in 0..n {
-                        let v = self.read_memory(self.index_register + (i as u16));
-                        erased |= self.write_display(vx, vy + i, (v & 0b1000_0000) != 0);
-                        erased |= self.write_display(vx + 1, vy + i, (v & 0b0100_0000) != 0);
-                        erased |= self.write_display(vx + 2, vy + i, (v & 0b0010_0000) != 0);
-                        erased |= self.write_display(vx + 3, vy + i, (v & 0b0001_0000) != 0);
-                        erased |= self.write_display(vx + 4, vy + i, (v & 0b0000_1000) != 0);
-                        erased |= self.write_display(vx + 5, vy + i, (v & 0b0000_0100) != 0);
-                        erased |= self.write_display(vx + 6, vy + i, (v & 0b0000_0010) != 0);
-                        erased |= self.write_display(vx + 7, vy + i, (v & 0b0000_0001) != 0);
-                    }
+    fn read_u16(&mut self) -> u16 {
+        let data = self.read(2);
+        u16::from_be_bytes([data[0], data[1]])
+    }
+}
 
-                    // set VF
-                    self.write_register(0xF, if erased { 1 } else { 0 });
-                }
-                0xE000 => match opcode & 0xF0FF {
-                    0xE09E => {
-                        // SKP Vx
-                        let x = ((opcode & 0x0F00) >> 8) as u8;
-                        info!("{:X}: SKP, V{}", program_index, x);
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::host::XorShiftRandom;
+
+    // load opcodes at 0x200
+    fn load(opcodes: &[u16]) -> Chip8 {
+        let program: Vec<u8> = opcodes.iter().flat_map(|op| op.to_be_bytes()).collect();
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&program);
+        chip8
+    }
 
-                        let vx = self.read_register(x);
-                        if self.read_keyboard(vx) {
-                            self.program_counter += 2;
-                        }
-                    }
-                    0xE0A1 => {
-                        // SKNP Vx
-                        let x = ((opcode & 0x0F00) >> 8) as u8;
-                        info!("{:X}: SKNP, V{}", program_index, x);
+    // execute a single opcode with registers preset as (index, value)
+    fn exec(opcode: u16, registers: &[(u8, u8)]) -> Chip8 {
+        let mut chip8 = load(&[opcode]);
+        for &(x, value) in registers {
+            chip8.write_register(x, value);
+        }
+        chip8.step();
+        chip8
+    }
 
-                        let vx = self.read_register(x);
-                        if !self.read_keyboard(vx) {
-                            self.program_counter += 2;
-                        }
-                    }
-                    _ => panic!("unknown opcode: {:X}", opcode),
-                },
-                0xF000 => match opcode & 0xF0FF {
-                    0xF007 => {
-                        // LD Vx, DT
-                        let x = ((opcode & 0x0F00) >> 8) as u8;
-                        info!("{:X}: LD, V{}, DT", program_index, x);
+    #[test]
+    fn cls() {
+        let mut chip8 = load(&[0x00E0]);
+        chip8.display[100] = true;
+        chip8.step();
+        assert!(chip8.display.iter().all(|v| !v));
+    }
 
-                        self.write_register(x, self.delay_timer);
-                    }
-                    0xF00A => {
-                        // LD Vx, K
-                        let x = ((opcode & 0x0F00) >> 8) as u8;
-                        info!("{:X}: LD, V{}, K", program_index, x);
-
-                        let mut key_index = 0;
-                        let mut key_pressed = false;
-                        for (index, value) in self.keyboard.iter().enumerate() {
-                            if *value {
-                                key_index = index;
-                                key_pressed = true;
-                            }
-                        }
+    #[test]
+    fn sys_is_ignored() {
+        let chip8 = exec(0x0123, &[]);
+        assert_eq!(chip8.program_counter(), 0x202);
+    }
 
-                        if key_pressed {
-                            self.write_register(x, key_index as u8);
-                        } else {
-                            // wait until any key pressed
-                            // NOTE: since keyboard input is handled outside this struct, we simulate waiting behavior by moving program counter back
-                            self.program_counter -= 2;
-                        }
-                    }
-                    0xF015 => {
-                        // LD DT, Vx
-                        let x = ((opcode & 0x0F00) >> 8) as u8;
-                        info!("{:X}: LD, DT, V{}", program_index, x);
+    #[test]
+    fn jp_call_ret() {
+        let mut chip8 = load(&[0x2206, 0x0000, 0x0000, 0x00EE]);
+        chip8.step();
+        assert_eq!(chip8.program_counter(), 0x206);
+        assert_eq!(chip8.stack(), &[0x202]);
+        chip8.step();
+        assert_eq!(chip8.program_counter(), 0x202);
+        assert_eq!(chip8.stack_pointer(), 0);
+
+        let chip8 = exec(0x1ABC, &[]);
+        assert_eq!(chip8.program_counter(), 0xABC);
+    }
 
-                        self.delay_timer = self.read_register(x);
-                    }
-                    0xF018 => {
-                        // LD ST, Vx
-                        let x = ((opcode & 0x0F00) >> 8) as u8;
-                        info!("{:X}: LD, ST, V{}", program_index, x);
+    #[test]
+    fn faults() {
+        // LD V0, 1; invalid
+        let mut chip8 = load(&[0x6001, 0xFFFF]);
+        assert_eq!(chip8.step_frame(10), Err(Fault::UnknownOpcode(0xFFFF)));
+        assert_eq!(chip8.fault(), Some(Fault::UnknownOpcode(0xFFFF)));
+        assert_eq!(chip8.program_counter(), 0x202);
+        assert_eq!(chip8.read_register(0), 1);
+        // halted until reloaded
+        chip8.step();
+        assert_eq!(chip8.program_counter(), 0x202);
+        chip8.load_program(&[0x60, 0x02]);
+        assert_eq!(chip8.fault(), None);
+        assert_eq!(chip8.step_frame(1), Ok(()));
+
+        let mut chip8 = load(&[0x00EE]);
+        chip8.step();
+        assert_eq!(chip8.fault(), Some(Fault::StackUnderflow));
+        assert_eq!(chip8.stack_pointer(), 0);
+
+        // CALL 0x200 recursing 17 levels
+        let mut chip8 = load(&[0x2200]);
+        for _ in 0..17 {
+            chip8.step();
+        }
+        assert_eq!(chip8.fault(), Some(Fault::StackOverflow));
+        assert_eq!(chip8.stack_pointer(), 16);
+        assert_eq!(chip8.program_counter(), 0x200);
+    }
 
-                        self.sound_timer = self.read_register(x);
-                    }
-                    0xF01E => {
-                        // ADD I, Vx
-                        let x = ((opcode & 0x0F00) >> 8) as u8;
-                        info!(
-                            "{:X}: ADD, {:X}, V{}",
-                            program_index, self.index_register, x
-                        );
+    #[test]
+    fn skips() {
+        assert_eq!(exec(0x3142, &[(1, 0x42)]).program_counter(), 0x204);
+        assert_eq!(exec(0x3142, &[(1, 0x41)]).program_counter(), 0x202);
+        assert_eq!(exec(0x4142, &[(1, 0x42)]).program_counter(), 0x202);
+        assert_eq!(exec(0x4142, &[(1, 0x41)]).program_counter(), 0x204);
+        assert_eq!(exec(0x5120, &[(1, 7), (2, 7)]).program_counter(), 0x204);
+        assert_eq!(exec(0x5120, &[(1, 7), (2, 8)]).program_counter(), 0x202);
+        assert_eq!(exec(0x9120, &[(1, 7), (2, 7)]).program_counter(), 0x202);
+        assert_eq!(exec(0x9120, &[(1, 7), (2, 8)]).program_counter(), 0x204);
+    }
 
-                        self.index_register += self.read_register(x) as u16;
-                    }
-                    0xF029 => {
-                        let x = ((opcode & 0x0F00) >> 8) as u8;
-                        info!("{:X}: LD, F, V{}", program_index, x);
+    #[test]
+    fn ld_add_byte() {
+        assert_eq!(exec(0x6A42, &[]).read_register(0xA), 0x42);
+        // ADD Vx, byte wraps and leaves VF alone
+        let chip8 = exec(0x7102, &[(1, 0xFF), (0xF, 0x55)]);
+        assert_eq!(chip8.read_register(1), 0x01);
+        assert_eq!(chip8.read_register(0xF), 0x55);
+    }
 
-                        let vx = self.read_register(x) as u16;
-                        self.index_register = 5 * vx;
-                    }
-                    0xF033 => {
-                        // LD B, Vx
-                        let x = ((opcode & 0x0F00) >> 8) as u8;
-                        info!("{:X}: LD, B, V{}", program_index, x);
+    #[test]
+    fn logic() {
+        assert_eq!(exec(0x8120, &[(2, 0x33)]).read_register(1), 0x33);
+        assert_eq!(exec(0x8121, &[(1, 0x0F), (2, 0xF0)]).read_register(1), 0xFF);
+        assert_eq!(exec(0x8122, &[(1, 0x3C), (2, 0x0F)]).read_register(1), 0x0C);
+        assert_eq!(exec(0x8123, &[(1, 0xFF), (2, 0x0F)]).read_register(1), 0xF0);
+    }
 
-                        let vx = self.read_register(x);
-                        let hundred = (vx / 100) % 10;
-                        let ten = (vx / 10) % 10;
-                        let one = vx % 10;
+    #[test]
+    fn add_reg() {
+        let chip8 = exec(0x8124, &[(1, 0xF0), (2, 0x20)]);
+        assert_eq!(chip8.read_register(1), 0x10);
+        assert_eq!(chip8.read_register(0xF), 1);
+
+        let chip8 = exec(0x8124, &[(1, 0x10), (2, 0x20), (0xF, 1)]);
+        assert_eq!(chip8.read_register(1), 0x30);
+        assert_eq!(chip8.read_register(0xF), 0);
+
+        // VF as operand, flag wins
+        let chip8 = exec(0x8F14, &[(1, 0x01), (0xF, 0xFF)]);
+        assert_eq!(chip8.read_register(0xF), 1);
+        let chip8 = exec(0x81F4, &[(1, 0x01), (0xF, 0x02)]);
+        assert_eq!(chip8.read_register(1), 0x03);
+        assert_eq!(chip8.read_register(0xF), 0);
+    }
 
-                        self.write_memory(self.index_register, hundred);
-                        self.write_memory(self.index_register + 1, ten);
-                        self.write_memory(self.index_register + 2, one);
-                    }
-                    0xF055 => {
-                        // LD [I], Vx
-                        let x = ((opcode & 0x0F00) >> 8) as u8;
-                        info!("{:X}: LD, [I], V{}", program_index, x);
-
-                        // set register values on memory
-                        for i in 0..(x + 1) {
-                            self.write_memory(
-                                self.index_register + (i as u16),
-                                self.read_register(i),
-                            );
-                        }
+    #[test]
+    fn sub() {
+        let chip8 = exec(0x8125, &[(1, 0x30), (2, 0x10)]);
+        assert_eq!(chip8.read_register(1), 0x20);
+        assert_eq!(chip8.read_register(0xF), 1);
 
-                        // set IP
-                        self.index_register += (x + 1) as u16;
-                    }
-                    0xF065 => {
-                        // LD Vx, [I]
-                        let x = ((opcode & 0x0F00) >> 8) as u8;
-                        info!("{:X}: LD, V{}, [I]", program_index, x);
-
-                        // load values into registers
-                        for i in 0..(x + 1) {
-                            self.write_register(
-                                i,
-                                self.read_memory(self.index_register + (i as u16)),
-                            );
-                        }
+        // equal operands do not borrow
+        let chip8 = exec(0x8125, &[(1, 0x10), (2, 0x10)]);
+        assert_eq!(chip8.read_register(1), 0x00);
+        assert_eq!(chip8.read_register(0xF), 1);
 
-                        // set IP
-                        self.index_register += (x + 1) as u16;
-                    }
-                    _ => panic!("unknown opcode: {:X}", opcode),
-                },
-                _ => panic!("unknown opcode: {:X}", opcode),
-            }
+        let chip8 = exec(0x8125, &[(1, 0x10), (2, 0x30)]);
+        assert_eq!(chip8.read_register(1), 0xE0);
+        assert_eq!(chip8.read_register(0xF), 0);
 
-            // process delay timer
-            // NOTE: timer running at 60hz
-            if self.delay_timer == 0 {
-                delay_timer_counter = std::time::Instant::now();
-            }
+        let chip8 = exec(0x8F15, &[(1, 0x01), (0xF, 0x00)]);
+        assert_eq!(chip8.read_register(0xF), 0);
+    }
 
-            if self.delay_timer > 0
-                && delay_timer_counter.elapsed() >= std::time::Duration::from_secs_f32(1.0 / 60.0)
-            {
-                self.delay_timer -= 1;
-                delay_timer_counter = std::time::Instant::now();
-            }
+    #[test]
+    fn subn() {
+        let chip8 = exec(0x8127, &[(1, 0x10), (2, 0x30)]);
+        assert_eq!(chip8.read_register(1), 0x20);
+        assert_eq!(chip8.read_register(0xF), 1);
 
-            // process sound timer
-            // NOTE: timer running at 60hz
-            if self.sound_timer == 0 {
-                sound_timer_counter = std::time::Instant::now();
-            }
-            if self.sound_timer > 0 {
-                // todo!("beep");
-                if sound_timer_counter.elapsed() >= std::time::Duration::from_secs_f32(1.0 / 60.0) {
-                    self.sound_timer -= 1;
-                    sound_timer_counter = std::time::Instant::now();
-                }
-            }
+        let chip8 = exec(0x8127, &[(1, 0x10), (2, 0x10)]);
+        assert_eq!(chip8.read_register(1), 0x00);
+        assert_eq!(chip8.read_register(0xF), 1);
 
-            // std::thread::sleep(std::time::Duration::from_millis(10));
+        let chip8 = exec(0x8127, &[(1, 0x30), (2, 0x10)]);
+        assert_eq!(chip8.read_register(1), 0xE0);
+        assert_eq!(chip8.read_register(0xF), 0);
+
+        let chip8 = exec(0x8F17, &[(1, 0x05), (0xF, 0x03)]);
+        assert_eq!(chip8.read_register(0xF), 1);
+    }
+
+    #[test]
+    fn shr() {
+        // shifts Vy into Vx, VF is the bit shifted out of the value
+        let chip8 = exec(0x8126, &[(1, 0x00), (2, 0x05)]);
+        assert_eq!(chip8.read_register(1), 0x02);
+        assert_eq!(chip8.read_register(0xF), 1);
+
+        let chip8 = exec(0x8126, &[(1, 0x01), (2, 0x04)]);
+        assert_eq!(chip8.read_register(1), 0x02);
+        assert_eq!(chip8.read_register(0xF), 0);
+
+        // VF as destination, flag wins
+        let chip8 = exec(0x8F26, &[(2, 0x04)]);
+        assert_eq!(chip8.read_register(0xF), 0);
+        let chip8 = exec(0x8FF6, &[(0xF, 0x03)]);
+        assert_eq!(chip8.read_register(0xF), 1);
+    }
+
+    #[test]
+    fn shl() {
+        let chip8 = exec(0x812E, &[(2, 0x81)]);
+        assert_eq!(chip8.read_register(1), 0x02);
+        assert_eq!(chip8.read_register(0xF), 1);
+
+        let chip8 = exec(0x812E, &[(1, 0x80), (2, 0x41)]);
+        assert_eq!(chip8.read_register(1), 0x82);
+        assert_eq!(chip8.read_register(0xF), 0);
+
+        let chip8 = exec(0x8F2E, &[(2, 0x40)]);
+        assert_eq!(chip8.read_register(0xF), 0);
+        let chip8 = exec(0x8FFE, &[(0xF, 0x80)]);
+        assert_eq!(chip8.read_register(0xF), 1);
+    }
+
+    #[test]
+    fn ld_i_and_jp_v0() {
+        assert_eq!(exec(0xA123, &[]).index_register(), 0x123);
+        assert_eq!(exec(0xB300, &[(0, 0x42)]).program_counter(), 0x342);
+    }
+
+    #[test]
+    fn rnd_masks_value() {
+        for _ in 0..32 {
+            let chip8 = exec(0xC10F, &[]);
+            assert_eq!(chip8.read_register(1) & 0xF0, 0);
+        }
+        // every value is possible
+        let mut chip8 = load(&[0xC1FF, 0x1200]);
+        let mut seen = [false; 256];
+        for _ in 0..10000 {
+            chip8.step();
+            chip8.step();
+            seen[chip8.read_register(1) as usize] = true;
         }
+        assert!(seen[255]);
+    }
+
+    #[test]
+    fn rnd_uses_random_source() {
+        let run = |seed| {
+            let mut chip8 = Chip8::with_random(XorShiftRandom::new(seed));
+            chip8.load_program(&[0xC1, 0xFF, 0xC2, 0xFF]);
+            chip8.step();
+            chip8.step();
+            [chip8.read_register(1), chip8.read_register(2)]
+        };
+        let mut random = XorShiftRandom::new(7);
+        assert_eq!(run(7), [random.random_byte(), random.random_byte()]);
+    }
+
+    #[test]
+    fn drw() {
+        // draw font "0" at (2, 3)
+        let mut chip8 = load(&[0xD125, 0xD125]);
+        chip8.write_register(1, 2);
+        chip8.write_register(2, 3);
+        chip8.step();
+        assert!(chip8.display[64 * 3 + 2]);
+        assert!(chip8.display[64 * 3 + 5]);
+        assert!(!chip8.display[64 * 4 + 3]);
+        assert!(chip8.display[64 * 7 + 5]);
+        assert_eq!(chip8.read_register(0xF), 0);
+
+        // drawing again erases the sprite
+        chip8.step();
+        assert!(chip8.display.iter().all(|v| !v));
+        assert_eq!(chip8.read_register(0xF), 1);
+    }
+
+    #[test]
+    fn drw_unset_pixels_do_not_collide() {
+        // a sprite with blank pixels drawn over an empty screen
+        let mut chip8 = load(&[0xD011]);
+        chip8.write_memory(0x300, 0b1000_0001);
+        chip8.set_index_register(0x300);
+        chip8.step();
+        assert_eq!(chip8.read_register(0xF), 0);
+    }
+
+    #[test]
+    fn drw_wraps_at_edges() {
+        let mut chip8 = load(&[0xD125]);
+        chip8.write_register(1, 0xFE);
+        chip8.write_register(2, 0xFF);
+        chip8.step();
+        // (62, 31) and wrapped around (1, 0)
+        assert!(chip8.display[64 * 31 + 62]);
+        assert!(chip8.display[1]);
+    }
+
+    #[test]
+    fn skp_sknp() {
+        let mut chip8 = load(&[0xE19E]);
+        chip8.write_register(1, 0xA);
+        chip8.keyboard[0xA] = true;
+        chip8.step();
+        assert_eq!(chip8.program_counter(), 0x204);
+
+        assert_eq!(exec(0xE19E, &[(1, 0xA)]).program_counter(), 0x202);
+        assert_eq!(exec(0xE1A1, &[(1, 0xA)]).program_counter(), 0x204);
+    }
+
+    #[test]
+    fn timers() {
+        let chip8 = exec(0xF115, &[(1, 0x20)]);
+        assert_eq!(chip8.delay_timer(), 0x20);
+        let chip8 = exec(0xF118, &[(1, 0x30)]);
+        assert_eq!(chip8.sound_timer(), 0x30);
+
+        let mut chip8 = load(&[0xF115, 0xF207]);
+        chip8.write_register(1, 0x20);
+        chip8.step();
+        chip8.tick_timers();
+        chip8.step();
+        assert_eq!(chip8.read_register(2), 0x1F);
+    }
+
+    #[test]
+    fn wait_key() {
+        let mut chip8 = load(&[0xF30A]);
+        chip8.step();
+        assert_eq!(chip8.program_counter(), 0x200);
+        chip8.keyboard[0x7] = true;
+        chip8.step();
+        assert_eq!(chip8.program_counter(), 0x202);
+        assert_eq!(chip8.read_register(3), 0x7);
+    }
+
+    #[test]
+    fn add_i() {
+        let mut chip8 = load(&[0xF11E]);
+        chip8.set_index_register(0x100);
+        chip8.write_register(1, 0x10);
+        chip8.step();
+        assert_eq!(chip8.index_register(), 0x110);
+
+        // wraps instead of overflowing
+        let mut chip8 = load(&[0xF11E]);
+        chip8.set_index_register(0xFFFF);
+        chip8.write_register(1, 0x02);
+        chip8.step();
+        assert_eq!(chip8.index_register(), 0x0001);
+    }
+
+    #[test]
+    fn font() {
+        let chip8 = exec(0xF129, &[(1, 0xA)]);
+        assert_eq!(chip8.index_register(), 50);
+        assert_eq!(chip8.read_memory(50), 0xF0);
+    }
+
+    #[test]
+    fn bcd() {
+        let mut chip8 = load(&[0xF133]);
+        chip8.set_index_register(0x300);
+        chip8.write_register(1, 254);
+        chip8.step();
+        assert_eq!(
+            [0x300, 0x301, 0x302].map(|a| chip8.read_memory(a)),
+            [2, 5, 4]
+        );
+    }
+
+    #[test]
+    fn save_load_state() {
+        // CALL 0x204; ...; LD V3, 0x42; DRW V0, V0, 5
+        let mut chip8 = load(&[0x2204, 0x0000, 0x6342, 0xD005]);
+        chip8.set_quirks(Quirks::schip());
+        chip8.keyboard[0xB] = true;
+        for _ in 0..3 {
+            chip8.step();
+        }
+        let mut state = [0; STATE_SIZE];
+        chip8.save_state(&mut state);
+
+        let mut restored = Chip8::new();
+        assert_eq!(restored.load_state(&state), Ok(()));
+        let mut saved_again = [0; STATE_SIZE];
+        restored.save_state(&mut saved_again);
+        assert!(state[..] == saved_again[..]);
+        assert_eq!(restored.read_register(3), 0x42);
+        assert_eq!(restored.stack(), &[0x202]);
+        assert_eq!(restored.program_counter(), 0x208);
+        assert!(restored.display[0]);
+        assert!(restored.keyboard[0xB]);
+        assert_eq!(restored.quirks, Quirks::schip());
+
+        // corrupted addresses are masked into memory
+        let mut corrupted = state;
+        corrupted[17..21].copy_from_slice(&[0xFF, 0xFF, 0xFF, 0xFE]);
+        corrupted[21..23].copy_from_slice(&[0xFF, 0xFF]);
+        assert_eq!(restored.load_state(&corrupted), Ok(()));
+        assert_eq!(restored.index_register(), 0xFFF);
+        assert_eq!(restored.program_counter(), 0xFFE);
+        assert_eq!(restored.stack(), &[0xFFF]);
+        restored.step();
+        assert_eq!(restored.fault(), None);
+        assert_eq!(restored.program_counter(), 0x1000);
+
+        assert_eq!(restored.load_state(&state[1..]), Err(StateError::Size));
+        state[0] = 0;
+        assert_eq!(restored.load_state(&state), Err(StateError::Version));
+    }
+
+    #[test]
+    fn store_load() {
+        let mut chip8 = load(&[0xF255, 0xF265]);
+        chip8.set_index_register(0x300);
+        chip8.write_register(0, 1);
+        chip8.write_register(1, 2);
+        chip8.write_register(2, 3);
+        chip8.write_register(3, 4);
+        chip8.step();
+        assert_eq!(
+            [0x300, 0x301, 0x302, 0x303].map(|a| chip8.read_memory(a)),
+            [1, 2, 3, 0]
+        );
+        assert_eq!(chip8.index_register(), 0x303);
+
+        chip8.write_memory(0x303, 9);
+        chip8.write_memory(0x304, 8);
+        chip8.write_memory(0x305, 7);
+        chip8.step();
+        assert_eq!([0, 1, 2, 3].map(|x| chip8.read_register(x)), [9, 8, 7, 4]);
+        assert_eq!(chip8.index_register(), 0x306);
+    }
+
+    #[test]
+    fn cpu_state() {
+        // CALL 0x204; ...; LD VA, 0x12; LD I, 0x345
+        let mut chip8 = load(&[0x2204, 0x0000, 0x6A12, 0xA345]);
+        for _ in 0..3 {
+            chip8.step();
+        }
+        let state = chip8.cpu_state();
+        assert_eq!(state.registers[0xA], 0x12);
+        assert_eq!(state.index_register, 0x345);
+        assert_eq!(state.program_counter, 0x208);
+        assert_eq!(state.call_stack(), &[0x202]);
+        assert_eq!(state.call_stack(), chip8.stack());
+    }
+
+    #[test]
+    fn memory_range() {
+        let chip8 = load(&[0x1234]);
+        assert_eq!(chip8.memory_range(0x200..0x202), Some(&[0x12, 0x34][..]));
+        assert_eq!(chip8.memory_range(0x000..0x005), Some(&CHIP8_FONTS[0..5]));
+        assert_eq!(chip8.memory_range(0xFFF..0x1001), None);
+        assert_eq!(chip8.memory()[0x201], 0x34);
+    }
+
+    #[test]
+    fn clone_compare_debug() {
+        let mut chip8 = load(&[0x6005]);
+        let copy = chip8.clone();
+        assert_eq!(chip8, copy);
+        chip8.step();
+        assert_ne!(chip8, copy);
+        assert!(format!("{:?}", chip8).contains("program_counter: 514"));
     }
 }
@@ -0,0 +1,261 @@
+//! Code/data coverage map.
+//!
+//! Records for every byte of memory whether it was executed as part of an instruction, read as
+//! data (sprites of DRW, `LD Vx, [I]`) or written (`LD B`, `LD [I]`), from the instructions
+//! executed.
+//!
+//! The map is exported as text, one hex digit of flags per byte, 64 bytes per line prefixed with
+//! the address of the first one. Lines of untouched bytes are left out, a summary of the program
+//! bytes comes first in comments:
+//!
+//! ```text
+//! # 412 of 512 program bytes executed (80.47%), 64 read, 3 written, 33 untouched
+//! 0x200: 3232323232323232...
+//! ```
+//!
+//! The disassembler reads it back to tell code from data.
+use crate::chip8::Chip8;
+use crate::disasm;
+use crate::instruction;
+use crate::watch::{memory_access, Access};
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+/// Flag of a byte: first byte of an executed instruction.
+pub const INSTRUCTION: u8 = 0x1;
+/// Flag of a byte: executed as part of an instruction.
+pub const EXECUTED: u8 = 0x2;
+/// Flag of a byte: read as data.
+pub const READ: u8 = 0x4;
+/// Flag of a byte: written.
+pub const WRITTEN: u8 = 0x8;
+
+const MEMORY_SIZE: usize = 0x1000;
+const PROGRAM_START: usize = 0x200;
+const BYTES_PER_LINE: usize = 64;
+
+/// Flags of every byte of memory.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Coverage {
+    flags: Vec<u8>,
+}
+
+impl Default for Coverage {
+    fn default() -> Coverage {
+        Coverage::new()
+    }
+}
+
+impl Coverage {
+    /// Map with no byte touched.
+    pub fn new() -> Coverage {
+        Coverage {
+            flags: vec![0; MEMORY_SIZE],
+        }
+    }
+
+    /// Called before each instruction.
+    pub fn on_instruction(&mut self, chip8: &Chip8) {
+        let state = chip8.cpu_state();
+        let pc = state.program_counter as usize % MEMORY_SIZE;
+        self.flags[pc] |= INSTRUCTION | EXECUTED;
+        self.flags[(pc + 1) % MEMORY_SIZE] |= EXECUTED;
+
+        let opcode = chip8.read_memory_u16(pc as u16);
+        if let Some((access, start, len)) = memory_access(instruction::decode(opcode), &state) {
+            let flag = if access == Access::Read {
+                READ
+            } else {
+                WRITTEN
+            };
+            for offset in 0..len {
+                self.flags[start.wrapping_add(offset) as usize % MEMORY_SIZE] |= flag;
+            }
+        }
+    }
+
+    /// Flags of the byte at `address`, see [`INSTRUCTION`], [`EXECUTED`], [`READ`] and
+    /// [`WRITTEN`].
+    pub fn flags(&self, address: u16) -> u8 {
+        self.flags[address as usize % MEMORY_SIZE]
+    }
+
+    /// Addresses of the executed instructions.
+    pub fn instructions(&self) -> BTreeSet<u16> {
+        (0..MEMORY_SIZE as u16)
+            .filter(|address| self.flags(*address) & INSTRUCTION != 0)
+            .collect()
+    }
+
+    /// Instruction start addresses of `rom`, for
+    /// [`disasm::disassemble_rom_with_code`].
+    ///
+    /// These are the executed instructions, plus those found by following control flow
+    /// statically which were not used as data, since coverage is rarely complete.
+    pub fn code(&self, rom: &[u8]) -> BTreeSet<u16> {
+        let mut code = self.instructions();
+        code.extend(disasm::trace_code(rom).into_iter().filter(|address| {
+            let flags = self.flags(*address) | self.flags(address + 1);
+            flags & READ == 0 || flags & EXECUTED != 0
+        }));
+        code
+    }
+
+    // counts of the program bytes of rom: (executed, read, written, untouched)
+    fn summary(&self, rom: &[u8]) -> (usize, usize, usize, usize) {
+        let end = (PROGRAM_START + rom.len()).min(MEMORY_SIZE);
+        let flags = &self.flags[PROGRAM_START..end];
+        let count = |flag: u8| flags.iter().filter(|f| *f & flag != 0).count();
+        let untouched = flags.iter().filter(|f| **f == 0).count();
+        (count(EXECUTED), count(READ), count(WRITTEN), untouched)
+    }
+
+    /// Text export, see the [module documentation](self).
+    pub fn export(&self, rom: &[u8]) -> String {
+        let mut text = String::new();
+        let (executed, read, written, untouched) = self.summary(rom);
+        let percent = if rom.is_empty() {
+            0.0
+        } else {
+            executed as f64 * 100.0 / rom.len() as f64
+        };
+        writeln!(
+            text,
+            "# {} of {} program bytes executed ({:.2}%), {} read, {} written, {} untouched",
+            executed,
+            rom.len(),
+            percent,
+            read,
+            written,
+            untouched
+        )
+        .unwrap();
+        let missed: Vec<String> = disasm::trace_code(rom)
+            .into_iter()
+            .filter(|address| self.flags(*address) & INSTRUCTION == 0)
+            .map(|address| format!("0x{:03X}", address))
+            .collect();
+        if !missed.is_empty() {
+            writeln!(text, "# never executed: {}", missed.join(" ")).unwrap();
+        }
+
+        for (line, flags) in self.flags.chunks(BYTES_PER_LINE).enumerate() {
+            if flags.iter().all(|f| *f == 0) {
+                continue;
+            }
+            let digits: String = flags.iter().map(|f| format!("{:X}", f)).collect();
+            writeln!(text, "0x{:03X}: {}", line * BYTES_PER_LINE, digits).unwrap();
+        }
+        text
+    }
+
+    /// Parses an exported map.
+    pub fn parse(text: &str) -> Result<Coverage, String> {
+        let mut coverage = Coverage::new();
+        for (n, line) in text.lines().enumerate() {
+            let line = line.trim();
+            if line.is_empty() || line.starts_with('#') {
+                continue;
+            }
+            let error = |e: &str| format!("line {}: {}", n + 1, e);
+
+            let (address, digits) = line
+                .split_once(':')
+                .ok_or_else(|| error("expected ADDRESS: FLAGS"))?;
+            let address = address.trim();
+            let address = u16::from_str_radix(address.trim_start_matches("0x"), 16)
+                .map_err(|_| error(&format!("invalid address: {}", address)))?;
+            for (offset, digit) in digits.trim().chars().enumerate() {
+                let flags = digit
+                    .to_digit(16)
+                    .ok_or_else(|| error(&format!("invalid flags: {}", digit)))?;
+                let index = address as usize + offset;
+                if index >= MEMORY_SIZE {
+                    return Err(error("past the end of memory"));
+                }
+                coverage.flags[index] = flags as u8;
+            }
+        }
+        Ok(coverage)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::disasm::Syntax;
+
+    // LD I, sprite; DRW V0, V0, 2; LD I, 0x300; LD B, V0; SE V0, 0; JP 0x20A; JP sprite;
+    // sprite: JP sprite
+    const ROM: [u8; 16] = [
+        0xA2, 0x0E, 0xD0, 0x02, 0xA3, 0x00, 0xF0, 0x33, 0x30, 0x00, 0x12, 0x0A, 0x12, 0x0E, 0x12,
+        0x0E,
+    ];
+
+    fn run() -> Coverage {
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&ROM);
+        let mut coverage = Coverage::new();
+        for _ in 0..10 {
+            coverage.on_instruction(&chip8);
+            chip8.step();
+        }
+        coverage
+    }
+
+    #[test]
+    fn flags() {
+        let coverage = run();
+        assert_eq!(coverage.flags(0x200), INSTRUCTION | EXECUTED);
+        assert_eq!(coverage.flags(0x201), EXECUTED);
+        // skipped
+        assert_eq!(coverage.flags(0x20A), 0);
+        assert_eq!(coverage.flags(0x20C), INSTRUCTION | EXECUTED);
+        // sprite, executed as JP too
+        assert_eq!(coverage.flags(0x20E), READ | INSTRUCTION | EXECUTED);
+        assert_eq!(coverage.flags(0x20F), READ | EXECUTED);
+        assert_eq!(coverage.flags(0x300), WRITTEN);
+        assert_eq!(coverage.flags(0x302), WRITTEN);
+        assert_eq!(coverage.flags(0x303), 0);
+    }
+
+    #[test]
+    fn export_and_parse() {
+        let coverage = run();
+        let text = coverage.export(&ROM);
+        assert!(text.starts_with(
+            "# 14 of 16 program bytes executed (87.50%), 2 read, 0 written, 2 untouched\n\
+             # never executed: 0x20A\n"
+        ));
+        assert!(text.contains("\n0x200: 32323232320032760000"));
+        assert!(text.contains("\n0x300: 8880000"));
+        assert_eq!(text.lines().count(), 4);
+        assert_eq!(Coverage::parse(&text).unwrap(), coverage);
+
+        assert!(Coverage::parse("0x200 32").is_err());
+        assert!(Coverage::parse("0x200: 3G").is_err());
+        assert!(Coverage::parse("0xFFF: 33").is_err());
+    }
+
+    #[test]
+    fn code_for_the_disassembler() {
+        // LD I, sprite; DRW V0, V0, 2; SNE V0, 0; JP 0x202; sprite
+        // the sprite follows a skip, but is only read
+        let rom = [0xA2, 0x08, 0xD0, 0x02, 0x40, 0x00, 0x12, 0x02, 0x12, 0x02];
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&rom);
+        let mut coverage = Coverage::new();
+        for _ in 0..5 {
+            coverage.on_instruction(&chip8);
+            chip8.step();
+        }
+        let code = coverage.code(&rom);
+        assert_eq!(
+            code.iter().copied().collect::<Vec<u16>>(),
+            [0x200, 0x202, 0x204, 0x206]
+        );
+        let listing = disasm::disassemble_rom_with_code(&rom, Syntax::Cowgod, &code);
+        assert!(listing.contains("0x208: 12 02       DB 0x12, 0x02"));
+    }
+}
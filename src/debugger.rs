@@ -0,0 +1,825 @@
+//! Interactive command-line debugger: breakpoints, tracepoints, watchpoints, stepping forward
+//! and back, and inspecting or patching the machine. Type `help` at its prompt for the commands.
+use crate::chip8::Chip8;
+use crate::disasm::disassemble;
+use crate::expr::{Expr, Template};
+use crate::history::History;
+use crate::watch::{Watchpoint, Watchpoints};
+
+use std::fmt;
+use std::io::prelude::*;
+
+const MEMORY_SIZE: usize = 0x1000;
+
+const HELP: &str = "\
+commands (addresses and values are hex, counts are decimal):
+  b, break <addr>         set breakpoint
+  b, break <addr> if <expr>
+                          set conditional breakpoint, e.g. if v3 > 10 && mem[i] == 0
+  b, break if <expr>      break at any address when expr is true
+  tp, tracepoint <addr> <message>
+                          print message instead of breaking, e.g. V3={v3} I={i:x}
+  cond, condition <n> [expr]
+                          set or clear the condition of breakpoint n
+  ignore <n> <count>      skip the next count hits of breakpoint n
+  d, delete <n>           clear breakpoint n
+  bl, breakpoints         list breakpoints with hit counts
+  w, watch <watch>        set watchpoint, break after an instruction which
+                            <addr>[-<end>] [r|w|rw]  accesses memory (default: w)
+                            v<x>                     changes Vx
+                            v<x> == <byte>           sets Vx to byte
+                            i <start>-<end>          moves I into the range
+  wd, unwatch <n>         clear watchpoint n
+  wl, watchpoints         list watchpoints
+  s, step [n]             execute n instructions (default: 1)
+  c, continue             continue until a breakpoint
+  rs, reverse-step [n]    go back n instructions (default: 1)
+  rc, reverse-continue    go back to the previous breakpoint or watchpoint hit
+  r, regs                 print V0-VF, I, PC, SP and timers
+  bt, stack               print call stack
+  l, list [addr] [n]      disassemble n instructions around addr (default: PC)
+  x, dump <addr> [len]    dump memory (default len: 0x40)
+  poke <addr> <byte>...   write bytes into memory
+  h, help                 print this help
+  q, quit                 exit emulator";
+
+// breakpoint or tracepoint
+struct Breakpoint {
+    // None: checked before every instruction
+    address: Option<u16>,
+    condition: Option<Expr>,
+    // tracepoints print the message instead of breaking
+    message: Option<Template>,
+    hits: u32,
+    ignore_count: u32,
+}
+
+impl fmt::Display for Breakpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self.address {
+            Some(address) => write!(f, "0x{:03X}", address)?,
+            None => write!(f, "*")?,
+        }
+        if let Some(condition) = self.condition.as_ref() {
+            write!(f, " if {}", condition)?;
+        }
+        if let Some(message) = self.message.as_ref() {
+            write!(f, " log \"{}\"", message)?;
+        }
+        write!(f, ", hit {} times", self.hits)?;
+        if self.ignore_count > 0 {
+            write!(f, ", ignore next {}", self.ignore_count)?;
+        }
+        Ok(())
+    }
+}
+
+/// Interactive debugger on stdin/stdout, which takes over before instructions.
+pub struct Debugger {
+    // NOTE: cleared breakpoints leave a hole, so numbers stay stable
+    breakpoints: Vec<Option<Breakpoint>>,
+    watchpoints: Watchpoints,
+    // NOTE: recorded while the debugger is enabled, for reverse execution
+    history: History,
+    // number of instructions to execute before breaking again
+    steps_remaining: Option<usize>,
+    // break before the next instruction (set on start and on user request)
+    break_requested: bool,
+}
+
+impl Default for Debugger {
+    fn default() -> Debugger {
+        Debugger::new()
+    }
+}
+
+impl Debugger {
+    /// Debugger which breaks before the first instruction.
+    pub fn new() -> Debugger {
+        Debugger {
+            breakpoints: Vec::new(),
+            watchpoints: Watchpoints::new(),
+            history: History::new(),
+            steps_remaining: None,
+            break_requested: true,
+        }
+    }
+
+    /// Breaks into the debugger before the next instruction.
+    pub fn request_break(&mut self) {
+        self.break_requested = true;
+    }
+
+    /// Called before each instruction, returns false if the user wants to quit.
+    pub fn on_instruction(&mut self, chip8: &mut Chip8) -> bool {
+        self.history.record(chip8);
+        if !self.should_break(chip8) {
+            if !self.watchpoints.is_empty() {
+                self.watchpoints.record(chip8);
+            }
+            return true;
+        }
+        print_instruction(chip8, chip8.program_counter());
+        let resume = self.repl(chip8);
+        // NOTE: changes made in the debugger do not trigger watchpoints, and are replayed
+        self.watchpoints.record(chip8);
+        self.history.amend(chip8);
+        resume
+    }
+
+    fn should_break(&mut self, chip8: &Chip8) -> bool {
+        // NOTE: the previous instruction may have triggered watchpoints
+        let hits = self.watchpoints.check(chip8);
+        for hit in hits.iter() {
+            println!("{}", hit);
+        }
+        let mut stop = !hits.is_empty();
+
+        // NOTE: tracepoints log even while stepping
+        stop |= self.check_breakpoints(chip8);
+
+        if self.break_requested {
+            self.break_requested = false;
+            stop = true;
+        }
+
+        if let Some(steps) = self.steps_remaining.as_mut() {
+            *steps -= 1;
+            if *steps == 0 {
+                stop = true;
+            }
+        }
+
+        if stop {
+            self.steps_remaining = None;
+        }
+        stop
+    }
+
+    // count hits and print tracepoints, returns true if a breakpoint stops execution
+    fn check_breakpoints(&mut self, chip8: &Chip8) -> bool {
+        let pc = chip8.program_counter();
+        let mut stop = false;
+        for (i, breakpoint) in self.breakpoints.iter_mut().enumerate() {
+            let breakpoint = match breakpoint {
+                Some(breakpoint) if breakpoint.address.unwrap_or(pc) == pc => breakpoint,
+                _ => continue,
+            };
+            let number = i + 1;
+            if let Some(condition) = breakpoint.condition.as_ref() {
+                match condition.is_true(chip8) {
+                    Ok(true) => (),
+                    Ok(false) => continue,
+                    Err(e) => {
+                        println!("breakpoint {}: error in condition: {}", number, e);
+                        stop = true;
+                        continue;
+                    }
+                }
+            }
+
+            breakpoint.hits += 1;
+            if breakpoint.ignore_count > 0 {
+                breakpoint.ignore_count -= 1;
+                continue;
+            }
+            match breakpoint
+                .message
+                .as_ref()
+                .map(|message| message.format(chip8))
+            {
+                Some(Ok(message)) => println!("0x{:03X}: {}", pc, message),
+                Some(Err(e)) => {
+                    println!("tracepoint {}: error in message: {}", number, e);
+                    stop = true;
+                }
+                None => {
+                    println!("breakpoint {} hit at 0x{:03X}", number, pc);
+                    stop = true;
+                }
+            }
+        }
+        stop
+    }
+
+    // would a breakpoint stop here? without counting hits or printing tracepoints
+    fn breakpoint_at(&self, chip8: &Chip8) -> Option<usize> {
+        let pc = chip8.program_counter();
+        self.breakpoints
+            .iter()
+            .enumerate()
+            .find_map(|(i, breakpoint)| {
+                let breakpoint = breakpoint.as_ref()?;
+                let hit = breakpoint.address.unwrap_or(pc) == pc
+                    && breakpoint.message.is_none()
+                    && breakpoint
+                        .condition
+                        .as_ref()
+                        .map_or(true, |condition| condition.is_true(chip8) == Ok(true));
+                if hit {
+                    Some(i + 1)
+                } else {
+                    None
+                }
+            })
+    }
+
+    // go back to the last breakpoint or watchpoint hit before the current instruction
+    fn reverse_continue(&mut self, chip8: &mut Chip8) -> Result<(), String> {
+        let position = self.history.position().unwrap();
+        let mut last_hit = None;
+        let mut previous = None;
+        self.history.scan(|index, state| {
+            if index == position {
+                return;
+            }
+            if let Some((before, opcode)) = previous {
+                for hit in self.watchpoints.check_instruction(before, opcode, state) {
+                    last_hit = Some((index, hit.to_string()));
+                }
+            }
+            if let Some(number) = self.breakpoint_at(state) {
+                let message = format!(
+                    "breakpoint {} hit at 0x{:03X}",
+                    number,
+                    state.program_counter()
+                );
+                last_hit = Some((index, message));
+            }
+            previous = Some((
+                state.cpu_state(),
+                state.read_memory_u16(state.program_counter()),
+            ));
+        });
+
+        let target = match last_hit {
+            Some((index, message)) => {
+                println!("{}", message);
+                index
+            }
+            None => {
+                println!("reached the start of the history");
+                self.history.oldest().unwrap()
+            }
+        };
+        *chip8 = self.history.rewind(target).unwrap();
+        Ok(())
+    }
+
+    fn add_breakpoint(&mut self, breakpoint: Breakpoint) {
+        self.breakpoints.push(Some(breakpoint));
+        let number = self.breakpoints.len();
+        let breakpoint = self.breakpoints[number - 1].as_ref().unwrap();
+        println!("breakpoint {} set: {}", number, breakpoint);
+    }
+
+    // index of an existing breakpoint from its number
+    fn breakpoint_index(&self, s: Option<&&str>) -> Result<usize, String> {
+        let s = s.ok_or_else(|| "missing breakpoint number".to_string())?;
+        let number = s
+            .parse::<usize>()
+            .map_err(|_| format!("invalid number: {}", s))?;
+        match number.checked_sub(1) {
+            Some(i) if matches!(self.breakpoints.get(i), Some(Some(_))) => Ok(i),
+            _ => Err(format!("no breakpoint {}", number)),
+        }
+    }
+
+    fn breakpoint_mut(&mut self, s: Option<&&str>) -> Result<&mut Breakpoint, String> {
+        let i = self.breakpoint_index(s)?;
+        Ok(self.breakpoints[i].as_mut().unwrap())
+    }
+
+    // read commands until execution is resumed
+    fn repl(&mut self, chip8: &mut Chip8) -> bool {
+        let stdin = std::io::stdin();
+        loop {
+            print!("(chip8) ");
+            std::io::stdout().flush().unwrap();
+
+            let mut line = String::new();
+            if stdin.lock().read_line(&mut line).unwrap() == 0 {
+                // EOF
+                return false;
+            }
+
+            let args: Vec<&str> = line.split_whitespace().collect();
+            if args.is_empty() {
+                continue;
+            }
+
+            match self.execute(chip8, &args) {
+                Ok(Resume::Stay) => (),
+                Ok(Resume::Run) => return true,
+                Ok(Resume::Quit) => return false,
+                Err(e) => println!("error: {}", e),
+            }
+        }
+    }
+
+    fn execute(&mut self, chip8: &mut Chip8, args: &[&str]) -> Result<Resume, String> {
+        match args[0] {
+            "b" | "break" => {
+                // "<addr>", "<addr> if <expr>" or "if <expr>"
+                let (address, condition) = match args.get(1) {
+                    Some(&"if") => (None, &args[1..]),
+                    _ => (
+                        Some(parse_address(args.get(1))?),
+                        &args[2.min(args.len())..],
+                    ),
+                };
+                let condition = match condition {
+                    [] => None,
+                    ["if", expr @ ..] if !expr.is_empty() => Some(expr.join(" ").parse()?),
+                    _ => return Err("usage: break <addr> [if <expr>]".to_string()),
+                };
+                self.add_breakpoint(Breakpoint {
+                    address,
+                    condition,
+                    message: None,
+                    hits: 0,
+                    ignore_count: 0,
+                });
+            }
+            "tp" | "tracepoint" => {
+                let address = parse_address(args.get(1))?;
+                if args.len() < 3 {
+                    return Err("usage: tracepoint <addr> <message>".to_string());
+                }
+                self.add_breakpoint(Breakpoint {
+                    address: Some(address),
+                    condition: None,
+                    message: Some(args[2..].join(" ").parse()?),
+                    hits: 0,
+                    ignore_count: 0,
+                });
+            }
+            "cond" | "condition" => {
+                let condition = match args.get(2) {
+                    Some(_) => Some(args[2..].join(" ").parse::<Expr>()?),
+                    None => None,
+                };
+                let breakpoint = self.breakpoint_mut(args.get(1))?;
+                breakpoint.condition = condition;
+                println!("{}", breakpoint);
+            }
+            "ignore" => {
+                let s = args.get(2).ok_or_else(|| "missing count".to_string())?;
+                let count = s
+                    .parse::<u32>()
+                    .map_err(|_| format!("invalid count: {}", s))?;
+                let breakpoint = self.breakpoint_mut(args.get(1))?;
+                breakpoint.ignore_count = count;
+                println!("{}", breakpoint);
+            }
+            "d" | "delete" => {
+                let i = self.breakpoint_index(args.get(1))?;
+                self.breakpoints[i] = None;
+                println!("breakpoint {} cleared", i + 1);
+            }
+            "bl" | "breakpoints" => {
+                for (i, breakpoint) in self.breakpoints.iter().enumerate() {
+                    if let Some(breakpoint) = breakpoint {
+                        println!("{}: {}", i + 1, breakpoint);
+                    }
+                }
+            }
+            "w" | "watch" => {
+                if args.len() < 2 {
+                    return Err("missing watchpoint".to_string());
+                }
+                let watchpoint: Watchpoint = args[1..].join(" ").parse()?;
+                let number = self.watchpoints.add(watchpoint);
+                println!("watchpoint {} set: {}", number, watchpoint);
+            }
+            "wd" | "unwatch" => {
+                let s = args.get(1).ok_or_else(|| "missing number".to_string())?;
+                let number = s
+                    .parse::<usize>()
+                    .map_err(|_| format!("invalid number: {}", s))?;
+                if self.watchpoints.remove(number).is_none() {
+                    return Err(format!("no watchpoint {}", number));
+                }
+                println!("watchpoint {} cleared", number);
+            }
+            "wl" | "watchpoints" => {
+                for (number, watchpoint) in self.watchpoints.iter() {
+                    println!("{}: {}", number, watchpoint);
+                }
+            }
+            "s" | "step" => {
+                let n = match args.get(1) {
+                    Some(s) => s
+                        .parse::<usize>()
+                        .map_err(|_| format!("invalid count: {}", s))?,
+                    None => 1,
+                };
+                if n == 0 {
+                    return Err("count must be positive".to_string());
+                }
+                self.steps_remaining = Some(n);
+                return Ok(Resume::Run);
+            }
+            "rs" | "reverse-step" => {
+                let n = match args.get(1) {
+                    Some(s) => s
+                        .parse::<u64>()
+                        .map_err(|_| format!("invalid count: {}", s))?,
+                    None => 1,
+                };
+                let position = self.history.position().unwrap();
+                let oldest = self.history.oldest().unwrap();
+                if position == oldest {
+                    return Err("no history before this instruction".to_string());
+                }
+                let target = position.saturating_sub(n).max(oldest);
+                *chip8 = self.history.rewind(target).unwrap();
+                print_instruction(chip8, chip8.program_counter());
+            }
+            "rc" | "reverse-continue" => {
+                self.reverse_continue(chip8)?;
+                print_instruction(chip8, chip8.program_counter());
+            }
+            "c" | "continue" => {
+                self.steps_remaining = None;
+                return Ok(Resume::Run);
+            }
+            "r" | "regs" => print_registers(chip8),
+            "bt" | "stack" => print_stack(chip8),
+            "l" | "list" => {
+                let address = match args.get(1) {
+                    Some(_) => parse_address(args.get(1))?,
+                    None => chip8.program_counter(),
+                };
+                let n = match args.get(2) {
+                    Some(s) => s
+                        .parse::<u16>()
+                        .map_err(|_| format!("invalid count: {}", s))?,
+                    None => 10,
+                };
+                // start a few instructions before the address
+                let start = address.saturating_sub(2 * (n / 2));
+                for i in 0..n {
+                    let a = start + 2 * i;
+                    if a as usize + 1 >= MEMORY_SIZE {
+                        break;
+                    }
+                    print_instruction(chip8, a);
+                }
+            }
+            "x" | "dump" => {
+                let address = parse_address(args.get(1))?;
+                let len = match args.get(2) {
+                    Some(s) => parse_hex(s)? as usize,
+                    None => 0x40,
+                };
+                let end = (address as usize + len).min(MEMORY_SIZE);
+                for row in (address as usize..end).step_by(16) {
+                    let bytes: Vec<String> = (row..(row + 16).min(end))
+                        .map(|a| format!("{:02X}", chip8.read_memory(a as u16)))
+                        .collect();
+                    println!("0x{:03X}: {}", row, bytes.join(" "));
+                }
+            }
+            "poke" => {
+                let address = parse_address(args.get(1))?;
+                if args.len() < 3 {
+                    return Err("usage: poke <addr> <byte>...".to_string());
+                }
+                for (i, s) in args[2..].iter().enumerate() {
+                    let value = parse_hex(s)?;
+                    if value > 0xFF {
+                        return Err(format!("invalid byte: {}", s));
+                    }
+                    let a = address as usize + i;
+                    if a >= MEMORY_SIZE {
+                        return Err(format!("address out of range: 0x{:X}", a));
+                    }
+                    chip8.write_memory(a as u16, value as u8);
+                }
+            }
+            "h" | "help" => println!("{}", HELP),
+            "q" | "quit" => return Ok(Resume::Quit),
+            command => return Err(format!("unknown command: {} (try 'help')", command)),
+        }
+        Ok(Resume::Stay)
+    }
+}
+
+enum Resume {
+    Stay,
+    Run,
+    Quit,
+}
+
+fn parse_hex(s: &str) -> Result<u16, String> {
+    let digits = s.trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid number: {}", s))
+}
+
+fn parse_address(s: Option<&&str>) -> Result<u16, String> {
+    let s = s.ok_or_else(|| "missing address".to_string())?;
+    let address = parse_hex(s)?;
+    if address as usize >= MEMORY_SIZE {
+        return Err(format!("address out of range: {}", s));
+    }
+    Ok(address)
+}
+
+fn print_instruction(chip8: &Chip8, address: u16) {
+    let marker = if address == chip8.program_counter() {
+        "=>"
+    } else {
+        "  "
+    };
+    let opcode = chip8.read_memory_u16(address);
+    println!(
+        "{} 0x{:03X}: {:04X}  {}",
+        marker,
+        address,
+        opcode,
+        disassemble(opcode)
+    );
+}
+
+fn print_registers(chip8: &Chip8) {
+    for row in 0..4 {
+        let registers: Vec<String> = (0..4)
+            .map(|col| {
+                let x = row * 4 + col;
+                format!("V{:X}=0x{:02X}", x, chip8.read_register(x))
+            })
+            .collect();
+        println!("{}", registers.join(" "));
+    }
+    println!(
+        "I=0x{:03X} PC=0x{:03X} SP={} DT={} ST={}",
+        chip8.index_register(),
+        chip8.program_counter(),
+        chip8.stack_pointer(),
+        chip8.delay_timer(),
+        chip8.sound_timer()
+    );
+}
+
+fn print_stack(chip8: &Chip8) {
+    if chip8.stack().is_empty() {
+        println!("stack is empty");
+    }
+    // most recent call first
+    for (depth, address) in chip8.stack().iter().rev().enumerate() {
+        println!("#{} return to 0x{:03X}", depth, address);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LD V3, 0; loop: ADD V3, 1; JP loop
+    fn machine() -> Chip8 {
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&[0x63, 0x00, 0x73, 0x01, 0x12, 0x02]);
+        chip8
+    }
+
+    fn command(debugger: &mut Debugger, chip8: &mut Chip8, line: &str) -> Result<(), String> {
+        let args: Vec<&str> = line.split_whitespace().collect();
+        debugger.execute(chip8, &args).map(|_| ())
+    }
+
+    // run until the debugger stops, returns the number of instructions executed
+    fn run(debugger: &mut Debugger, chip8: &mut Chip8) -> usize {
+        for executed in 0..1000 {
+            debugger.history.record(chip8);
+            if debugger.should_break(chip8) {
+                return executed;
+            }
+            chip8.step();
+        }
+        panic!("no break");
+    }
+
+    #[test]
+    fn step_continue_and_delete() {
+        let mut debugger = Debugger::new();
+        let mut chip8 = machine();
+        // stops before the first instruction
+        assert_eq!(run(&mut debugger, &mut chip8), 0);
+
+        let resume = debugger.execute(&mut chip8, &["s"]);
+        assert!(matches!(resume, Ok(Resume::Run)));
+        chip8.step();
+        assert_eq!(run(&mut debugger, &mut chip8), 0);
+        assert_eq!(chip8.program_counter(), 0x202);
+
+        let resume = debugger.execute(&mut chip8, &["step", "3"]);
+        assert!(matches!(resume, Ok(Resume::Run)));
+        chip8.step();
+        assert_eq!(run(&mut debugger, &mut chip8), 2);
+        assert_eq!(chip8.program_counter(), 0x204);
+        assert_eq!(chip8.read_register(3), 2);
+
+        // continue to a breakpoint
+        command(&mut debugger, &mut chip8, "b 202").unwrap();
+        let resume = debugger.execute(&mut chip8, &["c"]);
+        assert!(matches!(resume, Ok(Resume::Run)));
+        chip8.step();
+        assert_eq!(run(&mut debugger, &mut chip8), 0);
+        assert_eq!(chip8.program_counter(), 0x202);
+        let resume = debugger.execute(&mut chip8, &["continue"]);
+        assert!(matches!(resume, Ok(Resume::Run)));
+        chip8.step();
+        assert_eq!(run(&mut debugger, &mut chip8), 1);
+        assert_eq!(chip8.program_counter(), 0x202);
+        assert_eq!(chip8.read_register(3), 3);
+        assert_eq!(debugger.breakpoints[0].as_ref().unwrap().hits, 2);
+
+        // deleted breakpoints keep the numbers of the others
+        command(&mut debugger, &mut chip8, "break 204").unwrap();
+        command(&mut debugger, &mut chip8, "d 1").unwrap();
+        assert!(debugger.breakpoints[0].is_none());
+        command(&mut debugger, &mut chip8, "bl").unwrap();
+        chip8.step();
+        assert_eq!(run(&mut debugger, &mut chip8), 0);
+        assert_eq!(chip8.program_counter(), 0x204);
+        command(&mut debugger, &mut chip8, "delete 2").unwrap();
+
+        let error = |debugger: &mut Debugger, chip8: &mut Chip8, line| {
+            command(debugger, chip8, line).unwrap_err()
+        };
+        assert_eq!(error(&mut debugger, &mut chip8, "d 1"), "no breakpoint 1");
+        assert_eq!(error(&mut debugger, &mut chip8, "d 0"), "no breakpoint 0");
+        assert_eq!(
+            error(&mut debugger, &mut chip8, "d one"),
+            "invalid number: one"
+        );
+        assert_eq!(
+            error(&mut debugger, &mut chip8, "delete"),
+            "missing breakpoint number"
+        );
+        assert_eq!(error(&mut debugger, &mut chip8, "b"), "missing address");
+        assert_eq!(
+            error(&mut debugger, &mut chip8, "b 1000"),
+            "address out of range: 1000"
+        );
+        assert_eq!(
+            error(&mut debugger, &mut chip8, "b 2G0"),
+            "invalid number: 2G0"
+        );
+        assert_eq!(
+            error(&mut debugger, &mut chip8, "b 202 when v3 == 1"),
+            "usage: break <addr> [if <expr>]"
+        );
+        assert_eq!(
+            error(&mut debugger, &mut chip8, "s 0"),
+            "count must be positive"
+        );
+        assert_eq!(
+            error(&mut debugger, &mut chip8, "s -1"),
+            "invalid count: -1"
+        );
+        assert_eq!(
+            error(&mut debugger, &mut chip8, "frobnicate"),
+            "unknown command: frobnicate (try 'help')"
+        );
+        assert_eq!(debugger.breakpoints.len(), 2);
+
+        let resume = debugger.execute(&mut chip8, &["q"]);
+        assert!(matches!(resume, Ok(Resume::Quit)));
+    }
+
+    #[test]
+    fn inspect_and_poke() {
+        let mut debugger = Debugger::new();
+        let mut chip8 = machine();
+        for line in [
+            "r",
+            "regs",
+            "bt",
+            "l",
+            "list 200 4",
+            "l FFE",
+            "x 200 10",
+            "x FF0",
+            "h",
+        ]
+        .iter()
+        {
+            command(&mut debugger, &mut chip8, line).unwrap();
+        }
+
+        command(&mut debugger, &mut chip8, "poke 300 AB 0xcd").unwrap();
+        assert_eq!(chip8.read_memory(0x300), 0xAB);
+        assert_eq!(chip8.read_memory(0x301), 0xCD);
+        // patched code runs: LD V3, 0x42
+        command(&mut debugger, &mut chip8, "poke 200 63 42").unwrap();
+        chip8.step();
+        assert_eq!(chip8.read_register(3), 0x42);
+
+        let error = |debugger: &mut Debugger, chip8: &mut Chip8, line| {
+            command(debugger, chip8, line).unwrap_err()
+        };
+        assert_eq!(
+            error(&mut debugger, &mut chip8, "poke 300"),
+            "usage: poke <addr> <byte>..."
+        );
+        assert_eq!(
+            error(&mut debugger, &mut chip8, "poke 300 100"),
+            "invalid byte: 100"
+        );
+        assert_eq!(
+            error(&mut debugger, &mut chip8, "poke 300 zz"),
+            "invalid number: zz"
+        );
+        assert_eq!(
+            error(&mut debugger, &mut chip8, "poke FFF 01 02"),
+            "address out of range: 0x1000"
+        );
+        assert_eq!(error(&mut debugger, &mut chip8, "x"), "missing address");
+        assert_eq!(
+            error(&mut debugger, &mut chip8, "x 200 1G"),
+            "invalid number: 1G"
+        );
+        assert_eq!(
+            error(&mut debugger, &mut chip8, "l 1000"),
+            "address out of range: 1000"
+        );
+        assert_eq!(
+            error(&mut debugger, &mut chip8, "l 200 ten"),
+            "invalid count: ten"
+        );
+    }
+
+    #[test]
+    fn conditional_breakpoint() {
+        let mut debugger = Debugger::new();
+        let mut chip8 = machine();
+        debugger.break_requested = false;
+        command(
+            &mut debugger,
+            &mut chip8,
+            "b 202 if v3 == 5 && mem[pc] == 0x73",
+        )
+        .unwrap();
+        run(&mut debugger, &mut chip8);
+        assert_eq!(chip8.read_register(3), 5);
+        assert_eq!(chip8.program_counter(), 0x202);
+        assert_eq!(debugger.breakpoints[0].as_ref().unwrap().hits, 1);
+
+        // anywhere, ignoring the first two hits
+        command(&mut debugger, &mut chip8, "d 1").unwrap();
+        command(&mut debugger, &mut chip8, "b if v3 > 10").unwrap();
+        command(&mut debugger, &mut chip8, "ignore 2 2").unwrap();
+        chip8.step();
+        // V3 reaches 11 at 0x204, hits at 0x204 and 0x202 are ignored
+        run(&mut debugger, &mut chip8);
+        assert_eq!(chip8.read_register(3), 12);
+        assert_eq!(chip8.program_counter(), 0x204);
+        assert_eq!(debugger.breakpoints[1].as_ref().unwrap().hits, 3);
+
+        assert!(command(&mut debugger, &mut chip8, "b 202 if v3 =").is_err());
+        assert!(command(&mut debugger, &mut chip8, "cond 1 v3").is_err());
+    }
+
+    #[test]
+    fn tracepoint_does_not_stop() {
+        let mut debugger = Debugger::new();
+        let mut chip8 = machine();
+        debugger.break_requested = false;
+        command(&mut debugger, &mut chip8, "tp 204 V3={v3}").unwrap();
+        command(&mut debugger, &mut chip8, "b 202").unwrap();
+        command(&mut debugger, &mut chip8, "cond 2 v3 == 3").unwrap();
+        run(&mut debugger, &mut chip8);
+        assert_eq!(chip8.read_register(3), 3);
+        assert_eq!(debugger.breakpoints[0].as_ref().unwrap().hits, 3);
+    }
+
+    #[test]
+    fn reverse_execution() {
+        let mut debugger = Debugger::new();
+        let mut chip8 = machine();
+        debugger.break_requested = false;
+        command(&mut debugger, &mut chip8, "b 202 if v3 == 7").unwrap();
+        command(&mut debugger, &mut chip8, "b 202 if v3 == 20").unwrap();
+        run(&mut debugger, &mut chip8);
+        assert_eq!(chip8.read_register(3), 7);
+        chip8.step();
+        run(&mut debugger, &mut chip8);
+        assert_eq!(chip8.read_register(3), 20);
+
+        command(&mut debugger, &mut chip8, "rs 3").unwrap();
+        assert_eq!(chip8.read_register(3), 19);
+        assert_eq!(chip8.program_counter(), 0x204);
+
+        // back to the previous hit, the replayed breakpoints do not count hits
+        command(&mut debugger, &mut chip8, "rc").unwrap();
+        assert_eq!(chip8.read_register(3), 7);
+        assert_eq!(chip8.program_counter(), 0x202);
+        assert_eq!(debugger.breakpoints[0].as_ref().unwrap().hits, 1);
+
+        command(&mut debugger, &mut chip8, "rc").unwrap();
+        assert_eq!(chip8.program_counter(), 0x200);
+        assert!(command(&mut debugger, &mut chip8, "rs").is_err());
+    }
+}
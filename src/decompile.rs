@@ -0,0 +1,595 @@
+//! Decompiler to structured pseudocode.
+//!
+//! Functions are the code reachable from 0x200 (`main`) and from CALL targets (`sub_XXX`), found
+//! with the control-flow graph of [`cfg::recover`]. Each function is
+//! structured from the layout of its instructions:
+//!
+//! - a backward JP closes a loop at its target; a skip right before it makes it `do { } while`
+//! - a skip over a forward JP is an `if`, with an `else` when the then part ends jumping past the
+//!   target
+//! - a skip over any other instruction is an `if` around it
+//! - JP to the start or end of the enclosing loop is `continue` or `break`
+//!
+//! Whatever does not fit stays a `goto` to a label. Registers are the variables `v0`-`vf` and
+//! `i`, the timers are `delay` and `sound`.
+use crate::cfg::{self, EdgeKind};
+use crate::disasm;
+use crate::instruction::{decode, Instruction};
+
+use std::collections::BTreeSet;
+use std::fmt::Write;
+
+const PROGRAM_START: u16 = 0x200;
+
+/// Statement of the pseudocode, each one remembers the address of the instruction it starts at.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Stmt {
+    /// `(address, instruction)` without control flow of its own.
+    Instruction(u16, Instruction),
+    /// `if (condition) { then } else { otherwise }`.
+    If {
+        /// Address of the skip.
+        address: u16,
+        /// Condition as pseudocode, e.g. `v1 != 0x02`.
+        condition: String,
+        /// Statements executed when the condition holds.
+        then: Vec<Stmt>,
+        /// Statements executed otherwise, may be empty.
+        otherwise: Vec<Stmt>,
+    },
+    /// Endless loop.
+    Loop {
+        /// Address of the first instruction of the loop.
+        address: u16,
+        /// Statements of the loop.
+        body: Vec<Stmt>,
+    },
+    /// `do { body } while (condition)`.
+    DoWhile {
+        /// Address of the first instruction of the loop.
+        address: u16,
+        /// Statements of the loop.
+        body: Vec<Stmt>,
+        /// Condition as pseudocode.
+        condition: String,
+    },
+    /// `(address, target)` of a jump which could not be structured.
+    Goto(u16, u16),
+    /// Jump out of the enclosing loop, at the address.
+    Break(u16),
+    /// Jump to the start of the enclosing loop, at the address.
+    Continue(u16),
+}
+
+impl Stmt {
+    fn address(&self) -> u16 {
+        match self {
+            Stmt::Instruction(address, _)
+            | Stmt::If { address, .. }
+            | Stmt::Loop { address, .. }
+            | Stmt::DoWhile { address, .. }
+            | Stmt::Goto(address, _)
+            | Stmt::Break(address)
+            | Stmt::Continue(address) => *address,
+        }
+    }
+}
+
+/// Code reachable from 0x200 or from a CALL target.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Function {
+    /// Entry address.
+    pub address: u16,
+    /// `main` or `sub_XXX`.
+    pub name: String,
+    /// Structured statements.
+    pub body: Vec<Stmt>,
+}
+
+fn function_name(address: u16) -> String {
+    if address == PROGRAM_START {
+        "main".to_string()
+    } else {
+        format!("sub_{:03X}", address)
+    }
+}
+
+// condition under which a skip instruction skips, and its negation
+fn skip_condition(instruction: Instruction) -> (String, String) {
+    match instruction {
+        Instruction::SeByte(x, kk) => (
+            format!("v{:x} == 0x{:02X}", x, kk),
+            format!("v{:x} != 0x{:02X}", x, kk),
+        ),
+        Instruction::SneByte(x, kk) => (
+            format!("v{:x} != 0x{:02X}", x, kk),
+            format!("v{:x} == 0x{:02X}", x, kk),
+        ),
+        Instruction::SeReg(x, y) => (
+            format!("v{:x} == v{:x}", x, y),
+            format!("v{:x} != v{:x}", x, y),
+        ),
+        Instruction::SneReg(x, y) => (
+            format!("v{:x} != v{:x}", x, y),
+            format!("v{:x} == v{:x}", x, y),
+        ),
+        Instruction::Skp(x) => (format!("key(v{:x})", x), format!("!key(v{:x})", x)),
+        Instruction::Sknp(x) => (format!("!key(v{:x})", x), format!("key(v{:x})", x)),
+        _ => unreachable!("not a skip: {:?}", instruction),
+    }
+}
+
+// pseudocode statement of an instruction
+fn statement(instruction: Instruction) -> String {
+    match instruction {
+        Instruction::Sys(nnn) => format!("sys(0x{:03X});", nnn),
+        Instruction::Cls => "clear();".to_string(),
+        Instruction::Ret => "return;".to_string(),
+        Instruction::Jp(nnn) => format!("goto L_{:03X};", nnn),
+        Instruction::Call(nnn) => format!("{}();", function_name(nnn)),
+        Instruction::LdByte(x, kk) => format!("v{:x} = 0x{:02X};", x, kk),
+        Instruction::AddByte(x, kk) => format!("v{:x} += 0x{:02X};", x, kk),
+        Instruction::LdReg(x, y) => format!("v{:x} = v{:x};", x, y),
+        Instruction::Or(x, y) => format!("v{:x} |= v{:x};", x, y),
+        Instruction::And(x, y) => format!("v{:x} &= v{:x};", x, y),
+        Instruction::Xor(x, y) => format!("v{:x} ^= v{:x};", x, y),
+        Instruction::AddReg(x, y) => format!("v{:x} += v{:x}; // vf = carry", x, y),
+        Instruction::Sub(x, y) => format!("v{:x} -= v{:x}; // vf = no borrow", x, y),
+        Instruction::Shr(x, y) => format!("v{:x} = v{:x} >> 1; // vf = bit shifted out", x, y),
+        Instruction::Subn(x, y) => format!("v{:x} = v{:x} - v{:x}; // vf = no borrow", x, y, x),
+        Instruction::Shl(x, y) => format!("v{:x} = v{:x} << 1; // vf = bit shifted out", x, y),
+        Instruction::LdI(nnn) => format!("i = 0x{:03X};", nnn),
+        Instruction::JpV0(nnn) => format!("jump(v0 + 0x{:03X}); // computed", nnn),
+        Instruction::Rnd(x, kk) => format!("v{:x} = random() & 0x{:02X};", x, kk),
+        Instruction::Drw(x, y, n) => format!("vf = draw(v{:x}, v{:x}, {});", x, y, n),
+        Instruction::LdVxDt(x) => format!("v{:x} = delay;", x),
+        Instruction::LdVxK(x) => format!("v{:x} = wait_key();", x),
+        Instruction::LdDtVx(x) => format!("delay = v{:x};", x),
+        Instruction::LdStVx(x) => format!("sound = v{:x};", x),
+        Instruction::AddI(x) => format!("i += v{:x};", x),
+        Instruction::LdF(x) => format!("i = font(v{:x});", x),
+        Instruction::LdB(x) => format!("bcd(v{:x});", x),
+        Instruction::Store(x) => format!("save(v0-v{:x});", x),
+        Instruction::Load(x) => format!("load(v0-v{:x});", x),
+        Instruction::Unknown(opcode) => format!("unknown(0x{:04X});", opcode),
+        // NOTE: skips are structured into conditions, this is only a fallback
+        _ => {
+            let (skip, _) = skip_condition(instruction);
+            format!("if ({}) skip;", skip)
+        }
+    }
+}
+
+// structures one function
+struct Lifter<'a> {
+    rom: &'a [u8],
+    // instruction addresses of the function
+    code: BTreeSet<u16>,
+}
+
+impl<'a> Lifter<'a> {
+    fn instruction(&self, address: u16) -> Option<Instruction> {
+        if self.code.contains(&address) {
+            disasm::read_opcode(self.rom, address).map(decode)
+        } else {
+            None
+        }
+    }
+
+    // backward JP to header within header..end, the farthest one closes the loop
+    fn loop_end(&self, header: u16, end: u16) -> Option<u16> {
+        (header..end)
+            .step_by(2)
+            .rev()
+            .find(|address| self.instruction(*address) == Some(Instruction::Jp(header)))
+    }
+
+    // statements of the instructions in start..end, enclosing is the (header, exit) of the loop
+    fn structure(&self, start: u16, end: u16, enclosing: Option<(u16, u16)>) -> Vec<Stmt> {
+        let mut stmts = Vec::new();
+        let mut address = start;
+        while address < end {
+            let instruction = match self.instruction(address) {
+                Some(instruction) => instruction,
+                // NOTE: data inside the range, continue with the next instruction
+                None => match self.code.range(address + 1..end).next() {
+                    Some(next) => {
+                        address = *next;
+                        continue;
+                    }
+                    None => break,
+                },
+            };
+
+            let is_header = enclosing.is_some_and(|(header, _)| header == address);
+            if let Some(jump) = self.loop_end(address, end).filter(|_| !is_header) {
+                let header = address;
+                let exit = jump + 2;
+                let condition = jump
+                    .checked_sub(2)
+                    .filter(|skip| *skip >= header)
+                    .and_then(|skip| self.instruction(skip))
+                    .filter(|instruction| instruction.is_skip());
+                match condition {
+                    // the jump back is executed when the skip is not taken
+                    Some(skip) => stmts.push(Stmt::DoWhile {
+                        address: header,
+                        body: self.structure(header, jump - 2, Some((header, exit))),
+                        condition: skip_condition(skip).1,
+                    }),
+                    None => stmts.push(Stmt::Loop {
+                        address: header,
+                        body: self.structure(header, jump, Some((header, exit))),
+                    }),
+                }
+                address = exit;
+                continue;
+            }
+
+            match instruction {
+                Instruction::Jp(target) => {
+                    stmts.push(self.jump(address, target, enclosing));
+                    address += 2;
+                }
+                _ if instruction.is_skip() => {
+                    let (taken, not_taken) = skip_condition(instruction);
+                    let next = address + 2;
+                    match self.instruction(next).filter(|_| next < end) {
+                        // skip over a forward jump: if, maybe with else
+                        Some(Instruction::Jp(target))
+                            if target >= next + 2
+                                && target <= end
+                                && self.jump(next, target, enclosing)
+                                    == Stmt::Goto(next, target) =>
+                        {
+                            let then_start = next + 2;
+                            let otherwise_end = target
+                                .checked_sub(2)
+                                .filter(|last| *last >= then_start)
+                                .and_then(|last| match self.instruction(last) {
+                                    Some(Instruction::Jp(end_if))
+                                        if end_if > target && end_if <= end =>
+                                    {
+                                        Some(end_if)
+                                    }
+                                    _ => None,
+                                });
+                            match otherwise_end {
+                                Some(end_if) => {
+                                    stmts.push(Stmt::If {
+                                        address,
+                                        condition: taken,
+                                        then: self.structure(then_start, target - 2, enclosing),
+                                        otherwise: self.structure(target, end_if, enclosing),
+                                    });
+                                    address = end_if;
+                                }
+                                None => {
+                                    stmts.push(Stmt::If {
+                                        address,
+                                        condition: taken,
+                                        then: self.structure(then_start, target, enclosing),
+                                        otherwise: Vec::new(),
+                                    });
+                                    address = target;
+                                }
+                            }
+                        }
+                        Some(Instruction::Jp(target)) => {
+                            stmts.push(Stmt::If {
+                                address,
+                                condition: not_taken,
+                                then: vec![self.jump(next, target, enclosing)],
+                                otherwise: Vec::new(),
+                            });
+                            address += 4;
+                        }
+                        // skip over a single instruction
+                        Some(next_instruction) if !next_instruction.is_skip() => {
+                            stmts.push(Stmt::If {
+                                address,
+                                condition: not_taken,
+                                then: vec![Stmt::Instruction(next, next_instruction)],
+                                otherwise: Vec::new(),
+                            });
+                            address += 4;
+                        }
+                        _ => {
+                            stmts.push(Stmt::If {
+                                address,
+                                condition: taken,
+                                then: vec![Stmt::Goto(address, address + 4)],
+                                otherwise: Vec::new(),
+                            });
+                            address += 2;
+                        }
+                    }
+                }
+                _ => {
+                    stmts.push(Stmt::Instruction(address, instruction));
+                    address += 2;
+                }
+            }
+        }
+        stmts
+    }
+
+    fn jump(&self, address: u16, target: u16, enclosing: Option<(u16, u16)>) -> Stmt {
+        match enclosing {
+            Some((header, _)) if target == header => Stmt::Continue(address),
+            Some((_, exit)) if target == exit => Stmt::Break(address),
+            _ => Stmt::Goto(address, target),
+        }
+    }
+}
+
+/// Lifts the functions of `rom`, loaded at 0x200, main first.
+pub fn lift(rom: &[u8]) -> Vec<Function> {
+    let cfg = cfg::recover(rom);
+    let mut entries: BTreeSet<u16> = cfg
+        .blocks
+        .values()
+        .flat_map(|block| block.edges.iter())
+        .filter(|edge| edge.kind == EdgeKind::Call && cfg.blocks.contains_key(&edge.target))
+        .map(|edge| edge.target)
+        .collect();
+    if cfg.blocks.contains_key(&PROGRAM_START) {
+        entries.insert(PROGRAM_START);
+    }
+
+    let mut functions = Vec::new();
+    for entry in entries.iter() {
+        // blocks reachable without calls, other functions are left by jumping
+        let mut code = BTreeSet::new();
+        let mut visited = BTreeSet::new();
+        let mut worklist = vec![*entry];
+        while let Some(start) = worklist.pop() {
+            let block = match cfg.blocks.get(&start) {
+                Some(block) if visited.insert(start) => block,
+                _ => continue,
+            };
+            code.extend(block.instructions.iter().map(|(address, _)| *address));
+            worklist.extend(
+                block
+                    .edges
+                    .iter()
+                    .filter(|edge| edge.kind != EdgeKind::Call)
+                    .filter(|edge| edge.target == *entry || !entries.contains(&edge.target))
+                    .map(|edge| edge.target),
+            );
+        }
+
+        // runs of consecutive instructions, the one at the entry first
+        let mut runs: Vec<(u16, u16)> = Vec::new();
+        for address in code.iter() {
+            match runs.last_mut() {
+                Some((_, end)) if *end == *address => *end += 2,
+                _ => runs.push((*address, address + 2)),
+            }
+        }
+        runs.sort_by_key(|(start, end)| !(*start <= *entry && *entry < *end));
+
+        let lifter = Lifter { rom, code };
+        let mut body = Vec::new();
+        for (start, end) in runs {
+            // NOTE: a jump to the run which follows in the output is not needed
+            if let Some(Stmt::Goto(_, target)) = body.last() {
+                if *target == start {
+                    body.pop();
+                }
+            }
+            body.extend(lifter.structure(start, end, None));
+        }
+        functions.push(Function {
+            address: *entry,
+            name: function_name(*entry),
+            body,
+        });
+    }
+    // NOTE: BTreeSet order puts main first, it has the lowest address
+    functions
+}
+
+fn goto_targets(stmts: &[Stmt], targets: &mut BTreeSet<u16>) {
+    for stmt in stmts {
+        match stmt {
+            Stmt::Goto(_, target) => {
+                targets.insert(*target);
+            }
+            Stmt::If {
+                then, otherwise, ..
+            } => {
+                goto_targets(then, targets);
+                goto_targets(otherwise, targets);
+            }
+            Stmt::Loop { body, .. } | Stmt::DoWhile { body, .. } => goto_targets(body, targets),
+            _ => (),
+        }
+    }
+}
+
+// name of a goto target, functions by their name
+fn label(target: u16, entries: &BTreeSet<u16>) -> String {
+    if entries.contains(&target) {
+        function_name(target)
+    } else {
+        format!("L_{:03X}", target)
+    }
+}
+
+// labels: addresses which get a label, entries: function addresses
+fn render(
+    out: &mut String,
+    stmts: &[Stmt],
+    labels: &BTreeSet<u16>,
+    entries: &BTreeSet<u16>,
+    depth: usize,
+) {
+    let indent = "    ".repeat(depth);
+    for stmt in stmts {
+        if labels.contains(&stmt.address()) {
+            writeln!(out, "{}:", label(stmt.address(), entries)).unwrap();
+        }
+        match stmt {
+            Stmt::Instruction(_, instruction) => {
+                writeln!(out, "{}{}", indent, statement(*instruction)).unwrap();
+            }
+            Stmt::If {
+                condition,
+                then,
+                otherwise,
+                ..
+            } => {
+                writeln!(out, "{}if ({}) {{", indent, condition).unwrap();
+                render(out, then, labels, entries, depth + 1);
+                if !otherwise.is_empty() {
+                    writeln!(out, "{}}} else {{", indent).unwrap();
+                    render(out, otherwise, labels, entries, depth + 1);
+                }
+                writeln!(out, "{}}}", indent).unwrap();
+            }
+            Stmt::Loop { body, .. } => {
+                writeln!(out, "{}loop {{", indent).unwrap();
+                render(out, body, labels, entries, depth + 1);
+                writeln!(out, "{}}}", indent).unwrap();
+            }
+            Stmt::DoWhile {
+                body, condition, ..
+            } => {
+                writeln!(out, "{}do {{", indent).unwrap();
+                render(out, body, labels, entries, depth + 1);
+                writeln!(out, "{}}} while ({});", indent, condition).unwrap();
+            }
+            Stmt::Goto(_, target) => {
+                writeln!(out, "{}goto {};", indent, label(*target, entries)).unwrap();
+            }
+            Stmt::Break(_) => writeln!(out, "{}break;", indent).unwrap(),
+            Stmt::Continue(_) => writeln!(out, "{}continue;", indent).unwrap(),
+        }
+    }
+}
+
+/// Structured pseudocode of `rom`, loaded at 0x200.
+pub fn decompile(rom: &[u8]) -> String {
+    let functions = lift(rom);
+    let entries: BTreeSet<u16> = functions.iter().map(|function| function.address).collect();
+
+    let mut out = String::new();
+    for (n, function) in functions.iter().enumerate() {
+        // NOTE: jumps to functions use their name, no label
+        let mut labels = BTreeSet::new();
+        goto_targets(&function.body, &mut labels);
+        labels.retain(|target| !entries.contains(target));
+
+        if n > 0 {
+            writeln!(out).unwrap();
+        }
+        writeln!(out, "// 0x{:03X}", function.address).unwrap();
+        writeln!(out, "fn {}() {{", function.name).unwrap();
+        render(&mut out, &function.body, &labels, &entries, 1);
+        writeln!(out, "}}").unwrap();
+    }
+    out
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(opcodes: &[u16]) -> Vec<u8> {
+        opcodes.iter().flat_map(|op| op.to_be_bytes()).collect()
+    }
+
+    #[test]
+    fn functions_and_loops() {
+        // main: v0 = 0; loop { sub(); } / sub: do { v0 += 1 } while (v0 != 5); return
+        let rom = rom(&[0x6000, 0x2206, 0x1202, 0x7001, 0x3005, 0x1206, 0x00EE]);
+        assert_eq!(
+            decompile(&rom),
+            "// 0x200
+fn main() {
+    v0 = 0x00;
+    loop {
+        sub_206();
+    }
+}
+
+// 0x206
+fn sub_206() {
+    do {
+        v0 += 0x01;
+    } while (v0 != 0x05);
+    return;
+}
+"
+        );
+    }
+
+    #[test]
+    fn if_else() {
+        // if (v0 == 1) { v1 = 1 } else { v1 = 2 }; if (v2 == 3) v3 = 4; halt
+        let rom = rom(&[
+            0x3001, 0x1208, 0x6101, 0x120A, 0x6102, 0x4203, 0x6304, 0x120E,
+        ]);
+        assert_eq!(
+            decompile(&rom),
+            "// 0x200
+fn main() {
+    if (v0 == 0x01) {
+        v1 = 0x01;
+    } else {
+        v1 = 0x02;
+    }
+    if (v2 == 0x03) {
+        v3 = 0x04;
+    }
+    loop {
+    }
+}
+"
+        );
+    }
+
+    #[test]
+    fn break_continue_and_goto() {
+        // loop { if (!key(v0)) break; if (v1 != 2) continue; v1 += 1 }; jp V0; ...
+        let rom = rom(&[0xE09E, 0x120C, 0x3102, 0x1200, 0x7101, 0x1200, 0xB300]);
+        let functions = lift(&rom);
+        assert_eq!(functions.len(), 1);
+        assert_eq!(
+            functions[0].body[0],
+            Stmt::Loop {
+                address: 0x200,
+                body: vec![
+                    Stmt::If {
+                        address: 0x200,
+                        condition: "!key(v0)".to_string(),
+                        then: vec![Stmt::Break(0x202)],
+                        otherwise: vec![],
+                    },
+                    Stmt::If {
+                        address: 0x204,
+                        condition: "v1 != 0x02".to_string(),
+                        then: vec![Stmt::Continue(0x206)],
+                        otherwise: vec![],
+                    },
+                    Stmt::Instruction(0x208, Instruction::AddByte(1, 1)),
+                ],
+            }
+        );
+        assert!(decompile(&rom).contains("    jump(v0 + 0x300); // computed\n"));
+    }
+
+    #[test]
+    fn large_rom() {
+        // LD V0, 0x00 past the end of memory, the rest of the ROM is never executed
+        let rom = rom(&[0x6000; 0x8000]);
+        let functions = lift(&rom);
+        assert_eq!(functions.len(), 1);
+        assert_eq!(functions[0].body.len(), 0x700);
+        assert_eq!(
+            functions[0].body.last(),
+            Some(&Stmt::Instruction(0xFFE, Instruction::LdByte(0, 0)))
+        );
+    }
+}
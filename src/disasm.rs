@@ -0,0 +1,433 @@
+//! Disassembler, in Cowgod's or Octo syntax, which tells code from data by following control
+//! flow from 0x200.
+use crate::instruction::{decode, Instruction};
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::str::FromStr;
+
+const PROGRAM_START: u16 = 0x200;
+const MEMORY_SIZE: usize = 0x1000;
+
+// NOTE: number of data bytes shown per line
+const DATA_BYTES_PER_LINE: usize = 4;
+
+/// Assembly syntax of the output.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Syntax {
+    /// Cowgod's Chip-8 Technical Reference.
+    Cowgod,
+    /// Octo assembly language.
+    Octo,
+}
+
+impl FromStr for Syntax {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Syntax, String> {
+        match s {
+            "cowgod" => Ok(Syntax::Cowgod),
+            "octo" => Ok(Syntax::Octo),
+            _ => Err(format!("unknown syntax: {}", s)),
+        }
+    }
+}
+
+/// Converts an opcode into its mnemonic, in Cowgod's syntax.
+pub fn disassemble(opcode: u16) -> String {
+    format_instruction(decode(opcode), Syntax::Cowgod, &BTreeMap::new())
+}
+
+/// Converts an instruction into its mnemonic, addresses with a label are printed as the label.
+pub fn format_instruction(
+    instruction: Instruction,
+    syntax: Syntax,
+    labels: &BTreeMap<u16, String>,
+) -> String {
+    let address = |nnn: u16| match labels.get(&nnn) {
+        Some(label) => label.clone(),
+        None => format!("0x{:03X}", nnn),
+    };
+
+    match syntax {
+        Syntax::Cowgod => match instruction {
+            Instruction::Sys(nnn) => format!("SYS 0x{:03X}", nnn),
+            Instruction::Cls => "CLS".to_string(),
+            Instruction::Ret => "RET".to_string(),
+            Instruction::Jp(nnn) => format!("JP {}", address(nnn)),
+            Instruction::Call(nnn) => format!("CALL {}", address(nnn)),
+            Instruction::SeByte(x, kk) => format!("SE V{:X}, 0x{:02X}", x, kk),
+            Instruction::SneByte(x, kk) => format!("SNE V{:X}, 0x{:02X}", x, kk),
+            Instruction::SeReg(x, y) => format!("SE V{:X}, V{:X}", x, y),
+            Instruction::LdByte(x, kk) => format!("LD V{:X}, 0x{:02X}", x, kk),
+            Instruction::AddByte(x, kk) => format!("ADD V{:X}, 0x{:02X}", x, kk),
+            Instruction::LdReg(x, y) => format!("LD V{:X}, V{:X}", x, y),
+            Instruction::Or(x, y) => format!("OR V{:X}, V{:X}", x, y),
+            Instruction::And(x, y) => format!("AND V{:X}, V{:X}", x, y),
+            Instruction::Xor(x, y) => format!("XOR V{:X}, V{:X}", x, y),
+            Instruction::AddReg(x, y) => format!("ADD V{:X}, V{:X}", x, y),
+            Instruction::Sub(x, y) => format!("SUB V{:X}, V{:X}", x, y),
+            Instruction::Shr(x, y) => format!("SHR V{:X}, V{:X}", x, y),
+            Instruction::Subn(x, y) => format!("SUBN V{:X}, V{:X}", x, y),
+            Instruction::Shl(x, y) => format!("SHL V{:X}, V{:X}", x, y),
+            Instruction::SneReg(x, y) => format!("SNE V{:X}, V{:X}", x, y),
+            Instruction::LdI(nnn) => format!("LD I, {}", address(nnn)),
+            Instruction::JpV0(nnn) => format!("JP V0, {}", address(nnn)),
+            Instruction::Rnd(x, kk) => format!("RND V{:X}, 0x{:02X}", x, kk),
+            Instruction::Drw(x, y, n) => format!("DRW V{:X}, V{:X}, {}", x, y, n),
+            Instruction::Skp(x) => format!("SKP V{:X}", x),
+            Instruction::Sknp(x) => format!("SKNP V{:X}", x),
+            Instruction::LdVxDt(x) => format!("LD V{:X}, DT", x),
+            Instruction::LdVxK(x) => format!("LD V{:X}, K", x),
+            Instruction::LdDtVx(x) => format!("LD DT, V{:X}", x),
+            Instruction::LdStVx(x) => format!("LD ST, V{:X}", x),
+            Instruction::AddI(x) => format!("ADD I, V{:X}", x),
+            Instruction::LdF(x) => format!("LD F, V{:X}", x),
+            Instruction::LdB(x) => format!("LD B, V{:X}", x),
+            Instruction::Store(x) => format!("LD [I], V{:X}", x),
+            Instruction::Load(x) => format!("LD V{:X}, [I]", x),
+            Instruction::Unknown(opcode) => format!("DW 0x{:04X}", opcode),
+        },
+        Syntax::Octo => match instruction {
+            // NOTE: Octo has no mnemonic for machine code routines, emit raw bytes
+            Instruction::Sys(nnn) => format!("0x{:02X} 0x{:02X}", nnn >> 8, nnn & 0xFF),
+            Instruction::Cls => "clear".to_string(),
+            Instruction::Ret => "return".to_string(),
+            Instruction::Jp(nnn) => format!("jump {}", address(nnn)),
+            Instruction::Call(nnn) => format!(":call {}", address(nnn)),
+            // NOTE: Octo conditions express when the next instruction is executed
+            Instruction::SeByte(x, kk) => format!("if v{:x} != 0x{:02X} then", x, kk),
+            Instruction::SneByte(x, kk) => format!("if v{:x} == 0x{:02X} then", x, kk),
+            Instruction::SeReg(x, y) => format!("if v{:x} != v{:x} then", x, y),
+            Instruction::LdByte(x, kk) => format!("v{:x} := 0x{:02X}", x, kk),
+            Instruction::AddByte(x, kk) => format!("v{:x} += 0x{:02X}", x, kk),
+            Instruction::LdReg(x, y) => format!("v{:x} := v{:x}", x, y),
+            Instruction::Or(x, y) => format!("v{:x} |= v{:x}", x, y),
+            Instruction::And(x, y) => format!("v{:x} &= v{:x}", x, y),
+            Instruction::Xor(x, y) => format!("v{:x} ^= v{:x}", x, y),
+            Instruction::AddReg(x, y) => format!("v{:x} += v{:x}", x, y),
+            Instruction::Sub(x, y) => format!("v{:x} -= v{:x}", x, y),
+            Instruction::Shr(x, y) => format!("v{:x} >>= v{:x}", x, y),
+            Instruction::Subn(x, y) => format!("v{:x} =- v{:x}", x, y),
+            Instruction::Shl(x, y) => format!("v{:x} <<= v{:x}", x, y),
+            Instruction::SneReg(x, y) => format!("if v{:x} == v{:x} then", x, y),
+            Instruction::LdI(nnn) => format!("i := {}", address(nnn)),
+            Instruction::JpV0(nnn) => format!("jump0 {}", address(nnn)),
+            Instruction::Rnd(x, kk) => format!("v{:x} := random 0x{:02X}", x, kk),
+            Instruction::Drw(x, y, n) => format!("sprite v{:x} v{:x} {}", x, y, n),
+            Instruction::Skp(x) => format!("if v{:x} -key then", x),
+            Instruction::Sknp(x) => format!("if v{:x} key then", x),
+            Instruction::LdVxDt(x) => format!("v{:x} := delay", x),
+            Instruction::LdVxK(x) => format!("v{:x} := key", x),
+            Instruction::LdDtVx(x) => format!("delay := v{:x}", x),
+            Instruction::LdStVx(x) => format!("buzzer := v{:x}", x),
+            Instruction::AddI(x) => format!("i += v{:x}", x),
+            Instruction::LdF(x) => format!("i := hex v{:x}", x),
+            Instruction::LdB(x) => format!("bcd v{:x}", x),
+            Instruction::Store(x) => format!("save v{:x}", x),
+            Instruction::Load(x) => format!("load v{:x}", x),
+            Instruction::Unknown(opcode) => {
+                format!("0x{:02X} 0x{:02X}", opcode >> 8, opcode & 0xFF)
+            }
+        },
+    }
+}
+
+// NOTE: ROM bytes which do not fit in memory (past 0xE00) are never executed
+pub(crate) fn read_opcode(rom: &[u8], address: u16) -> Option<u16> {
+    if address as usize + 1 >= MEMORY_SIZE {
+        return None;
+    }
+    let index = address.checked_sub(PROGRAM_START)? as usize;
+    let hi = *rom.get(index)? as u16;
+    let low = *rom.get(index + 1)? as u16;
+    Some((hi << 8) | low)
+}
+
+/// Addresses executed after the instruction at `address`. Computed jumps (`JP V0`) cannot be
+/// followed statically and have none.
+pub fn successors(instruction: Instruction, address: u16) -> Vec<u16> {
+    let next = address.checked_add(2);
+    match instruction {
+        Instruction::Jp(nnn) => vec![nnn],
+        Instruction::Call(nnn) => Some(nnn).into_iter().chain(next).collect(),
+        Instruction::Ret | Instruction::JpV0(_) => vec![],
+        Instruction::Sys(_) | Instruction::Unknown(_) => vec![],
+        _ if instruction.is_skip() => next.into_iter().chain(address.checked_add(4)).collect(),
+        _ => next.into_iter().collect(),
+    }
+}
+
+/// Follows control flow from 0x200 and returns the addresses where instructions start.
+pub fn trace_code(rom: &[u8]) -> BTreeSet<u16> {
+    let mut code = BTreeSet::new();
+    let mut worklist = vec![PROGRAM_START];
+
+    while let Some(address) = worklist.pop() {
+        if code.contains(&address) {
+            continue;
+        }
+        let instruction = match read_opcode(rom, address) {
+            Some(opcode) => decode(opcode),
+            None => continue,
+        };
+        // SYS and unknown opcodes are most likely data reached by a fall through
+        if let Instruction::Sys(_) | Instruction::Unknown(_) = instruction {
+            continue;
+        }
+
+        code.insert(address);
+        worklist.extend(successors(instruction, address));
+    }
+    code
+}
+
+/// Labels of the jump and call targets of `code`.
+pub fn make_labels(rom: &[u8], code: &BTreeSet<u16>) -> BTreeMap<u16, String> {
+    let mut labels = BTreeMap::new();
+    for address in code.iter() {
+        match read_opcode(rom, *address).map(decode) {
+            Some(Instruction::Call(nnn)) => {
+                labels.insert(nnn, format!("sub_{:03X}", nnn));
+            }
+            Some(Instruction::Jp(nnn)) => {
+                // NOTE: subroutine label takes precedence
+                labels.entry(nnn).or_insert(format!("L_{:03X}", nnn));
+            }
+            _ => (),
+        }
+    }
+    labels
+}
+
+fn format_label(label: &str, syntax: Syntax) -> String {
+    match syntax {
+        Syntax::Cowgod => format!("{}:", label),
+        Syntax::Octo => format!(": {}", label),
+    }
+}
+
+fn format_data(bytes: &[u8], syntax: Syntax) -> String {
+    let values: Vec<String> = bytes.iter().map(|b| format!("0x{:02X}", b)).collect();
+    match syntax {
+        Syntax::Cowgod => format!("DB {}", values.join(", ")),
+        Syntax::Octo => values.join(" "),
+    }
+}
+
+/// Disassembles a ROM, separating code from data by following control flow from 0x200.
+pub fn disassemble_rom(rom: &[u8], syntax: Syntax) -> String {
+    disassemble_rom_with_code(rom, syntax, &trace_code(rom))
+}
+
+/// Disassembles a ROM, treating the given instruction start addresses as code.
+pub fn disassemble_rom_with_code(rom: &[u8], syntax: Syntax, code: &BTreeSet<u16>) -> String {
+    let labels = make_labels(rom, code);
+    let end = (PROGRAM_START as usize + rom.len()).min(MEMORY_SIZE);
+
+    let mut lines = Vec::new();
+    let mut address = PROGRAM_START as usize;
+    while address < end {
+        if let Some(label) = labels.get(&(address as u16)) {
+            lines.push(format_label(label, syntax));
+        }
+
+        let opcode = read_opcode(rom, address as u16);
+        match opcode {
+            Some(opcode) if code.contains(&(address as u16)) => {
+                let mnemonic = format_instruction(decode(opcode), syntax, &labels);
+                lines.push(format!(
+                    "0x{:03X}: {:<12}{}",
+                    address,
+                    format!("{:04X}", opcode),
+                    mnemonic
+                ));
+                address += 2;
+            }
+            _ => {
+                // group data bytes until next code or label
+                let mut data_end = address + 1;
+                while data_end < end
+                    && data_end - address < DATA_BYTES_PER_LINE
+                    && !code.contains(&(data_end as u16))
+                    && !labels.contains_key(&(data_end as u16))
+                {
+                    data_end += 1;
+                }
+                let bytes =
+                    &rom[(address - PROGRAM_START as usize)..(data_end - PROGRAM_START as usize)];
+                let raw: Vec<String> = bytes.iter().map(|b| format!("{:02X}", b)).collect();
+                lines.push(format!(
+                    "0x{:03X}: {:<12}{}",
+                    address,
+                    raw.join(" "),
+                    format_data(bytes, syntax)
+                ));
+                address = data_end;
+            }
+        }
+    }
+
+    let mut listing = lines.join("\n");
+    listing.push('\n');
+    listing
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_disassemble_cowgod() {
+        assert_eq!(disassemble(0x00E0), "CLS");
+        assert_eq!(disassemble(0x00EE), "RET");
+        assert_eq!(disassemble(0x0123), "SYS 0x123");
+        assert_eq!(disassemble(0x1234), "JP 0x234");
+        assert_eq!(disassemble(0x2345), "CALL 0x345");
+        assert_eq!(disassemble(0x3A12), "SE VA, 0x12");
+        assert_eq!(disassemble(0x4B34), "SNE VB, 0x34");
+        assert_eq!(disassemble(0x5120), "SE V1, V2");
+        assert_eq!(disassemble(0x6F01), "LD VF, 0x01");
+        assert_eq!(disassemble(0x7001), "ADD V0, 0x01");
+        assert_eq!(disassemble(0x8120), "LD V1, V2");
+        assert_eq!(disassemble(0x8121), "OR V1, V2");
+        assert_eq!(disassemble(0x8122), "AND V1, V2");
+        assert_eq!(disassemble(0x8123), "XOR V1, V2");
+        assert_eq!(disassemble(0x8124), "ADD V1, V2");
+        assert_eq!(disassemble(0x8125), "SUB V1, V2");
+        assert_eq!(disassemble(0x8126), "SHR V1, V2");
+        assert_eq!(disassemble(0x8127), "SUBN V1, V2");
+        assert_eq!(disassemble(0x812E), "SHL V1, V2");
+        assert_eq!(disassemble(0x9120), "SNE V1, V2");
+        assert_eq!(disassemble(0xA2F0), "LD I, 0x2F0");
+        assert_eq!(disassemble(0xB300), "JP V0, 0x300");
+        assert_eq!(disassemble(0xC10F), "RND V1, 0x0F");
+        assert_eq!(disassemble(0xD125), "DRW V1, V2, 5");
+        assert_eq!(disassemble(0xE39E), "SKP V3");
+        assert_eq!(disassemble(0xE3A1), "SKNP V3");
+        assert_eq!(disassemble(0xF407), "LD V4, DT");
+        assert_eq!(disassemble(0xF40A), "LD V4, K");
+        assert_eq!(disassemble(0xF415), "LD DT, V4");
+        assert_eq!(disassemble(0xF418), "LD ST, V4");
+        assert_eq!(disassemble(0xF41E), "ADD I, V4");
+        assert_eq!(disassemble(0xF429), "LD F, V4");
+        assert_eq!(disassemble(0xF433), "LD B, V4");
+        assert_eq!(disassemble(0xF455), "LD [I], V4");
+        assert_eq!(disassemble(0xF465), "LD V4, [I]");
+    }
+
+    #[test]
+    fn test_disassemble_unknown_opcode() {
+        assert_eq!(disassemble(0x5121), "DW 0x5121");
+        assert_eq!(disassemble(0x812F), "DW 0x812F");
+        assert_eq!(disassemble(0xE100), "DW 0xE100");
+        assert_eq!(disassemble(0xF1FF), "DW 0xF1FF");
+    }
+
+    #[test]
+    fn test_format_octo() {
+        let octo = |opcode| format_instruction(decode(opcode), Syntax::Octo, &BTreeMap::new());
+        assert_eq!(octo(0x00E0), "clear");
+        assert_eq!(octo(0x00EE), "return");
+        assert_eq!(octo(0x1234), "jump 0x234");
+        assert_eq!(octo(0x2345), ":call 0x345");
+        assert_eq!(octo(0x3A12), "if va != 0x12 then");
+        assert_eq!(octo(0x4A12), "if va == 0x12 then");
+        assert_eq!(octo(0x5120), "if v1 != v2 then");
+        assert_eq!(octo(0x9120), "if v1 == v2 then");
+        assert_eq!(octo(0x6F01), "vf := 0x01");
+        assert_eq!(octo(0x8127), "v1 =- v2");
+        assert_eq!(octo(0x812E), "v1 <<= v2");
+        assert_eq!(octo(0xB300), "jump0 0x300");
+        assert_eq!(octo(0xC10F), "v1 := random 0x0F");
+        assert_eq!(octo(0xD125), "sprite v1 v2 5");
+        assert_eq!(octo(0xE39E), "if v3 -key then");
+        assert_eq!(octo(0xE3A1), "if v3 key then");
+        assert_eq!(octo(0xF418), "buzzer := v4");
+        assert_eq!(octo(0xF429), "i := hex v4");
+        assert_eq!(octo(0xF433), "bcd v4");
+        assert_eq!(octo(0xF455), "save v4");
+        assert_eq!(octo(0xF465), "load v4");
+        assert_eq!(octo(0x5121), "0x51 0x21");
+    }
+
+    #[test]
+    fn test_trace_code_skips_data() {
+        let rom = [
+            0x22, 0x06, // 0x200: CALL 0x206
+            0x12, 0x04, // 0x202: JP 0x204
+            0x12, 0x04, // 0x204: JP 0x204
+            0xA2, 0x0A, // 0x206: LD I, 0x20A
+            0x00, 0xEE, // 0x208: RET
+            0xFF, 0x81, // 0x20A: sprite data
+        ];
+        let code = trace_code(&rom);
+        let expected: BTreeSet<u16> = [0x200, 0x202, 0x204, 0x206, 0x208]
+            .iter()
+            .copied()
+            .collect();
+        assert_eq!(code, expected);
+    }
+
+    #[test]
+    fn test_trace_code_follows_skips() {
+        let rom = [
+            0x30, 0x00, // 0x200: SE V0, 0x00
+            0x12, 0x06, // 0x202: JP 0x206
+            0x12, 0x04, // 0x204: JP 0x204
+            0x12, 0x06, // 0x206: JP 0x206
+        ];
+        let code = trace_code(&rom);
+        assert_eq!(code.len(), 4);
+    }
+
+    #[test]
+    fn test_large_rom_is_truncated_to_memory() {
+        // LD V0, 0x00 up to the end of the address space
+        let rom: Vec<u8> = [0x60, 0x00].iter().copied().cycle().take(0x10000).collect();
+        let code = trace_code(&rom);
+        assert_eq!(code.len(), 0x700);
+        assert_eq!(code.iter().next_back(), Some(&0xFFE));
+        let listing = disassemble_rom(&rom, Syntax::Cowgod);
+        assert_eq!(listing.lines().count(), 0x700);
+        assert!(listing.ends_with("0xFFE: 6000        LD V0, 0x00\n"));
+
+        assert_eq!(successors(decode(0x6000), 0xFFFE), vec![]);
+        assert_eq!(successors(decode(0x3000), 0xFFFC), vec![0xFFFE]);
+        assert_eq!(successors(decode(0x2300), 0xFFFE), vec![0x300]);
+    }
+
+    #[test]
+    fn test_disassemble_rom() {
+        let rom = [
+            0x22, 0x04, // 0x200: CALL 0x204
+            0x12, 0x02, // 0x202: JP 0x202
+            0xA2, 0x08, // 0x204: LD I, 0x208
+            0x00, 0xEE, // 0x206: RET
+            0xFF, 0x81, 0x42, // 0x208: data
+        ];
+        assert_eq!(
+            disassemble_rom(&rom, Syntax::Cowgod),
+            "\
+0x200: 2204        CALL sub_204
+L_202:
+0x202: 1202        JP L_202
+sub_204:
+0x204: A208        LD I, 0x208
+0x206: 00EE        RET
+0x208: FF 81 42    DB 0xFF, 0x81, 0x42
+"
+        );
+        assert_eq!(
+            disassemble_rom(&rom, Syntax::Octo),
+            "\
+0x200: 2204        :call sub_204
+: L_202
+0x202: 1202        jump L_202
+: sub_204
+0x204: A208        i := 0x208
+0x206: 00EE        return
+0x208: FF 81 42    0xFF 0x81 0x42
+"
+        );
+    }
+}
@@ -0,0 +1,485 @@
+//! Expressions over the machine state, for conditional breakpoints and tracepoints.
+//!
+//! ```text
+//! pc == 0x2A4 && v3 > 10 && mem[i] == 0
+//! ```
+//!
+//! Numbers are decimal, or hex with `0x`. Variables: `v0`-`vf`, `i`, `pc`, `sp`, `dt`, `st`,
+//! `key[N]` (1 if pressed), `mem[ADDR]` (wraps at 4KB). Operators, loosest first, as in C:
+//!
+//! ```text
+//! ||  &&  |  ^  &  == !=  < <= > >=  + -  * / %  unary ! - ~
+//! ```
+//!
+//! Comparisons and logical operators give 1 or 0, any non-zero value is true.
+//!
+//! Templates interpolate expressions into messages: `"V3={v3} at {pc:x}"`, `:x` prints hex.
+use crate::bus::Bus;
+use crate::chip8::Chip8;
+use crate::host::Random;
+
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Variable {
+    Register(u8),
+    Index,
+    ProgramCounter,
+    StackPointer,
+    DelayTimer,
+    SoundTimer,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Unary {
+    Not,
+    Negate,
+    Complement,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Binary {
+    Or,
+    And,
+    BitOr,
+    BitXor,
+    BitAnd,
+    Equal,
+    NotEqual,
+    Less,
+    LessEqual,
+    Greater,
+    GreaterEqual,
+    Add,
+    Subtract,
+    Multiply,
+    Divide,
+    Remainder,
+}
+
+// binary operators by precedence level, loosest first
+const BINARY_LEVELS: [&[(&str, Binary)]; 8] = [
+    &[("||", Binary::Or)],
+    &[("&&", Binary::And)],
+    &[("|", Binary::BitOr)],
+    &[("^", Binary::BitXor)],
+    &[("&", Binary::BitAnd)],
+    &[("==", Binary::Equal), ("!=", Binary::NotEqual)],
+    &[
+        ("<=", Binary::LessEqual),
+        (">=", Binary::GreaterEqual),
+        ("<", Binary::Less),
+        (">", Binary::Greater),
+    ],
+    &[("+", Binary::Add), ("-", Binary::Subtract)],
+];
+
+const MULTIPLICATIVE: [(&str, Binary); 3] = [
+    ("*", Binary::Multiply),
+    ("/", Binary::Divide),
+    ("%", Binary::Remainder),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Node {
+    Number(i64),
+    Variable(Variable),
+    Memory(Box<Node>),
+    Key(Box<Node>),
+    Unary(Unary, Box<Node>),
+    Binary(Binary, Box<Node>, Box<Node>),
+}
+
+// NOTE: two-character operators come first so "<=" is not read as "<"
+const OPERATORS: [&str; 23] = [
+    "||", "&&", "==", "!=", "<=", ">=", "|", "^", "&", "<", ">", "+", "-", "*", "/", "%", "!", "~",
+    "(", ")", "[", "]", "=",
+];
+
+fn tokenize(s: &str) -> Result<Vec<String>, String> {
+    let mut tokens = Vec::new();
+    let mut rest = s.trim_start();
+    while !rest.is_empty() {
+        let word_len = rest
+            .find(|c: char| !c.is_ascii_alphanumeric() && c != '_')
+            .unwrap_or(rest.len());
+        let len = if word_len > 0 {
+            word_len
+        } else if let Some(operator) = OPERATORS.iter().find(|op| rest.starts_with(*op)) {
+            operator.len()
+        } else {
+            return Err(format!(
+                "unexpected character: {}",
+                rest.chars().next().unwrap()
+            ));
+        };
+        tokens.push(rest[..len].to_string());
+        rest = rest[len..].trim_start();
+    }
+    Ok(tokens)
+}
+
+struct Parser {
+    tokens: Vec<String>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&str> {
+        self.tokens.get(self.position).map(|t| t.as_str())
+    }
+
+    fn next(&mut self) -> Result<String, String> {
+        let token = self
+            .tokens
+            .get(self.position)
+            .cloned()
+            .ok_or_else(|| "unexpected end of expression".to_string())?;
+        self.position += 1;
+        Ok(token)
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), String> {
+        match self.next()? {
+            token if token == expected => Ok(()),
+            token => Err(format!("expected '{}', found '{}'", expected, token)),
+        }
+    }
+
+    fn binary(&mut self, level: usize) -> Result<Node, String> {
+        let operators: &[(&str, Binary)] = match BINARY_LEVELS.get(level) {
+            Some(operators) => operators,
+            None => &MULTIPLICATIVE,
+        };
+        let operand = |parser: &mut Parser| {
+            if level < BINARY_LEVELS.len() {
+                parser.binary(level + 1)
+            } else {
+                parser.unary()
+            }
+        };
+        let mut left = operand(self)?;
+        while let Some(&(_, op)) = operators.iter().find(|(s, _)| Some(*s) == self.peek()) {
+            self.position += 1;
+            let right = operand(self)?;
+            left = Node::Binary(op, Box::new(left), Box::new(right));
+        }
+        Ok(left)
+    }
+
+    fn unary(&mut self) -> Result<Node, String> {
+        let op = match self.peek() {
+            Some("!") => Unary::Not,
+            Some("-") => Unary::Negate,
+            Some("~") => Unary::Complement,
+            _ => return self.primary(),
+        };
+        self.position += 1;
+        Ok(Node::Unary(op, Box::new(self.unary()?)))
+    }
+
+    fn primary(&mut self) -> Result<Node, String> {
+        let token = self.next()?;
+        let lower = token.to_ascii_lowercase();
+        let node = match lower.as_str() {
+            "(" => {
+                let node = self.binary(0)?;
+                self.expect(")")?;
+                node
+            }
+            "mem" | "key" => {
+                self.expect("[")?;
+                let address = Box::new(self.binary(0)?);
+                self.expect("]")?;
+                if lower == "mem" {
+                    Node::Memory(address)
+                } else {
+                    Node::Key(address)
+                }
+            }
+            "i" => Node::Variable(Variable::Index),
+            "pc" => Node::Variable(Variable::ProgramCounter),
+            "sp" => Node::Variable(Variable::StackPointer),
+            "dt" => Node::Variable(Variable::DelayTimer),
+            "st" => Node::Variable(Variable::SoundTimer),
+            register if register.len() == 2 && register.starts_with('v') => {
+                match u8::from_str_radix(&register[1..], 16) {
+                    Ok(x) => Node::Variable(Variable::Register(x)),
+                    Err(_) => return Err(format!("unknown variable: {}", token)),
+                }
+            }
+            number if number.starts_with(|c: char| c.is_ascii_digit()) => {
+                let value = match number.strip_prefix("0x") {
+                    Some(hex) => i64::from_str_radix(hex, 16),
+                    None => number.parse(),
+                };
+                Node::Number(value.map_err(|_| format!("invalid number: {}", token))?)
+            }
+            "=" => return Err("use '==' to compare".to_string()),
+            _ => return Err(format!("unexpected '{}'", token)),
+        };
+        Ok(node)
+    }
+}
+
+fn truth(b: bool) -> i64 {
+    b as i64
+}
+
+impl Node {
+    fn eval<R: Random, B: Bus>(&self, chip8: &Chip8<R, B>) -> Result<i64, String> {
+        let value = match self {
+            Node::Number(n) => *n,
+            Node::Variable(variable) => match *variable {
+                Variable::Register(x) => chip8.read_register(x) as i64,
+                Variable::Index => chip8.index_register() as i64,
+                Variable::ProgramCounter => chip8.program_counter() as i64,
+                Variable::StackPointer => chip8.stack_pointer() as i64,
+                Variable::DelayTimer => chip8.delay_timer() as i64,
+                Variable::SoundTimer => chip8.sound_timer() as i64,
+            },
+            Node::Memory(address) => chip8.read_memory(address.eval(chip8)? as u16) as i64,
+            Node::Key(key) => {
+                let key = key.eval(chip8)?;
+                match chip8.keyboard.get(key as usize) {
+                    Some(&pressed) if key >= 0 => truth(pressed),
+                    _ => return Err(format!("no key {}", key)),
+                }
+            }
+            Node::Unary(op, operand) => {
+                let value = operand.eval(chip8)?;
+                match op {
+                    Unary::Not => truth(value == 0),
+                    Unary::Negate => value.wrapping_neg(),
+                    Unary::Complement => !value,
+                }
+            }
+            // NOTE: && and || short-circuit
+            Node::Binary(Binary::And, left, right) => {
+                truth(left.eval(chip8)? != 0 && right.eval(chip8)? != 0)
+            }
+            Node::Binary(Binary::Or, left, right) => {
+                truth(left.eval(chip8)? != 0 || right.eval(chip8)? != 0)
+            }
+            Node::Binary(op, left, right) => {
+                let (a, b) = (left.eval(chip8)?, right.eval(chip8)?);
+                match op {
+                    Binary::BitOr => a | b,
+                    Binary::BitXor => a ^ b,
+                    Binary::BitAnd => a & b,
+                    Binary::Equal => truth(a == b),
+                    Binary::NotEqual => truth(a != b),
+                    Binary::Less => truth(a < b),
+                    Binary::LessEqual => truth(a <= b),
+                    Binary::Greater => truth(a > b),
+                    Binary::GreaterEqual => truth(a >= b),
+                    Binary::Add => a.wrapping_add(b),
+                    Binary::Subtract => a.wrapping_sub(b),
+                    Binary::Multiply => a.wrapping_mul(b),
+                    Binary::Divide | Binary::Remainder if b == 0 => {
+                        return Err("division by zero".to_string())
+                    }
+                    Binary::Divide => a.wrapping_div(b),
+                    Binary::Remainder => a.wrapping_rem(b),
+                    Binary::And | Binary::Or => unreachable!(),
+                }
+            }
+        };
+        Ok(value)
+    }
+}
+
+/// Parsed expression, displayed as written.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Expr {
+    source: String,
+    node: Node,
+}
+
+impl Expr {
+    /// Evaluates the expression against the machine state.
+    pub fn eval<R: Random, B: Bus>(&self, chip8: &Chip8<R, B>) -> Result<i64, String> {
+        self.node.eval(chip8)
+    }
+
+    /// Evaluates the expression, non-zero is true.
+    pub fn is_true<R: Random, B: Bus>(&self, chip8: &Chip8<R, B>) -> Result<bool, String> {
+        Ok(self.eval(chip8)? != 0)
+    }
+}
+
+impl FromStr for Expr {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Expr, String> {
+        let mut parser = Parser {
+            tokens: tokenize(s)?,
+            position: 0,
+        };
+        let node = parser.binary(0)?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("unexpected '{}'", token));
+        }
+        Ok(Expr {
+            source: s.trim().to_string(),
+            node,
+        })
+    }
+}
+
+impl fmt::Display for Expr {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[derive(Clone, Debug, PartialEq)]
+enum Segment {
+    Text(String),
+    Decimal(Expr),
+    Hex(Expr),
+}
+
+/// Message with {expr} and {expr:x} placeholders, "{{" and "}}" are literal braces.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Template {
+    source: String,
+    segments: Vec<Segment>,
+}
+
+impl Template {
+    /// Replaces the placeholders with the values of their expressions.
+    pub fn format<R: Random, B: Bus>(&self, chip8: &Chip8<R, B>) -> Result<String, String> {
+        let mut message = String::new();
+        for segment in self.segments.iter() {
+            match segment {
+                Segment::Text(text) => message.push_str(text),
+                Segment::Decimal(expr) => message.push_str(&expr.eval(chip8)?.to_string()),
+                Segment::Hex(expr) => message.push_str(&format!("{:X}", expr.eval(chip8)?)),
+            }
+        }
+        Ok(message)
+    }
+}
+
+impl FromStr for Template {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Template, String> {
+        let mut segments = Vec::new();
+        let mut text = String::new();
+        let mut rest = s;
+        while let Some(c) = rest.chars().next() {
+            if rest.starts_with("{{") || rest.starts_with("}}") {
+                text.push(c);
+                rest = &rest[2..];
+            } else if c == '{' {
+                let end = rest
+                    .find('}')
+                    .ok_or_else(|| format!("unterminated '{{' in: {}", s))?;
+                let placeholder = &rest[1..end];
+                if !text.is_empty() {
+                    segments.push(Segment::Text(std::mem::take(&mut text)));
+                }
+                segments.push(match placeholder.strip_suffix(":x") {
+                    Some(expr) => Segment::Hex(expr.parse()?),
+                    None => Segment::Decimal(placeholder.parse()?),
+                });
+                rest = &rest[(end + 1)..];
+            } else if c == '}' {
+                return Err(format!("unmatched '}}' in: {}", s));
+            } else {
+                text.push(c);
+                rest = &rest[c.len_utf8()..];
+            }
+        }
+        if !text.is_empty() {
+            segments.push(Segment::Text(text));
+        }
+        Ok(Template {
+            source: s.to_string(),
+            segments,
+        })
+    }
+}
+
+impl fmt::Display for Template {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.source)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn machine() -> Chip8 {
+        // LD V3, 11; LD I, 0x300
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&[0x63, 0x0B, 0xA3, 0x00]);
+        chip8.step();
+        chip8.step();
+        chip8.keyboard[0xA] = true;
+        chip8
+    }
+
+    fn eval(s: &str) -> Result<i64, String> {
+        s.parse::<Expr>()?.eval(&machine())
+    }
+
+    #[test]
+    fn variables() {
+        assert_eq!(eval("pc"), Ok(0x204));
+        assert_eq!(eval("V3"), Ok(11));
+        assert_eq!(eval("i"), Ok(0x300));
+        assert_eq!(eval("sp + dt + st"), Ok(0));
+        assert_eq!(eval("mem[pc - 4]"), Ok(0x63));
+        assert_eq!(eval("mem[0x1000]"), Ok(0xF0));
+        assert_eq!(eval("key[0xA] && !key[0]"), Ok(1));
+        assert!(eval("key[16]").is_err());
+    }
+
+    #[test]
+    fn operators() {
+        assert_eq!(eval("1 + 2 * 3"), Ok(7));
+        assert_eq!(eval("(1 + 2) * 3"), Ok(9));
+        assert_eq!(eval("10 - 2 - 3"), Ok(5));
+        assert_eq!(eval("7 / 2 + 7 % 2"), Ok(4));
+        assert_eq!(eval("0xF0 & 0x3C | 1 ^ 3"), Ok(0x32));
+        assert_eq!(eval("-v3 + ~0"), Ok(-12));
+        assert_eq!(eval("1 < 2 == 2 >= 2"), Ok(1));
+        assert_eq!(eval("0 || 3 && 4 != 4"), Ok(0));
+        assert!(eval("1 / (v3 - 11)").is_err());
+        // NOTE: short-circuit skips the error
+        assert_eq!(eval("0 && 1 / 0"), Ok(0));
+    }
+
+    #[test]
+    fn condition() {
+        let expr: Expr = "pc == 0x204 && v3 > 10 && mem[i] == 0".parse().unwrap();
+        assert_eq!(expr.is_true(&machine()), Ok(true));
+        assert_eq!(expr.to_string(), "pc == 0x204 && v3 > 10 && mem[i] == 0");
+    }
+
+    #[test]
+    fn parse_errors() {
+        for s in [
+            "", "v3 >", "v3 = 1", "vg", "(1", "mem 1", "1 2", "0xZ", "v3 $ 1",
+        ] {
+            assert!(s.parse::<Expr>().is_err(), "{}", s);
+        }
+    }
+
+    #[test]
+    fn template() {
+        let template: Template = "V3={v3} I={i:x} {{literal}}".parse().unwrap();
+        assert_eq!(
+            template.format(&machine()),
+            Ok("V3=11 I=300 {literal}".to_string())
+        );
+        assert!("{v3".parse::<Template>().is_err());
+        assert!("v3}".parse::<Template>().is_err());
+        assert!("{v3 >}".parse::<Template>().is_err());
+    }
+}
@@ -0,0 +1,468 @@
+// SDL2 window, keyboard input and capture hotkeys
+use crate::{read_rom, Opts};
+
+use chip8_emulator::capture::{self, Palette, Recorder};
+use chip8_emulator::coverage::Coverage;
+use chip8_emulator::debugger::Debugger;
+use chip8_emulator::disasm::Syntax;
+use chip8_emulator::gdb::GdbStub;
+use chip8_emulator::lint::Linter;
+use chip8_emulator::profile::Profiler;
+use chip8_emulator::trace::Tracer;
+use chip8_emulator::Chip8;
+
+use sdl2::event::Event;
+use sdl2::keyboard::Keycode;
+use sdl2::pixels::PixelFormatEnum;
+use sdl2::EventPump;
+
+use log::{error, info};
+
+// requests to the frontend raised by user input
+#[derive(Default)]
+struct FrontendRequest {
+    quit: bool,
+    screenshot: bool,
+    debug_break: bool,
+}
+
+fn handle_user_input(chip8: &mut Chip8, event_pump: &mut EventPump) -> FrontendRequest {
+    let mut request = FrontendRequest::default();
+    for event in event_pump.poll_iter() {
+        match event {
+            Event::Quit { .. }
+            | Event::KeyDown {
+                keycode: Some(Keycode::Escape),
+                ..
+            } => request.quit = true,
+
+            Event::KeyDown {
+                keycode: Some(Keycode::F12),
+                repeat: false,
+                ..
+            } => request.screenshot = true,
+
+            Event::KeyDown {
+                keycode: Some(Keycode::F9),
+                repeat: false,
+                ..
+            } => request.debug_break = true,
+
+            Event::KeyDown {
+                keycode: Some(Keycode::Num1),
+                ..
+            } => chip8.keyboard[0x1] = true,
+            Event::KeyUp {
+                keycode: Some(Keycode::Num1),
+                repeat: false,
+                ..
+            } => chip8.keyboard[0x1] = false,
+
+            Event::KeyDown {
+                keycode: Some(Keycode::Num2),
+                repeat: false,
+                ..
+            } => chip8.keyboard[0x2] = true,
+            Event::KeyUp {
+                keycode: Some(Keycode::Num2),
+                repeat: false,
+                ..
+            } => chip8.keyboard[0x2] = false,
+
+            Event::KeyDown {
+                keycode: Some(Keycode::Num3),
+                ..
+            } => chip8.keyboard[0x3] = true,
+            Event::KeyUp {
+                keycode: Some(Keycode::Num3),
+                repeat: false,
+                ..
+            } => chip8.keyboard[0x3] = false,
+
+            Event::KeyDown {
+                keycode: Some(Keycode::Num4),
+                ..
+            } => chip8.keyboard[0xC] = true,
+            Event::KeyUp {
+                keycode: Some(Keycode::Num4),
+                repeat: false,
+                ..
+            } => chip8.keyboard[0xC] = false,
+
+            Event::KeyDown {
+                keycode: Some(Keycode::Q),
+                ..
+            } => chip8.keyboard[0x4] = true,
+            Event::KeyUp {
+                keycode: Some(Keycode::Q),
+                repeat: false,
+                ..
+            } => chip8.keyboard[0x4] = false,
+
+            Event::KeyDown {
+                keycode: Some(Keycode::W),
+                ..
+            } => chip8.keyboard[0x5] = true,
+            Event::KeyUp {
+                keycode: Some(Keycode::W),
+                repeat: false,
+                ..
+            } => chip8.keyboard[0x5] = false,
+
+            Event::KeyDown {
+                keycode: Some(Keycode::E),
+                ..
+            } => chip8.keyboard[0x6] = true,
+            Event::KeyUp {
+                keycode: Some(Keycode::E),
+                repeat: false,
+                ..
+            } => chip8.keyboard[0x6] = false,
+
+            Event::KeyDown {
+                keycode: Some(Keycode::R),
+                ..
+            } => chip8.keyboard[0xD] = true,
+            Event::KeyUp {
+                keycode: Some(Keycode::R),
+                repeat: false,
+                ..
+            } => chip8.keyboard[0xD] = false,
+
+            Event::KeyDown {
+                keycode: Some(Keycode::A),
+                ..
+            } => chip8.keyboard[0x7] = true,
+            Event::KeyUp {
+                keycode: Some(Keycode::A),
+                repeat: false,
+                ..
+            } => chip8.keyboard[0x7] = false,
+
+            Event::KeyDown {
+                keycode: Some(Keycode::S),
+                ..
+            } => chip8.keyboard[0x8] = true,
+            Event::KeyUp {
+                keycode: Some(Keycode::S),
+                repeat: false,
+                ..
+            } => chip8.keyboard[0x8] = false,
+
+            Event::KeyDown {
+                keycode: Some(Keycode::D),
+                ..
+            } => chip8.keyboard[0x9] = true,
+            Event::KeyUp {
+                keycode: Some(Keycode::D),
+                repeat: false,
+                ..
+            } => chip8.keyboard[0x9] = false,
+
+            Event::KeyDown {
+                keycode: Some(Keycode::F),
+                ..
+            } => chip8.keyboard[0xE] = true,
+            Event::KeyUp {
+                keycode: Some(Keycode::F),
+                repeat: false,
+                ..
+            } => chip8.keyboard[0xE] = false,
+
+            Event::KeyDown {
+                keycode: Some(Keycode::Z),
+                ..
+            } => chip8.keyboard[0xA] = true,
+            Event::KeyUp {
+                keycode: Some(Keycode::Z),
+                repeat: false,
+                ..
+            } => chip8.keyboard[0xA] = false,
+
+            Event::KeyDown {
+                keycode: Some(Keycode::X),
+                ..
+            } => chip8.keyboard[0x0] = true,
+            Event::KeyUp {
+                keycode: Some(Keycode::X),
+                repeat: false,
+                ..
+            } => chip8.keyboard[0x0] = false,
+
+            Event::KeyDown {
+                keycode: Some(Keycode::C),
+                ..
+            } => chip8.keyboard[0xB] = true,
+            Event::KeyUp {
+                keycode: Some(Keycode::C),
+                repeat: false,
+                ..
+            } => chip8.keyboard[0xB] = false,
+
+            Event::KeyDown {
+                keycode: Some(Keycode::V),
+                ..
+            } => chip8.keyboard[0xF] = true,
+            Event::KeyUp {
+                keycode: Some(Keycode::V),
+                repeat: false,
+                ..
+            } => chip8.keyboard[0xF] = false,
+
+            _ => (),
+        }
+    }
+    request
+}
+
+// read screen from chip8, coloring, if pixel changed, update screen state
+fn read_screen_state(
+    chip8: &Chip8,
+    palette: &Palette,
+    screen_state: &mut [u8; 64 * 32 * 3],
+) -> bool {
+    let mut idx = 0;
+    let mut update = false;
+    for i in 0..(64 * 32) {
+        let [r, g, b] = palette.color(chip8.display[i]);
+        if screen_state[idx] != r || screen_state[idx + 1] != g || screen_state[idx + 2] != b {
+            screen_state[idx] = r;
+            screen_state[idx + 1] = g;
+            screen_state[idx + 2] = b;
+            update = true
+        }
+        idx += 3;
+    }
+    update
+}
+
+pub fn run_emulator(opts: Opts) {
+    // open ROM
+    let program = match opts.filepath.as_ref() {
+        Some(filepath) => read_rom(filepath),
+        None => {
+            eprintln!("error: a ROM path or a subcommand is required (see --help)");
+            std::process::exit(2);
+        }
+    };
+
+    // init sdl2
+    let sdl_context = sdl2::init().unwrap();
+    let video_subsystem = sdl_context.video().unwrap();
+    let window = video_subsystem
+        .window(
+            "Chip-8 Emulator",
+            (64 * opts.scale) as u32,
+            (32 * opts.scale) as u32,
+        )
+        .position_centered()
+        .build()
+        .unwrap();
+
+    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
+    let mut event_pump = sdl_context.event_pump().unwrap();
+    canvas
+        .set_scale(opts.scale as f32, opts.scale as f32)
+        .unwrap();
+
+    let creator = canvas.texture_creator();
+    let mut texture = creator
+        .create_texture_target(PixelFormatEnum::RGB24, 64, 32)
+        .unwrap();
+
+    let mut screen_state = [0_u8; 64 * 32 * 3];
+
+    // init capture
+    let mut recorder = opts.record.as_ref().map(|path| {
+        Recorder::create(path, &opts.palette, opts.scale).expect("failed to start recording")
+    });
+    let mut record_timer = std::time::Instant::now();
+    let mut screenshot_count = 0;
+
+    // init debugger
+    let mut debugger = if opts.debug {
+        Some(Debugger::new())
+    } else {
+        None
+    };
+    let mut gdb_stub = opts
+        .gdb
+        .map(|port| GdbStub::listen(port).expect("failed to accept GDB connection"));
+
+    // init trace
+    let mut tracer = opts.trace.as_ref().map(|path| {
+        Tracer::create(path, opts.trace_format, opts.trace_range).expect("failed to start trace")
+    });
+
+    // init lint
+    let mut linter = if opts.lint || opts.lint_halt {
+        Some(Linter::new(&program))
+    } else {
+        None
+    };
+
+    // init profiler and coverage
+    let mut reports = Reports::new(&opts);
+
+    // start emulator
+    let mut chip8 = Chip8::new();
+    chip8.set_quirks(opts.quirks);
+    chip8.load_program(&program);
+    let mut fault_reported = false;
+    chip8.run_with_callback(move |chip8| {
+        let request = handle_user_input(chip8, &mut event_pump);
+
+        if request.screenshot {
+            let timestamp = std::time::SystemTime::now()
+                .duration_since(std::time::UNIX_EPOCH)
+                .unwrap()
+                .as_secs();
+            let path = std::path::Path::new(&opts.screenshot_dir)
+                .join(format!("chip8-{}-{}.png", timestamp, screenshot_count));
+            screenshot_count += 1;
+            match capture::save_png(
+                path.to_str().unwrap(),
+                &chip8.display,
+                &opts.palette,
+                opts.scale,
+            ) {
+                Ok(()) => info!("saved screenshot: {}", path.display()),
+                Err(e) => error!("failed to save screenshot: {}", e),
+            }
+        }
+
+        // NOTE: recording at 60hz
+        if let Some(recorder) = recorder.as_mut() {
+            if record_timer.elapsed() >= std::time::Duration::from_secs_f32(1.0 / 60.0) {
+                recorder
+                    .push_frame(&chip8.display)
+                    .expect("failed to record frame");
+                record_timer = std::time::Instant::now();
+            }
+        }
+
+        if request.quit {
+            exit(&opts, &program, recorder.take(), tracer.take(), &reports, 0);
+        }
+
+        if read_screen_state(chip8, &opts.palette, &mut screen_state) {
+            texture.update(None, &screen_state, 64 * 3).unwrap();
+            canvas.copy(&texture, None, None).unwrap();
+            canvas.present();
+        }
+
+        // NOTE: a faulted machine stays at the instruction, it can be inspected in the debugger
+        if let Some(fault) = chip8.fault() {
+            if !fault_reported {
+                eprintln!(
+                    "error: ROM stopped at 0x{:03X}: {}",
+                    chip8.program_counter(),
+                    fault
+                );
+                fault_reported = true;
+            }
+            match debugger.as_mut() {
+                Some(debugger) => debugger.request_break(),
+                None => exit(&opts, &program, recorder.take(), tracer.take(), &reports, 1),
+            }
+        }
+
+        // NOTE: before the debuggers, so halting breaks at the instruction responsible
+        if let Some(linter) = linter.as_mut() {
+            let warnings = linter.on_instruction(chip8);
+            for warning in warnings.iter() {
+                eprintln!("lint: {}", warning);
+            }
+            if opts.lint_halt && !warnings.is_empty() {
+                match debugger.as_mut() {
+                    Some(debugger) => debugger.request_break(),
+                    None => exit(&opts, &program, recorder.take(), tracer.take(), &reports, 1),
+                }
+            }
+        }
+
+        if let Some(debugger) = debugger.as_mut() {
+            if request.debug_break {
+                debugger.request_break();
+            }
+            if !debugger.on_instruction(chip8) {
+                exit(&opts, &program, recorder.take(), tracer.take(), &reports, 0);
+            }
+        }
+
+        if let Some(gdb_stub) = gdb_stub.as_mut() {
+            if !gdb_stub.on_instruction(chip8) {
+                exit(&opts, &program, recorder.take(), tracer.take(), &reports, 0);
+            }
+        }
+
+        // NOTE: traced last, debuggers may have modified the state
+        if let Some(tracer) = tracer.as_mut() {
+            tracer.on_instruction(chip8).expect("failed to write trace");
+        }
+        reports.on_instruction(chip8);
+    });
+}
+
+// execution analyses, written at exit
+struct Reports {
+    profiler: Option<Profiler>,
+    coverage: Option<Coverage>,
+}
+
+impl Reports {
+    fn new(opts: &Opts) -> Reports {
+        Reports {
+            profiler: opts.profile.as_ref().map(|_| Profiler::new()),
+            coverage: opts.coverage.as_ref().map(|_| Coverage::new()),
+        }
+    }
+
+    fn on_instruction(&mut self, chip8: &Chip8) {
+        if let Some(profiler) = self.profiler.as_mut() {
+            profiler.on_instruction(chip8);
+        }
+        if let Some(coverage) = self.coverage.as_mut() {
+            coverage.on_instruction(chip8);
+        }
+    }
+
+    fn write(&self, opts: &Opts, rom: &[u8]) -> std::io::Result<()> {
+        if let Some(profiler) = self.profiler.as_ref() {
+            if let Some(path) = opts.profile.as_ref() {
+                let report = if path.ends_with(".json") {
+                    profiler.json_report()
+                } else {
+                    profiler.text_report()
+                };
+                std::fs::write(path, report)?;
+            }
+            if let Some(path) = opts.profile_disasm.as_ref() {
+                std::fs::write(path, profiler.annotate(rom, Syntax::Cowgod))?;
+            }
+        }
+        if let (Some(coverage), Some(path)) = (self.coverage.as_ref(), opts.coverage.as_ref()) {
+            std::fs::write(path, coverage.export(rom))?;
+        }
+        Ok(())
+    }
+}
+
+// flush captures and exit, status is non-zero when the run failed
+fn exit(
+    opts: &Opts,
+    rom: &[u8],
+    recorder: Option<Recorder>,
+    tracer: Option<Tracer>,
+    reports: &Reports,
+    status: i32,
+) -> ! {
+    if let Some(recorder) = recorder {
+        recorder.finish().expect("failed to finish recording");
+    }
+    if let Some(tracer) = tracer {
+        tracer.finish().expect("failed to finish trace");
+    }
+    reports.write(opts, rom).expect("failed to write reports");
+    std::process::exit(status);
+}
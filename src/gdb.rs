@@ -0,0 +1,486 @@
+//! GDB remote serial protocol stub.
+//!
+//! Register layout (`g`/`G`, `p`/`P`), multi-byte registers are big-endian like CHIP-8 memory:
+//!
+//! ```text
+//! 0-15: V0-VF (8bit)
+//! 16:   I (16bit)
+//! 17:   PC (16bit)
+//! 18:   SP (8bit)
+//! ```
+use crate::chip8::Chip8;
+
+use log::info;
+
+use std::collections::BTreeSet;
+use std::io::prelude::*;
+use std::io::BufReader;
+use std::net::{TcpListener, TcpStream};
+
+const MEMORY_SIZE: usize = 0x1000;
+
+// NOTE: how often the connection is polled for an interrupt (Ctrl-C) while running
+const INTERRUPT_POLL_INTERVAL: u32 = 1024;
+
+const SIGINT: u8 = 2;
+const SIGTRAP: u8 = 5;
+
+#[derive(PartialEq)]
+enum State {
+    Stopped,
+    Stepping,
+    Running,
+    Detached,
+}
+
+/// GDB remote serial protocol server for one connection.
+pub struct GdbStub {
+    reader: BufReader<TcpStream>,
+    writer: TcpStream,
+    breakpoints: BTreeSet<u16>,
+    state: State,
+    instructions_since_poll: u32,
+}
+
+impl GdbStub {
+    /// Waits for a debugger to connect on localhost.
+    pub fn listen(port: u16) -> std::io::Result<GdbStub> {
+        let listener = TcpListener::bind(("127.0.0.1", port))?;
+        info!("waiting for GDB connection on 127.0.0.1:{}", port);
+        GdbStub::accept(&listener)
+    }
+
+    // wait for a debugger to connect on listener
+    fn accept(listener: &TcpListener) -> std::io::Result<GdbStub> {
+        let (stream, address) = listener.accept()?;
+        info!("GDB connected from {}", address);
+
+        stream.set_nodelay(true)?;
+        Ok(GdbStub {
+            reader: BufReader::new(stream.try_clone()?),
+            writer: stream,
+            breakpoints: BTreeSet::new(),
+            state: State::Stopped,
+            instructions_since_poll: 0,
+        })
+    }
+
+    /// Called before each instruction, returns false if debugger killed the target.
+    pub fn on_instruction(&mut self, chip8: &mut Chip8) -> bool {
+        match self.state {
+            State::Detached => return true,
+            State::Stopped => (),
+            State::Stepping => {
+                self.state = State::Stopped;
+                self.send_stop(SIGTRAP);
+            }
+            State::Running => {
+                if self.breakpoints.contains(&chip8.program_counter()) {
+                    self.state = State::Stopped;
+                    self.send_stop(SIGTRAP);
+                } else if self.poll_interrupt() {
+                    self.state = State::Stopped;
+                    self.send_stop(SIGINT);
+                } else {
+                    return true;
+                }
+            }
+        }
+
+        // process packets until execution is resumed
+        while self.state == State::Stopped {
+            let packet = match self.read_packet() {
+                Some(packet) => packet,
+                None => {
+                    // connection closed
+                    info!("GDB disconnected");
+                    self.state = State::Detached;
+                    return true;
+                }
+            };
+
+            match self.handle_packet(chip8, &packet) {
+                Reply::Packet(response) => self.send_packet(&response),
+                Reply::Nothing => (),
+                Reply::Kill => return false,
+            }
+        }
+        true
+    }
+
+    // check whether debugger sent an interrupt request without blocking
+    fn poll_interrupt(&mut self) -> bool {
+        self.instructions_since_poll += 1;
+        if self.instructions_since_poll < INTERRUPT_POLL_INTERVAL {
+            return false;
+        }
+        self.instructions_since_poll = 0;
+
+        if self.reader.buffer().is_empty() {
+            self.writer.set_nonblocking(true).unwrap();
+            let result = self.reader.fill_buf().map(|buf| buf.len());
+            self.writer.set_nonblocking(false).unwrap();
+            if result.is_err() {
+                // WouldBlock: nothing has been sent
+                return false;
+            }
+        }
+
+        let interrupted = self.reader.buffer().contains(&0x03);
+        if interrupted {
+            let len = self.reader.buffer().len();
+            self.reader.consume(len);
+        }
+        interrupted
+    }
+
+    // read "$<data>#<checksum>", acknowledge it and return data
+    fn read_packet(&mut self) -> Option<String> {
+        loop {
+            let mut byte = [0_u8];
+            // skip acks, interrupts and garbage until the start of a packet
+            loop {
+                self.reader.read_exact(&mut byte).ok()?;
+                if byte[0] == b'$' {
+                    break;
+                }
+            }
+
+            let mut data = Vec::new();
+            self.reader.read_until(b'#', &mut data).ok()?;
+            data.pop();
+
+            let mut checksum = [0_u8; 2];
+            self.reader.read_exact(&mut checksum).ok()?;
+            let expected = std::str::from_utf8(&checksum)
+                .ok()
+                .and_then(|s| u8::from_str_radix(s, 16).ok());
+
+            if expected == Some(checksum_of(&data)) {
+                self.writer.write_all(b"+").ok()?;
+                return String::from_utf8(data).ok();
+            }
+            self.writer.write_all(b"-").ok()?;
+        }
+    }
+
+    fn send_packet(&mut self, data: &str) {
+        let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+        // NOTE: acknowledgement from debugger is skipped in read_packet
+        if self.writer.write_all(packet.as_bytes()).is_err() {
+            self.state = State::Detached;
+        }
+    }
+
+    fn send_stop(&mut self, signal: u8) {
+        self.send_packet(&format!("S{:02x}", signal));
+    }
+
+    fn handle_packet(&mut self, chip8: &mut Chip8, packet: &str) -> Reply {
+        let command = packet.get(0..1).unwrap_or("");
+        let args = packet.get(1..).unwrap_or("");
+        let response = match command {
+            "?" => format!("S{:02x}", SIGTRAP),
+            "g" => read_registers(chip8),
+            "G" => match write_registers(chip8, args) {
+                Some(()) => "OK".to_string(),
+                None => "E01".to_string(),
+            },
+            "p" => match usize::from_str_radix(args, 16) {
+                Ok(n) if n < 19 => read_registers_range(chip8, n, n + 1),
+                _ => "E01".to_string(),
+            },
+            "P" => match write_register(chip8, args) {
+                Some(()) => "OK".to_string(),
+                None => "E01".to_string(),
+            },
+            "m" => match read_memory(chip8, args) {
+                Some(data) => data,
+                None => "E14".to_string(),
+            },
+            "M" => match write_memory(chip8, args) {
+                Some(()) => "OK".to_string(),
+                None => "E14".to_string(),
+            },
+            "Z" | "z" => match parse_breakpoint(args) {
+                Some(address) => {
+                    if command == "Z" {
+                        self.breakpoints.insert(address);
+                    } else {
+                        self.breakpoints.remove(&address);
+                    }
+                    "OK".to_string()
+                }
+                // unsupported breakpoint type
+                None => "".to_string(),
+            },
+            "s" | "c" => {
+                // optional resume address
+                if !args.is_empty() {
+                    match u16::from_str_radix(args, 16) {
+                        Ok(address) => chip8.set_program_counter(address),
+                        Err(_) => return Reply::Packet("E01".to_string()),
+                    }
+                }
+                self.state = if command == "s" {
+                    State::Stepping
+                } else {
+                    State::Running
+                };
+                self.instructions_since_poll = 0;
+                // NOTE: stop reply is sent when execution stops
+                return Reply::Nothing;
+            }
+            "D" => {
+                self.state = State::Detached;
+                "OK".to_string()
+            }
+            "k" => return Reply::Kill,
+            "H" => "OK".to_string(),
+            "q" => match args {
+                "Attached" => "1".to_string(),
+                "C" => "QC1".to_string(),
+                "fThreadInfo" => "m1".to_string(),
+                "sThreadInfo" => "l".to_string(),
+                _ if args.starts_with("Supported") => "PacketSize=1000".to_string(),
+                _ => "".to_string(),
+            },
+            // unsupported packet
+            _ => "".to_string(),
+        };
+        Reply::Packet(response)
+    }
+}
+
+enum Reply {
+    Packet(String),
+    Nothing,
+    Kill,
+}
+
+fn checksum_of(data: &[u8]) -> u8 {
+    data.iter().fold(0_u8, |sum, b| sum.wrapping_add(*b))
+}
+
+fn decode_hex(s: &str) -> Option<Vec<u8>> {
+    if s.len() % 2 == 1 {
+        return None;
+    }
+    (0..s.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(s.get(i..(i + 2))?, 16).ok())
+        .collect()
+}
+
+fn encode_hex(data: &[u8]) -> String {
+    data.iter().map(|b| format!("{:02x}", b)).collect()
+}
+
+// serialize registers [start, end) in protocol layout
+fn read_registers_range(chip8: &Chip8, start: usize, end: usize) -> String {
+    let mut data = Vec::new();
+    for n in start..end {
+        match n {
+            0..=15 => data.push(chip8.read_register(n as u8)),
+            16 => data.extend_from_slice(&chip8.index_register().to_be_bytes()),
+            17 => data.extend_from_slice(&chip8.program_counter().to_be_bytes()),
+            18 => data.push(chip8.stack_pointer()),
+            _ => (),
+        }
+    }
+    encode_hex(&data)
+}
+
+fn read_registers(chip8: &Chip8) -> String {
+    read_registers_range(chip8, 0, 19)
+}
+
+fn write_registers(chip8: &mut Chip8, args: &str) -> Option<()> {
+    let data = decode_hex(args)?;
+    if data.len() != 16 + 2 + 2 + 1 {
+        return None;
+    }
+    for (x, value) in data[0..16].iter().enumerate() {
+        chip8.write_register(x as u8, *value);
+    }
+    chip8.set_index_register(u16::from_be_bytes([data[16], data[17]]));
+    chip8.set_program_counter(u16::from_be_bytes([data[18], data[19]]));
+    chip8.set_stack_pointer(data[20]);
+    Some(())
+}
+
+// "n=value"
+fn write_register(chip8: &mut Chip8, args: &str) -> Option<()> {
+    let mut split = args.splitn(2, '=');
+    let n = usize::from_str_radix(split.next()?, 16).ok()?;
+    let data = decode_hex(split.next()?)?;
+    match (n, data.len()) {
+        (0..=15, 1) => chip8.write_register(n as u8, data[0]),
+        (16, 2) => chip8.set_index_register(u16::from_be_bytes([data[0], data[1]])),
+        (17, 2) => chip8.set_program_counter(u16::from_be_bytes([data[0], data[1]])),
+        (18, 1) => chip8.set_stack_pointer(data[0]),
+        _ => return None,
+    }
+    Some(())
+}
+
+fn parse_address_length(s: &str) -> Option<(usize, usize)> {
+    let mut split = s.splitn(2, ',');
+    let address = usize::from_str_radix(split.next()?, 16).ok()?;
+    let length = usize::from_str_radix(split.next()?, 16).ok()?;
+    // NOTE: both come from the network, the end may overflow
+    address
+        .checked_add(length)
+        .filter(|end| *end <= MEMORY_SIZE)?;
+    Some((address, length))
+}
+
+// "addr,length"
+fn read_memory(chip8: &Chip8, args: &str) -> Option<String> {
+    let (address, length) = parse_address_length(args)?;
+    let data: Vec<u8> = (address..(address + length))
+        .map(|a| chip8.read_memory(a as u16))
+        .collect();
+    Some(encode_hex(&data))
+}
+
+// "addr,length:data"
+fn write_memory(chip8: &mut Chip8, args: &str) -> Option<()> {
+    let mut split = args.splitn(2, ':');
+    let (address, length) = parse_address_length(split.next()?)?;
+    let data = decode_hex(split.next()?)?;
+    if data.len() != length {
+        return None;
+    }
+    for (i, value) in data.iter().enumerate() {
+        chip8.write_memory((address + i) as u16, *value);
+    }
+    Some(())
+}
+
+// "type,addr,kind", software (0) and hardware (1) breakpoints are treated the same
+fn parse_breakpoint(args: &str) -> Option<u16> {
+    let mut split = args.split(',');
+    match split.next()? {
+        "0" | "1" => (),
+        _ => return None,
+    }
+    let address = u16::from_str_radix(split.next()?, 16).ok()?;
+    if address as usize >= MEMORY_SIZE {
+        return None;
+    }
+    Some(address)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+
+    // scripted GDB client
+    struct Client {
+        reader: BufReader<TcpStream>,
+        writer: TcpStream,
+    }
+
+    impl Client {
+        fn read_byte(&mut self) -> u8 {
+            let mut byte = [0_u8];
+            self.reader.read_exact(&mut byte).unwrap();
+            byte[0]
+        }
+
+        // send a packet, expect the ack
+        fn send(&mut self, data: &str) {
+            let packet = format!("${}#{:02x}", data, checksum_of(data.as_bytes()));
+            self.writer.write_all(packet.as_bytes()).unwrap();
+            assert_eq!(self.read_byte(), b'+', "no ack for {}", data);
+        }
+
+        // read a reply, check its checksum and ack it
+        fn receive(&mut self) -> String {
+            assert_eq!(self.read_byte(), b'$');
+            let mut data = Vec::new();
+            self.reader.read_until(b'#', &mut data).unwrap();
+            data.pop();
+            let checksum = [self.read_byte(), self.read_byte()];
+            let checksum = u8::from_str_radix(std::str::from_utf8(&checksum).unwrap(), 16);
+            assert_eq!(checksum, Ok(checksum_of(&data)));
+            self.writer.write_all(b"+").unwrap();
+            String::from_utf8(data).unwrap()
+        }
+
+        fn request(&mut self, data: &str) -> String {
+            self.send(data);
+            self.receive()
+        }
+    }
+
+    #[test]
+    fn remote_protocol() {
+        // LD V0, 5; LD V1, 6; loop: ADD V0, 1; JP loop
+        let rom = [0x60, 0x05, 0x61, 0x06, 0x70, 0x01, 0x12, 0x04];
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&rom);
+
+        let listener = TcpListener::bind(("127.0.0.1", 0)).unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let client = std::thread::spawn(move || {
+            let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+            stream
+                .set_read_timeout(Some(Duration::from_secs(10)))
+                .unwrap();
+            let mut client = Client {
+                reader: BufReader::new(stream.try_clone().unwrap()),
+                writer: stream,
+            };
+
+            assert_eq!(client.request("?"), "S05");
+            let registers = format!("{}{}", "00".repeat(16), "0000020000");
+            assert_eq!(client.request("g"), registers);
+
+            assert_eq!(client.request("m200,4"), "60056106");
+            assert_eq!(client.request("M300,2:abcd"), "OK");
+            assert_eq!(client.request("m2ff,4"), "00abcd00");
+            assert_eq!(client.request("mffe,3"), "E14");
+            assert_eq!(client.request("mffffffffffffffff,2"), "E14");
+
+            client.send("s");
+            assert_eq!(client.receive(), "S05");
+            assert_eq!(client.request("p11"), "0202");
+
+            // stops at the breakpoint, not after the jump to it
+            assert_eq!(client.request("Z0,206,2"), "OK");
+            client.send("c");
+            assert_eq!(client.receive(), "S05");
+            assert_eq!(client.request("p11"), "0206");
+            assert_eq!(client.request("p0"), "06");
+            assert_eq!(client.request("p1"), "06");
+            client.send("c");
+            assert_eq!(client.receive(), "S05");
+            assert_eq!(client.request("p0"), "07");
+
+            // runs until interrupted
+            assert_eq!(client.request("z0,206,2"), "OK");
+            client.send("c");
+            client.writer.write_all(&[0x03]).unwrap();
+            assert_eq!(client.receive(), "S02");
+
+            client.send("k");
+        });
+
+        let mut stub = GdbStub::accept(&listener).unwrap();
+        let mut killed = false;
+        // NOTE: bounded, in case the client fails and disconnects
+        for _ in 0..1_000_000 {
+            if !stub.on_instruction(&mut chip8) {
+                killed = true;
+                break;
+            }
+            chip8.step();
+        }
+        client.join().unwrap();
+        assert!(killed);
+        assert_eq!(chip8.read_memory(0x300), 0xAB);
+    }
+}
@@ -0,0 +1,280 @@
+//! Execution history for reverse debugging.
+//!
+//! A snapshot of the machine is kept every `SNAPSHOT_INTERVAL` instructions, and for every
+//! instruction what the outside world changed before it: keypad, timers (they tick on wall-clock
+//! time) and the result of RND. Any earlier state is rebuilt by replaying from the nearest
+//! snapshot.
+//!
+//! Memory use is bounded by `MAX_SNAPSHOTS`, older history is dropped.
+use crate::chip8::Chip8;
+use crate::instruction::{self, Instruction};
+
+use std::collections::VecDeque;
+
+const SNAPSHOT_INTERVAL: u64 = 1000;
+const MAX_SNAPSHOTS: usize = 1000;
+
+// inputs of one instruction
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Event {
+    keyboard: [bool; 16],
+    delay_timer: u8,
+    sound_timer: u8,
+    // value RND stored in Vx
+    random: Option<u8>,
+}
+
+impl Event {
+    fn capture(chip8: &Chip8) -> Event {
+        Event {
+            keyboard: chip8.keyboard,
+            delay_timer: chip8.delay_timer(),
+            sound_timer: chip8.sound_timer(),
+            random: None,
+        }
+    }
+
+    fn apply(&self, chip8: &mut Chip8) {
+        chip8.keyboard = self.keyboard;
+        chip8.set_delay_timer(self.delay_timer);
+        chip8.set_sound_timer(self.sound_timer);
+    }
+}
+
+/// Execution history for reverse stepping, snapshots plus the inputs between them.
+pub struct History {
+    // (instruction index, state before it), oldest first
+    snapshots: VecDeque<(u64, Chip8)>,
+    // inputs of instructions first_event..count
+    events: VecDeque<Event>,
+    first_event: u64,
+    // instructions recorded so far
+    count: u64,
+    // register written by the last recorded instruction, if it was RND
+    pending_random: Option<u8>,
+    snapshot_interval: u64,
+    max_snapshots: usize,
+}
+
+impl Default for History {
+    fn default() -> History {
+        History::new()
+    }
+}
+
+impl History {
+    /// History with the default snapshot interval and limit.
+    pub fn new() -> History {
+        History::with_limits(SNAPSHOT_INTERVAL, MAX_SNAPSHOTS)
+    }
+
+    /// History taking a snapshot every `snapshot_interval` instructions, keeping at most
+    /// `max_snapshots` of them.
+    pub fn with_limits(snapshot_interval: u64, max_snapshots: usize) -> History {
+        History {
+            snapshots: VecDeque::new(),
+            events: VecDeque::new(),
+            first_event: 0,
+            count: 0,
+            pending_random: None,
+            snapshot_interval,
+            max_snapshots,
+        }
+    }
+
+    /// Index of the last recorded instruction, the one about to execute.
+    pub fn position(&self) -> Option<u64> {
+        self.count.checked_sub(1)
+    }
+
+    /// Index of the oldest instruction which can be returned to.
+    pub fn oldest(&self) -> Option<u64> {
+        self.snapshots.front().map(|(index, _)| *index)
+    }
+
+    /// Records the state before the next instruction, call this right before executing it.
+    pub fn record(&mut self, chip8: &Chip8) {
+        if let Some(x) = self.pending_random.take() {
+            if let Some(event) = self.events.back_mut() {
+                event.random = Some(chip8.read_register(x));
+            }
+        }
+
+        let index = self.count;
+        if index % self.snapshot_interval == 0 || self.snapshots.is_empty() {
+            self.push_snapshot(index, chip8);
+        }
+        self.events.push_back(Event::capture(chip8));
+        self.count += 1;
+
+        let opcode = chip8.read_memory_u16(chip8.program_counter());
+        if let Instruction::Rnd(x, _) = instruction::decode(opcode) {
+            self.pending_random = Some(x);
+        }
+    }
+
+    /// Replaces the state of the last recorded instruction, after the debugger changed it.
+    pub fn amend(&mut self, chip8: &Chip8) {
+        if let Some(position) = self.position() {
+            self.truncate(position);
+            // NOTE: a snapshot keeps the change, replays from older ones would miss it
+            self.push_snapshot(position, chip8);
+        }
+        self.record(chip8);
+    }
+
+    /// Goes back to the state before instruction `target`, forgetting the history after it.
+    pub fn rewind(&mut self, target: u64) -> Option<Chip8> {
+        let chip8 = self.replay(target)?;
+        self.truncate(target);
+        self.record(&chip8);
+        Some(chip8)
+    }
+
+    /// State before instruction `target`.
+    pub fn replay(&self, target: u64) -> Option<Chip8> {
+        if target >= self.count {
+            return None;
+        }
+        let snapshot = self
+            .snapshots
+            .iter()
+            .rposition(|(index, _)| *index <= target)?;
+        self.replay_from(snapshot, target, |_, _| ())
+    }
+
+    /// Visits the state before every instruction in the history, oldest first.
+    pub fn scan<F: FnMut(u64, &Chip8)>(&self, visit: F) {
+        if let Some(position) = self.position() {
+            self.replay_from(0, position, visit);
+        }
+    }
+
+    fn replay_from<F>(&self, snapshot: usize, target: u64, mut visit: F) -> Option<Chip8>
+    where
+        F: FnMut(u64, &Chip8),
+    {
+        let (start, chip8) = self.snapshots.get(snapshot)?;
+        let mut chip8 = chip8.clone();
+        for index in *start..=target {
+            let event = self.events[(index - self.first_event) as usize];
+            event.apply(&mut chip8);
+            visit(index, &chip8);
+            if index == target {
+                break;
+            }
+            let opcode = chip8.read_memory_u16(chip8.program_counter());
+            chip8.step();
+            if let (Instruction::Rnd(x, _), Some(value)) =
+                (instruction::decode(opcode), event.random)
+            {
+                chip8.write_register(x, value);
+            }
+        }
+        Some(chip8)
+    }
+
+    fn push_snapshot(&mut self, index: u64, chip8: &Chip8) {
+        match self.snapshots.back_mut() {
+            Some(last) if last.0 == index => last.1 = chip8.clone(),
+            _ => self.snapshots.push_back((index, chip8.clone())),
+        }
+        if self.snapshots.len() > self.max_snapshots {
+            self.snapshots.pop_front();
+            let oldest = self.oldest().unwrap();
+            while self.first_event < oldest {
+                self.events.pop_front();
+                self.first_event += 1;
+            }
+        }
+    }
+
+    // forget instructions from `index` on
+    fn truncate(&mut self, index: u64) {
+        while matches!(self.snapshots.back(), Some((last, _)) if *last > index) {
+            self.snapshots.pop_back();
+        }
+        self.events.truncate((index - self.first_event) as usize);
+        self.count = index;
+        self.pending_random = None;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // LD V1, 0; loop: RND V0, 0xFF; ADD V1, V0; LD V2, DT; SKP V3 (key 3); JP loop; halt
+    const PROGRAM: [u8; 14] = [
+        0x61, 0x00, 0xC0, 0xFF, 0x81, 0x04, 0xF2, 0x07, 0xE3, 0x9E, 0x12, 0x02, 0x12, 0x0C,
+    ];
+
+    // run with inputs changing along the way, recording every state
+    fn record(history: &mut History, instructions: usize) -> (Chip8, Vec<Chip8>) {
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&PROGRAM);
+        chip8.set_delay_timer(200);
+        let mut states = Vec::new();
+        for i in 0..instructions {
+            if i % 7 == 0 {
+                chip8.tick_timers();
+            }
+            chip8.keyboard[0x3] = i == 150;
+            history.record(&chip8);
+            states.push(chip8.clone());
+            chip8.step();
+        }
+        (chip8, states)
+    }
+
+    #[test]
+    fn replay_matches_recording() {
+        let mut history = History::with_limits(16, 100);
+        let (_, states) = record(&mut history, 200);
+        assert_eq!(history.position(), Some(199));
+        for (index, state) in states.iter().enumerate() {
+            let replayed = history.replay(index as u64).unwrap();
+            assert!(replayed == *state, "differs at {}", index);
+        }
+        assert!(history.replay(200).is_none());
+
+        let mut scanned = 0;
+        history.scan(|index, chip8| {
+            assert!(*chip8 == states[index as usize]);
+            scanned += 1;
+        });
+        assert_eq!(scanned, 200);
+    }
+
+    #[test]
+    fn rewind_forgets_the_future() {
+        let mut history = History::with_limits(16, 100);
+        let (_, states) = record(&mut history, 100);
+        let chip8 = history.rewind(40).unwrap();
+        assert!(chip8 == states[40]);
+        assert_eq!(history.position(), Some(40));
+        assert!(history.replay(41).is_none());
+        assert!(history.replay(33).unwrap() == states[33]);
+    }
+
+    #[test]
+    fn amend_keeps_debugger_changes() {
+        let mut history = History::with_limits(16, 100);
+        let (mut chip8, _) = record(&mut history, 50);
+        history.record(&chip8);
+        chip8.write_register(0x1, 0x42);
+        history.amend(&chip8);
+        assert_eq!(history.position(), Some(50));
+        assert!(history.replay(50).unwrap() == chip8);
+    }
+
+    #[test]
+    fn old_history_is_dropped() {
+        let mut history = History::with_limits(10, 3);
+        let (_, states) = record(&mut history, 100);
+        assert_eq!(history.oldest(), Some(70));
+        assert!(history.replay(69).is_none());
+        assert!(history.replay(70).unwrap() == states[70]);
+        assert!(history.replay(99).unwrap() == states[99]);
+    }
+}
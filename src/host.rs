@@ -0,0 +1,93 @@
+//! Services the interpreter needs from the host, so the core runs without `std`.
+//!
+//! Display and keypad are plain fields of [`Chip8`](crate::Chip8), which the host reads and
+//! writes between instructions.
+
+/// Source of random bytes for `CXKK`.
+pub trait Random {
+    /// Next random byte.
+    fn random_byte(&mut self) -> u8;
+}
+
+/// Monotonic time source for [`Chip8::run_with_clock`](crate::Chip8::run_with_clock).
+pub trait Clock {
+    /// Microseconds since an arbitrary, fixed starting point.
+    fn now_micros(&mut self) -> u64;
+}
+
+/// Deterministic xorshift generator, for hosts without an entropy source and for reproducible
+/// runs.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct XorShiftRandom {
+    state: u32,
+}
+
+impl XorShiftRandom {
+    /// Generator starting from `seed`, a zero seed is replaced by a fixed non-zero one.
+    pub fn new(seed: u32) -> XorShiftRandom {
+        // NOTE: xorshift gets stuck at zero
+        XorShiftRandom {
+            state: if seed == 0 { 0x2545_F491 } else { seed },
+        }
+    }
+}
+
+impl Default for XorShiftRandom {
+    fn default() -> XorShiftRandom {
+        XorShiftRandom::new(0)
+    }
+}
+
+impl Random for XorShiftRandom {
+    fn random_byte(&mut self) -> u8 {
+        self.state ^= self.state << 13;
+        self.state ^= self.state >> 17;
+        self.state ^= self.state << 5;
+        (self.state >> 24) as u8
+    }
+}
+
+/// Random bytes from the thread-local generator of the `rand` crate.
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct ThreadRandom;
+
+#[cfg(feature = "std")]
+impl Random for ThreadRandom {
+    fn random_byte(&mut self) -> u8 {
+        use rand::Rng;
+        rand::thread_rng().gen()
+    }
+}
+
+/// Wall-clock time from [`std::time::Instant`].
+#[cfg(feature = "std")]
+#[derive(Clone, Copy, Debug)]
+pub struct SystemClock {
+    start: std::time::Instant,
+}
+
+#[cfg(feature = "std")]
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        SystemClock {
+            start: std::time::Instant::now(),
+        }
+    }
+}
+
+#[cfg(feature = "std")]
+impl Clock for SystemClock {
+    fn now_micros(&mut self) -> u64 {
+        self.start.elapsed().as_micros() as u64
+    }
+}
+
+/// Random source of [`Chip8::new`](crate::Chip8::new): [`ThreadRandom`] with `std`,
+/// [`XorShiftRandom`] without.
+#[cfg(feature = "std")]
+pub type DefaultRandom = ThreadRandom;
+/// Random source of [`Chip8::new`](crate::Chip8::new): [`ThreadRandom`] with `std`,
+/// [`XorShiftRandom`] without.
+#[cfg(not(feature = "std"))]
+pub type DefaultRandom = XorShiftRandom;
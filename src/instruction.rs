@@ -0,0 +1,154 @@
+//! Decoded CHIP-8 instructions.
+
+/// A decoded instruction, named after Cowgod's Chip-8 Technical Reference.
+///
+/// X and Y are register indices, KK is a byte, N a nibble and NNN an address.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Instruction {
+    /// `0NNN` SYS: machine code routine, ignored.
+    Sys(u16),
+    /// `00E0` CLS: clear the display.
+    Cls,
+    /// `00EE` RET: return from a subroutine.
+    Ret,
+    /// `1NNN` JP: jump to NNN.
+    Jp(u16),
+    /// `2NNN` CALL: call the subroutine at NNN.
+    Call(u16),
+    /// `3XKK` SE Vx, KK: skip if Vx == KK.
+    SeByte(u8, u8),
+    /// `4XKK` SNE Vx, KK: skip if Vx != KK.
+    SneByte(u8, u8),
+    /// `5XY0` SE Vx, Vy: skip if Vx == Vy.
+    SeReg(u8, u8),
+    /// `6XKK` LD Vx, KK.
+    LdByte(u8, u8),
+    /// `7XKK` ADD Vx, KK, without carry.
+    AddByte(u8, u8),
+    /// `8XY0` LD Vx, Vy.
+    LdReg(u8, u8),
+    /// `8XY1` OR Vx, Vy.
+    Or(u8, u8),
+    /// `8XY2` AND Vx, Vy.
+    And(u8, u8),
+    /// `8XY3` XOR Vx, Vy.
+    Xor(u8, u8),
+    /// `8XY4` ADD Vx, Vy, VF is the carry.
+    AddReg(u8, u8),
+    /// `8XY5` SUB Vx, Vy: Vx -= Vy, VF is set when there is no borrow.
+    Sub(u8, u8),
+    /// `8XY6` SHR Vx, Vy, VF is the bit shifted out.
+    Shr(u8, u8),
+    /// `8XY7` SUBN Vx, Vy: Vx = Vy - Vx, VF is set when there is no borrow.
+    Subn(u8, u8),
+    /// `8XYE` SHL Vx, Vy, VF is the bit shifted out.
+    Shl(u8, u8),
+    /// `9XY0` SNE Vx, Vy: skip if Vx != Vy.
+    SneReg(u8, u8),
+    /// `ANNN` LD I, NNN.
+    LdI(u16),
+    /// `BNNN` JP V0, NNN: computed jump.
+    JpV0(u16),
+    /// `CXKK` RND Vx, KK: random byte masked with KK.
+    Rnd(u8, u8),
+    /// `DXYN` DRW Vx, Vy, N: draw the N-byte sprite at I, VF is set on collision.
+    Drw(u8, u8, u8),
+    /// `EX9E` SKP Vx: skip if key Vx is pressed.
+    Skp(u8),
+    /// `EXA1` SKNP Vx: skip if key Vx is not pressed.
+    Sknp(u8),
+    /// `FX07` LD Vx, DT.
+    LdVxDt(u8),
+    /// `FX0A` LD Vx, K: wait for a key press.
+    LdVxK(u8),
+    /// `FX15` LD DT, Vx.
+    LdDtVx(u8),
+    /// `FX18` LD ST, Vx.
+    LdStVx(u8),
+    /// `FX1E` ADD I, Vx.
+    AddI(u8),
+    /// `FX29` LD F, Vx: point I at the font glyph of Vx.
+    LdF(u8),
+    /// `FX33` LD B, Vx: store the BCD digits of Vx at I.
+    LdB(u8),
+    /// `FX55` LD \[I\], Vx: store V0-Vx at I.
+    Store(u8),
+    /// `FX65` LD Vx, \[I\]: load V0-Vx from I.
+    Load(u8),
+    /// Any other opcode, most likely data.
+    Unknown(u16),
+}
+
+/// Decodes a big-endian opcode, opcodes which are no instruction decode to `Unknown`.
+pub fn decode(opcode: u16) -> Instruction {
+    let nnn = opcode & 0x0FFF;
+    let x = ((opcode & 0x0F00) >> 8) as u8;
+    let y = ((opcode & 0x00F0) >> 4) as u8;
+    let kk = (opcode & 0x00FF) as u8;
+    let n = (opcode & 0x000F) as u8;
+
+    match opcode & 0xF000 {
+        0x0000 => match opcode {
+            0x00E0 => Instruction::Cls,
+            0x00EE => Instruction::Ret,
+            _ => Instruction::Sys(nnn),
+        },
+        0x1000 => Instruction::Jp(nnn),
+        0x2000 => Instruction::Call(nnn),
+        0x3000 => Instruction::SeByte(x, kk),
+        0x4000 => Instruction::SneByte(x, kk),
+        0x5000 if n == 0 => Instruction::SeReg(x, y),
+        0x6000 => Instruction::LdByte(x, kk),
+        0x7000 => Instruction::AddByte(x, kk),
+        0x8000 => match n {
+            0x0 => Instruction::LdReg(x, y),
+            0x1 => Instruction::Or(x, y),
+            0x2 => Instruction::And(x, y),
+            0x3 => Instruction::Xor(x, y),
+            0x4 => Instruction::AddReg(x, y),
+            0x5 => Instruction::Sub(x, y),
+            0x6 => Instruction::Shr(x, y),
+            0x7 => Instruction::Subn(x, y),
+            0xE => Instruction::Shl(x, y),
+            _ => Instruction::Unknown(opcode),
+        },
+        0x9000 if n == 0 => Instruction::SneReg(x, y),
+        0xA000 => Instruction::LdI(nnn),
+        0xB000 => Instruction::JpV0(nnn),
+        0xC000 => Instruction::Rnd(x, kk),
+        0xD000 => Instruction::Drw(x, y, n),
+        0xE000 => match kk {
+            0x9E => Instruction::Skp(x),
+            0xA1 => Instruction::Sknp(x),
+            _ => Instruction::Unknown(opcode),
+        },
+        0xF000 => match kk {
+            0x07 => Instruction::LdVxDt(x),
+            0x0A => Instruction::LdVxK(x),
+            0x15 => Instruction::LdDtVx(x),
+            0x18 => Instruction::LdStVx(x),
+            0x1E => Instruction::AddI(x),
+            0x29 => Instruction::LdF(x),
+            0x33 => Instruction::LdB(x),
+            0x55 => Instruction::Store(x),
+            0x65 => Instruction::Load(x),
+            _ => Instruction::Unknown(opcode),
+        },
+        _ => Instruction::Unknown(opcode),
+    }
+}
+
+impl Instruction {
+    /// Does this instruction conditionally skip the next one?
+    pub fn is_skip(&self) -> bool {
+        matches!(
+            self,
+            Instruction::SeByte(..)
+                | Instruction::SneByte(..)
+                | Instruction::SeReg(..)
+                | Instruction::SneReg(..)
+                | Instruction::Skp(..)
+                | Instruction::Sknp(..)
+        )
+    }
+}
@@ -0,0 +1,65 @@
+//! CHIP-8 emulator core and tooling.
+//!
+//! [`Chip8`] is the interpreter. It has no frontend, the embedder drives it and reads `display`
+//! and writes `keyboard` between instructions or frames:
+//!
+//! ```
+//! use chip8_emulator::Chip8;
+//!
+//! let mut chip8 = Chip8::new();
+//! // DRW V0, V0, 5 with I pointing at the font glyph "0"
+//! chip8.load_program(&[0xD0, 0x05]);
+//! chip8.step_frame(1).unwrap();
+//! assert!(chip8.display[0]);
+//! ```
+//!
+//! The other modules are the tools built on top of it: assembler ([`asm`]), Octo compiler
+//! ([`octo`]), disassembler ([`disasm`]), control-flow graphs ([`cfg`](mod@cfg)), decompiler
+//! ([`decompile`]), execution traces ([`trace`]), profiler ([`profile`]), coverage maps
+//! ([`coverage`]), screenshots and recording ([`capture`]), the [`lint`] mode, and the
+//! interactive ([`debugger`]) and GDB remote ([`gdb`]) debuggers with their [`watch`]points,
+//! [`expr`]essions for conditional breakpoints and execution [`history`] for reverse stepping.
+//!
+//! Without the default `std` feature the crate is `no_std` and only contains the interpreter
+//! ([`chip8`], [`bus`], [`instruction`]); randomness and time come from the host through the traits in
+//! [`host`].
+#![cfg_attr(all(not(feature = "std"), not(test)), no_std)]
+#![warn(missing_docs)]
+
+#[cfg(feature = "std")]
+pub mod asm;
+pub mod bus;
+#[cfg(feature = "std")]
+pub mod capture;
+#[cfg(feature = "std")]
+pub mod cfg;
+pub mod chip8;
+#[cfg(feature = "std")]
+pub mod coverage;
+#[cfg(feature = "std")]
+pub mod debugger;
+#[cfg(feature = "std")]
+pub mod decompile;
+#[cfg(feature = "std")]
+pub mod disasm;
+#[cfg(feature = "std")]
+pub mod expr;
+#[cfg(feature = "std")]
+pub mod gdb;
+#[cfg(feature = "std")]
+pub mod history;
+pub mod host;
+pub mod instruction;
+#[cfg(feature = "std")]
+pub mod lint;
+#[cfg(feature = "std")]
+pub mod octo;
+#[cfg(feature = "std")]
+pub mod profile;
+#[cfg(feature = "std")]
+pub mod trace;
+#[cfg(feature = "std")]
+pub mod watch;
+
+pub use bus::Bus;
+pub use chip8::{Chip8, CpuState, Fault, Quirks, StateError, STATE_SIZE};
@@ -0,0 +1,310 @@
+//! Lint mode: warnings about behavior which works on this interpreter by accident.
+//!
+//! Checked before each instruction, against the state it executes from:
+//!
+//! - executing memory neither loaded nor written by the program
+//! - writing into the interpreter area 0x000-0x1FF, which holds the font
+//! - self-modifying code: overwriting executed instructions, executing written bytes
+//! - I accessing memory past 0xFFF, other interpreters do not wrap around
+//! - calls nesting deeper than the 12 levels of the original interpreter
+//! - `LD F, Vx` with Vx above 0xF, which has no font digit
+//! - jumps and calls to odd addresses
+//!
+//! Every lint is reported once per instruction address.
+use crate::chip8::Chip8;
+use crate::disasm::disassemble;
+use crate::instruction::{self, Instruction};
+use crate::watch::{memory_access, Access};
+
+use std::collections::BTreeSet;
+use std::fmt;
+
+// NOTE: the font is loaded at 0x000 (80 bytes)
+const FONT_SIZE: usize = 80;
+const MEMORY_SIZE: usize = 0x1000;
+const PROGRAM_START: usize = 0x200;
+// stack levels of the COSMAC VIP interpreter
+const MAX_STACK_DEPTH: u8 = 12;
+
+// flags of a byte
+const INITIALIZED: u8 = 0x1;
+const EXECUTED: u8 = 0x2;
+const WRITTEN: u8 = 0x4;
+
+/// Kind of suspicious behaviour.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum Lint {
+    /// Executing memory which was never loaded or written.
+    UninitializedExecution,
+    /// Writing below 0x200, where the interpreter lives.
+    InterpreterAreaWrite,
+    /// Executing memory written at runtime, or writing memory which was executed.
+    SelfModifyingCode,
+    /// Accessing memory through I past its end.
+    IndexPastEnd,
+    /// Nesting calls deeper than the stack.
+    StackDepth,
+    /// Loading the font address of a value larger than 0xF.
+    FontDigit,
+    /// Jumping or calling to an odd address.
+    OddJump,
+}
+
+impl fmt::Display for Lint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Lint::UninitializedExecution => "uninitialized-execution",
+            Lint::InterpreterAreaWrite => "interpreter-area-write",
+            Lint::SelfModifyingCode => "self-modifying-code",
+            Lint::IndexPastEnd => "index-past-end",
+            Lint::StackDepth => "stack-depth",
+            Lint::FontDigit => "font-digit",
+            Lint::OddJump => "odd-jump",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Lint reported for an instruction.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Warning {
+    /// Kind of the warning.
+    pub lint: Lint,
+    /// Address of the instruction.
+    pub address: u16,
+    /// Opcode of the instruction.
+    pub opcode: u16,
+    /// What happened, e.g. the address written.
+    pub detail: String,
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "0x{:03X} {}: {} [{}]",
+            self.address,
+            disassemble(self.opcode),
+            self.detail,
+            self.lint
+        )
+    }
+}
+
+/// Tracks memory initialization and execution to report suspicious instructions.
+pub struct Linter {
+    memory: Vec<u8>,
+    reported: BTreeSet<(Lint, u16)>,
+}
+
+impl Linter {
+    /// Linter for `program`, the ROM loaded at 0x200.
+    pub fn new(program: &[u8]) -> Linter {
+        let mut memory = vec![0; MEMORY_SIZE];
+        let end = (PROGRAM_START + program.len()).min(MEMORY_SIZE);
+        memory[..FONT_SIZE].fill(INITIALIZED);
+        memory[PROGRAM_START..end].fill(INITIALIZED);
+        Linter {
+            memory,
+            reported: BTreeSet::new(),
+        }
+    }
+
+    /// Called before each instruction, returns the new warnings.
+    pub fn on_instruction(&mut self, chip8: &Chip8) -> Vec<Warning> {
+        let state = chip8.cpu_state();
+        let pc = state.program_counter % MEMORY_SIZE as u16;
+        let opcode = chip8.read_memory_u16(pc);
+        let instruction = instruction::decode(opcode);
+        let mut lints: Vec<(Lint, String)> = Vec::new();
+
+        let bytes = [pc as usize, (pc as usize + 1) % MEMORY_SIZE];
+        if bytes
+            .iter()
+            .any(|address| self.memory[*address] & INITIALIZED == 0)
+        {
+            lints.push((
+                Lint::UninitializedExecution,
+                "executes memory the program never loaded or wrote".to_string(),
+            ));
+        }
+        if bytes
+            .iter()
+            .any(|address| self.memory[*address] & WRITTEN != 0)
+        {
+            lints.push((
+                Lint::SelfModifyingCode,
+                "executes an instruction written at runtime".to_string(),
+            ));
+        }
+        for address in bytes.iter() {
+            self.memory[*address] |= EXECUTED;
+        }
+
+        let target = match instruction {
+            Instruction::Jp(nnn) | Instruction::Call(nnn) => Some(nnn),
+            Instruction::JpV0(nnn) => {
+                let x = if chip8.quirks().jump_vx {
+                    (nnn >> 8) as u8
+                } else {
+                    0
+                };
+                Some(nnn + state.registers[x as usize] as u16)
+            }
+            _ => None,
+        };
+        if let Some(target) = target.filter(|target| target % 2 != 0) {
+            lints.push((
+                Lint::OddJump,
+                format!("jumps to odd address 0x{:03X}", target),
+            ));
+        }
+
+        match instruction {
+            Instruction::Call(_) if state.stack_pointer >= MAX_STACK_DEPTH => lints.push((
+                Lint::StackDepth,
+                format!(
+                    "calls nest {} levels deep, more than {}",
+                    state.stack_pointer + 1,
+                    MAX_STACK_DEPTH
+                ),
+            )),
+            Instruction::LdF(x) if state.registers[x as usize] > 0xF => lints.push((
+                Lint::FontDigit,
+                format!(
+                    "V{:X} = 0x{:02X} is no font digit",
+                    x, state.registers[x as usize]
+                ),
+            )),
+            _ => (),
+        }
+
+        if let Some((access, start, len)) = memory_access(instruction, &state) {
+            if start as usize + len as usize > MEMORY_SIZE {
+                lints.push((
+                    Lint::IndexPastEnd,
+                    format!(
+                        "I = 0x{:03X} accesses {} bytes, past the end of memory",
+                        start, len
+                    ),
+                ));
+            }
+            if access == Access::Write {
+                let addresses: Vec<usize> = (0..len)
+                    .map(|offset| (start as usize + offset as usize) % MEMORY_SIZE)
+                    .collect();
+                if let Some(address) = addresses.iter().find(|a| **a < PROGRAM_START) {
+                    lints.push((
+                        Lint::InterpreterAreaWrite,
+                        format!("writes 0x{:03X} in the interpreter area", address),
+                    ));
+                }
+                if let Some(address) = addresses.iter().find(|a| self.memory[**a] & EXECUTED != 0) {
+                    lints.push((
+                        Lint::SelfModifyingCode,
+                        format!("overwrites the executed instruction at 0x{:03X}", address),
+                    ));
+                }
+                for address in addresses {
+                    self.memory[address] |= INITIALIZED | WRITTEN;
+                }
+            }
+        }
+
+        lints
+            .into_iter()
+            .filter(|(lint, _)| self.reported.insert((*lint, pc)))
+            .map(|(lint, detail)| Warning {
+                lint,
+                address: pc,
+                opcode,
+                detail,
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // lints raised running opcodes for n instructions
+    fn lint(opcodes: &[u16], n: usize) -> Vec<(Lint, u16)> {
+        let program: Vec<u8> = opcodes.iter().flat_map(|op| op.to_be_bytes()).collect();
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&program);
+        let mut linter = Linter::new(&program);
+        let mut lints = Vec::new();
+        for _ in 0..n {
+            for warning in linter.on_instruction(&chip8) {
+                lints.push((warning.lint, warning.address));
+            }
+            chip8.step();
+        }
+        lints
+    }
+
+    #[test]
+    fn clean() {
+        // LD I, 0x300; LD [I], V0; CALL 0x208; JP 0x206; RET
+        assert_eq!(lint(&[0xA300, 0xF055, 0x2208, 0x1206, 0x00EE], 20), []);
+    }
+
+    #[test]
+    fn memory() {
+        // LD I, 0x050; LD [I], V0; LD I, 0xFFE; LD B, V0; JP 0x300
+        assert_eq!(
+            lint(&[0xA050, 0xF055, 0xAFFE, 0xF033, 0x1300], 6),
+            [
+                (Lint::InterpreterAreaWrite, 0x202),
+                (Lint::IndexPastEnd, 0x206),
+                (Lint::InterpreterAreaWrite, 0x206),
+                (Lint::UninitializedExecution, 0x300),
+            ]
+        );
+    }
+
+    #[test]
+    fn self_modifying_code() {
+        // LD V0, 0x12; LD V1, 0x0A; LD I, 0x200; LD [I], V1; JP 0x200
+        assert_eq!(
+            lint(&[0x6012, 0x610A, 0xA200, 0xF155, 0x1200], 6),
+            [
+                (Lint::SelfModifyingCode, 0x206),
+                (Lint::SelfModifyingCode, 0x200),
+            ]
+        );
+    }
+
+    #[test]
+    fn control_flow() {
+        // LD V0, 0x13; LD F, V0; JP V0, 0x2F0; ...; 0x303: CALL 0x303
+        let mut program = vec![0x6013, 0xF029, 0xB2F0];
+        program.resize(0x81, 0);
+        program.extend_from_slice(&[0x0023, 0x0300]);
+        let lints = lint(&program, 17);
+        assert_eq!(
+            lints,
+            [
+                (Lint::FontDigit, 0x202),
+                (Lint::OddJump, 0x204),
+                (Lint::OddJump, 0x303),
+                (Lint::StackDepth, 0x303),
+            ]
+        );
+    }
+
+    #[test]
+    fn display() {
+        let warning = Warning {
+            lint: Lint::OddJump,
+            address: 0x204,
+            opcode: 0x1203,
+            detail: "jumps to odd address 0x203".to_string(),
+        };
+        assert_eq!(
+            warning.to_string(),
+            "0x204 JP 0x203: jumps to odd address 0x203 [odd-jump]"
+        );
+    }
+}
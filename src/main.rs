@@ -1,273 +1,310 @@
-mod chip8;
+#[cfg(feature = "sdl")]
+mod frontend;
 
-use chip8::Chip8;
+use chip8_emulator::capture::{self, Palette};
+use chip8_emulator::coverage::Coverage;
+use chip8_emulator::disasm::{self, Syntax};
+use chip8_emulator::trace::{self, AddressRange, Mismatch, TraceFormat};
+use chip8_emulator::{asm, cfg, decompile, octo, Chip8, Quirks};
 
 use std::fs::File;
 use std::io::prelude::*;
 
-use sdl2::event::Event;
-use sdl2::keyboard::Keycode;
-use sdl2::pixels::Color;
-use sdl2::pixels::PixelFormatEnum;
-use sdl2::EventPump;
-
 use clap::{AppSettings, Clap};
 
-#[macro_use]
-extern crate lazy_static;
-
 #[derive(Clap)]
 #[clap(setting = AppSettings::ColoredHelp)]
+#[clap(setting = AppSettings::ArgsNegateSubcommands)]
 struct Opts {
-    filepath: String,
+    /// ROM to run
+    filepath: Option<String>,
+    /// Window and capture scale
+    #[clap(long, default_value = "10", parse(try_from_str = capture::parse_scale))]
+    scale: usize,
+    /// Interpreter behavior preset
+    #[clap(long, default_value = "default", possible_values = &["default", "cosmac", "schip"])]
+    quirks: Quirks,
+    /// Display colors as FOREGROUND,BACKGROUND in hex
+    #[clap(long, default_value = "FFFFFF,000000")]
+    palette: Palette,
+    /// Directory where screenshots (F12) are written
+    #[clap(long, default_value = ".")]
+    screenshot_dir: String,
+    /// Record gameplay, as GIF if the path ends with .gif, otherwise as raw RGB24 frames ("-" for stdout)
+    #[clap(long)]
+    record: Option<String>,
+    /// Start in the interactive debugger (F9 breaks into it while running)
+    #[clap(long)]
+    debug: bool,
+    /// Wait for a GDB remote protocol connection on this localhost port before starting
+    #[clap(long, conflicts_with = "debug")]
+    gdb: Option<u16>,
+    /// Write a per-instruction execution trace to this path ("-" for stdout)
+    #[clap(long)]
+    trace: Option<String>,
+    /// Trace file format
+    #[clap(long, default_value = "text", possible_values = &["text", "binary"])]
+    trace_format: TraceFormat,
+    /// Only trace instructions in this inclusive hex address range, e.g. 200-2FF
+    #[clap(long)]
+    trace_range: Option<AddressRange>,
+    /// Profile execution and write the report to this path at exit, as JSON if it ends with .json
+    #[clap(long)]
+    profile: Option<String>,
+    /// Also write the disassembly annotated with execution counts to this path
+    #[clap(long, requires = "profile")]
+    profile_disasm: Option<String>,
+    /// Write the code/data coverage map of memory to this path at exit
+    #[clap(long)]
+    coverage: Option<String>,
+    /// Warn about behavior which only works on this interpreter by accident (see src/lint.rs)
+    #[clap(long)]
+    lint: bool,
+    /// Stop at the first lint warning, in the debugger with --debug
+    #[clap(long)]
+    lint_halt: bool,
+    #[clap(subcommand)]
+    command: Option<Command>,
 }
 
-fn handle_user_input(chip8: &mut Chip8, event_pump: &mut EventPump) {
-    for event in event_pump.poll_iter() {
-        match event {
-            Event::Quit { .. }
-            | Event::KeyDown {
-                keycode: Some(Keycode::Escape),
-                ..
-            } => std::process::exit(0),
-
-            Event::KeyDown {
-                keycode: Some(Keycode::Num1),
-                ..
-            } => chip8.keyboard[0x1] = true,
-            Event::KeyUp {
-                keycode: Some(Keycode::Num1),
-                repeat: false,
-                ..
-            } => chip8.keyboard[0x1] = false,
-
-            Event::KeyDown {
-                keycode: Some(Keycode::Num2),
-                repeat: false,
-                ..
-            } => chip8.keyboard[0x2] = true,
-            Event::KeyUp {
-                keycode: Some(Keycode::Num2),
-                repeat: false,
-                ..
-            } => chip8.keyboard[0x2] = false,
-
-            Event::KeyDown {
-                keycode: Some(Keycode::Num3),
-                ..
-            } => chip8.keyboard[0x3] = true,
-            Event::KeyUp {
-                keycode: Some(Keycode::Num3),
-                repeat: false,
-                ..
-            } => chip8.keyboard[0x3] = false,
-
-            Event::KeyDown {
-                keycode: Some(Keycode::Num4),
-                ..
-            } => chip8.keyboard[0xC] = true,
-            Event::KeyUp {
-                keycode: Some(Keycode::Num4),
-                repeat: false,
-                ..
-            } => chip8.keyboard[0xC] = false,
-
-            Event::KeyDown {
-                keycode: Some(Keycode::Q),
-                ..
-            } => chip8.keyboard[0x4] = true,
-            Event::KeyUp {
-                keycode: Some(Keycode::Q),
-                repeat: false,
-                ..
-            } => chip8.keyboard[0x4] = false,
-
-            Event::KeyDown {
-                keycode: Some(Keycode::W),
-                ..
-            } => chip8.keyboard[0x5] = true,
-            Event::KeyUp {
-                keycode: Some(Keycode::W),
-                repeat: false,
-                ..
-            } => chip8.keyboard[0x5] = false,
-
-            Event::KeyDown {
-                keycode: Some(Keycode::E),
-                ..
-            } => chip8.keyboard[0x6] = true,
-            Event::KeyUp {
-                keycode: Some(Keycode::E),
-                repeat: false,
-                ..
-            } => chip8.keyboard[0x6] = false,
-
-            Event::KeyDown {
-                keycode: Some(Keycode::R),
-                ..
-            } => chip8.keyboard[0xD] = true,
-            Event::KeyUp {
-                keycode: Some(Keycode::R),
-                repeat: false,
-                ..
-            } => chip8.keyboard[0xD] = false,
-
-            Event::KeyDown {
-                keycode: Some(Keycode::A),
-                ..
-            } => chip8.keyboard[0x7] = true,
-            Event::KeyUp {
-                keycode: Some(Keycode::A),
-                repeat: false,
-                ..
-            } => chip8.keyboard[0x7] = false,
-
-            Event::KeyDown {
-                keycode: Some(Keycode::S),
-                ..
-            } => chip8.keyboard[0x8] = true,
-            Event::KeyUp {
-                keycode: Some(Keycode::S),
-                repeat: false,
-                ..
-            } => chip8.keyboard[0x8] = false,
-
-            Event::KeyDown {
-                keycode: Some(Keycode::D),
-                ..
-            } => chip8.keyboard[0x9] = true,
-            Event::KeyUp {
-                keycode: Some(Keycode::D),
-                repeat: false,
-                ..
-            } => chip8.keyboard[0x9] = false,
+#[derive(Clap)]
+enum Command {
+    /// Disassemble ROM
+    Disasm(DisasmOpts),
+    /// Recover the control-flow graph of ROM and print it in Graphviz DOT
+    Cfg(CfgOpts),
+    /// Decompile ROM into structured pseudocode
+    Decompile(DecompileOpts),
+    /// Assemble source into ROM
+    Asm(AsmOpts),
+    /// Compile Octo source into ROM
+    Octo(OctoOpts),
+    /// Run ROM in lockstep with a reference trace and report the first divergence
+    TraceDiff(TraceDiffOpts),
+}
 
-            Event::KeyDown {
-                keycode: Some(Keycode::F),
-                ..
-            } => chip8.keyboard[0xE] = true,
-            Event::KeyUp {
-                keycode: Some(Keycode::F),
-                repeat: false,
-                ..
-            } => chip8.keyboard[0xE] = false,
+#[derive(Clap)]
+struct DisasmOpts {
+    /// ROM to disassemble
+    filepath: String,
+    /// Mnemonic syntax
+    #[clap(long, default_value = "cowgod", possible_values = &["cowgod", "octo"])]
+    syntax: Syntax,
+    /// Coverage map written by --coverage, to tell code from data
+    #[clap(long)]
+    coverage: Option<String>,
+}
 
-            Event::KeyDown {
-                keycode: Some(Keycode::Z),
-                ..
-            } => chip8.keyboard[0xA] = true,
-            Event::KeyUp {
-                keycode: Some(Keycode::Z),
-                repeat: false,
-                ..
-            } => chip8.keyboard[0xA] = false,
+#[derive(Clap)]
+struct CfgOpts {
+    /// ROM to analyse
+    filepath: String,
+    /// Mnemonic syntax
+    #[clap(long, default_value = "cowgod", possible_values = &["cowgod", "octo"])]
+    syntax: Syntax,
+}
 
-            Event::KeyDown {
-                keycode: Some(Keycode::X),
-                ..
-            } => chip8.keyboard[0x0] = true,
-            Event::KeyUp {
-                keycode: Some(Keycode::X),
-                repeat: false,
-                ..
-            } => chip8.keyboard[0x0] = false,
+#[derive(Clap)]
+struct DecompileOpts {
+    /// ROM to decompile
+    filepath: String,
+}
 
-            Event::KeyDown {
-                keycode: Some(Keycode::C),
-                ..
-            } => chip8.keyboard[0xB] = true,
-            Event::KeyUp {
-                keycode: Some(Keycode::C),
-                repeat: false,
-                ..
-            } => chip8.keyboard[0xB] = false,
+#[derive(Clap)]
+struct OctoOpts {
+    /// Octo source to compile
+    filepath: String,
+    /// Output ROM (default: source path with .ch8 extension)
+    #[clap(short, long)]
+    output: Option<String>,
+    /// Write symbol table ("0xADDR name" per line) to this path
+    #[clap(long)]
+    symbols: Option<String>,
+}
 
-            Event::KeyDown {
-                keycode: Some(Keycode::V),
-                ..
-            } => chip8.keyboard[0xF] = true,
-            Event::KeyUp {
-                keycode: Some(Keycode::V),
-                repeat: false,
-                ..
-            } => chip8.keyboard[0xF] = false,
+#[derive(Clap)]
+struct TraceDiffOpts {
+    /// ROM to run
+    filepath: String,
+    /// Reference trace in text format (see src/trace.rs)
+    reference: String,
+    /// Number of preceding instructions printed on divergence
+    #[clap(long, default_value = "10")]
+    context: usize,
+    /// Instructions executed per 60hz timer tick
+    #[clap(long, default_value = "10")]
+    instructions_per_frame: usize,
+    /// Interpreter behavior preset
+    #[clap(long, default_value = "default", possible_values = &["default", "cosmac", "schip"])]
+    quirks: Quirks,
+}
 
-            _ => (),
-        }
-    }
+#[derive(Clap)]
+struct AsmOpts {
+    /// Source to assemble
+    filepath: String,
+    /// Output ROM (default: source path with .ch8 extension)
+    #[clap(short, long)]
+    output: Option<String>,
 }
 
-fn color(b: bool) -> Color {
-    match b {
-        true => sdl2::pixels::Color::WHITE,
-        false => sdl2::pixels::Color::BLACK,
+fn read_rom(filepath: &str) -> Vec<u8> {
+    // compile Octo source
+    if filepath.ends_with(".8o") {
+        return compile_octo(filepath).rom;
     }
+
+    let mut f = File::open(filepath).expect("Failed to open the file");
+    let mut program: Vec<u8> = Vec::new();
+    f.read_to_end(&mut program)
+        .expect("failed to read the file");
+    program
 }
 
-// read screen from chip8, coloring, if pixel changed, update screen state
-fn read_screen_state(chip8: &Chip8, screen_state: &mut [u8; 64 * 32 * 3]) -> bool {
-    let mut idx = 0;
-    let mut update = false;
-    for i in 0..(64 * 32) {
-        let b = chip8.display[i];
-        let (r, g, b) = color(b).rgb();
-        if screen_state[idx] != r || screen_state[idx + 1] != g || screen_state[idx + 2] != b {
-            screen_state[idx] = r;
-            screen_state[idx + 1] = g;
-            screen_state[idx + 2] = b;
-            update = true
+fn compile_octo(filepath: &str) -> octo::Program {
+    let source = std::fs::read_to_string(filepath).expect("failed to read the file");
+    match octo::compile(&source) {
+        Ok(program) => program,
+        Err(e) => {
+            eprintln!("error: {}:{}", filepath, e);
+            std::process::exit(1);
         }
-        idx += 3;
     }
-    update
 }
 
 fn main() {
     env_logger::init();
 
     // parse args
-    let opts: Opts = Opts::parse();
+    let mut opts: Opts = Opts::parse();
+
+    match opts.command.take() {
+        Some(Command::Disasm(disasm_opts)) => run_disasm(disasm_opts),
+        Some(Command::Cfg(cfg_opts)) => run_cfg(cfg_opts),
+        Some(Command::Decompile(decompile_opts)) => run_decompile(decompile_opts),
+        Some(Command::Asm(asm_opts)) => run_asm(asm_opts),
+        Some(Command::Octo(octo_opts)) => run_octo(octo_opts),
+        Some(Command::TraceDiff(trace_diff_opts)) => run_trace_diff(trace_diff_opts),
+        #[cfg(feature = "sdl")]
+        None => frontend::run_emulator(opts),
+        #[cfg(not(feature = "sdl"))]
+        None => {
+            eprintln!("error: built without the sdl feature, only subcommands are available");
+            std::process::exit(2);
+        }
+    }
+}
 
-    // open ROM
-    let mut f = File::open(opts.filepath).expect("Failed to open the file");
-    let mut program: Vec<u8> = Vec::new();
-    f.read_to_end(&mut program)
-        .expect("failed to read the file");
+fn run_disasm(opts: DisasmOpts) {
+    let program = read_rom(&opts.filepath);
+    let code = match opts.coverage {
+        Some(path) => {
+            let text = std::fs::read_to_string(&path).expect("failed to read the file");
+            match Coverage::parse(&text) {
+                Ok(coverage) => coverage.code(&program),
+                Err(e) => {
+                    eprintln!("error: {}:{}", path, e);
+                    std::process::exit(1);
+                }
+            }
+        }
+        None => disasm::trace_code(&program),
+    };
+    print!(
+        "{}",
+        disasm::disassemble_rom_with_code(&program, opts.syntax, &code)
+    );
+}
 
-    // init sdl2
-    let sdl_context = sdl2::init().unwrap();
-    let video_subsystem = sdl_context.video().unwrap();
-    let window = video_subsystem
-        .window(
-            "Chip-8 Emulator",
-            (64.0 * 10.0) as u32,
-            (32.0 * 10.0) as u32,
-        )
-        .position_centered()
-        .build()
-        .unwrap();
+fn run_cfg(opts: CfgOpts) {
+    let program = read_rom(&opts.filepath);
+    print!("{}", cfg::recover(&program).to_dot(opts.syntax));
+}
 
-    let mut canvas = window.into_canvas().present_vsync().build().unwrap();
-    let mut event_pump = sdl_context.event_pump().unwrap();
-    canvas.set_scale(10.0, 10.0).unwrap();
+fn run_decompile(opts: DecompileOpts) {
+    let program = read_rom(&opts.filepath);
+    print!("{}", decompile::decompile(&program));
+}
 
-    let creator = canvas.texture_creator();
-    let mut texture = creator
-        .create_texture_target(PixelFormatEnum::RGB24, 64, 32)
-        .unwrap();
+fn run_octo(opts: OctoOpts) {
+    let program = compile_octo(&opts.filepath);
 
-    let mut screen_state = [0_u8; 64 * 32 * 3];
+    let filepath = opts.filepath;
+    let output = opts.output.unwrap_or_else(|| {
+        std::path::Path::new(&filepath)
+            .with_extension("ch8")
+            .to_string_lossy()
+            .into_owned()
+    });
+    std::fs::write(&output, &program.rom).expect("failed to write the file");
 
-    // start emulator
-    let mut chip8 = Chip8::new();
-    chip8.load_program(program);
-    chip8.run_with_callback(move |chip8| {
-        handle_user_input(chip8, &mut event_pump);
+    if let Some(symbols) = opts.symbols {
+        std::fs::write(&symbols, program.symbol_table()).expect("failed to write the file");
+    }
+}
 
-        if read_screen_state(chip8, &mut screen_state) {
-            texture.update(None, &screen_state, 64 * 3).unwrap();
-            canvas.copy(&texture, None, None).unwrap();
-            canvas.present();
+fn run_asm(opts: AsmOpts) {
+    let rom = match asm::assemble_file(&opts.filepath) {
+        Ok(rom) => rom,
+        Err(e) => {
+            eprintln!("error: {}", e);
+            std::process::exit(1);
         }
+    };
+
+    let filepath = opts.filepath;
+    let output = opts.output.unwrap_or_else(|| {
+        std::path::Path::new(&filepath)
+            .with_extension("ch8")
+            .to_string_lossy()
+            .into_owned()
     });
+    std::fs::write(&output, rom).expect("failed to write the file");
+}
+
+fn run_trace_diff(opts: TraceDiffOpts) {
+    let program = read_rom(&opts.filepath);
+    let text = std::fs::read_to_string(&opts.reference).expect("failed to read the file");
+    let reference = match trace::parse_reference(&text) {
+        Ok(reference) => reference,
+        Err(e) => {
+            eprintln!("error: {}:{}", opts.reference, e);
+            std::process::exit(1);
+        }
+    };
+
+    let mut chip8 = Chip8::new();
+    chip8.set_quirks(opts.quirks);
+    chip8.load_program(&program);
+    let instructions_per_frame = opts.instructions_per_frame.max(1);
+    match trace::run_lockstep(&mut chip8, &reference, instructions_per_frame, opts.context) {
+        None => println!("{} instructions match the reference", reference.len()),
+        Some(Mismatch::Divergence(divergence)) => {
+            println!(
+                "divergence after {} instructions in {}",
+                divergence.index,
+                divergence
+                    .expected
+                    .differences(&divergence.actual)
+                    .join(", ")
+            );
+            for record in divergence.context.iter() {
+                println!("           {}", record.to_line());
+            }
+            println!("expected:  {}", divergence.expected.to_line());
+            println!("actual:    {}", divergence.actual.to_line());
+            std::process::exit(1);
+        }
+        Some(Mismatch::Fault(fault)) => {
+            println!(
+                "ROM stopped at 0x{:03X} after {} instructions: {}",
+                fault.record.program_counter, fault.index, fault.fault
+            );
+            for record in fault.context.iter() {
+                println!("           {}", record.to_line());
+            }
+            println!("faulted:   {}", fault.record.to_line());
+            std::process::exit(1);
+        }
+    }
 }
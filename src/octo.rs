@@ -0,0 +1,1137 @@
+//! Compiler for the Octo assembly language (CHIP-8 subset).
+//!
+//! ```text
+//! :const SPEED 2
+//! :alias x v0
+//! : main
+//!   x := 0
+//!   loop
+//!     x += SPEED
+//!     if x == 64 then x := 0
+//!     i := ball
+//!     sprite x v1 3
+//!   again
+//! : ball 0x3C 0x42 0x81
+//! ```
+//!
+//! Like Octo, unless `: main` is the first code, a jump to main is placed at 0x200.
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::fmt;
+
+const PROGRAM_START: u16 = 0x200;
+const MEMORY_SIZE: usize = 0x1000;
+
+// NOTE: protects against recursive macros
+const MAX_MACRO_EXPANSIONS: usize = 10000;
+
+/// Compile error.
+#[derive(Debug, PartialEq)]
+pub struct OctoError {
+    /// Line of the source, starting at 1.
+    pub line: usize,
+    /// What went wrong.
+    pub message: String,
+}
+
+impl fmt::Display for OctoError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "line {}: {}", self.line, self.message)
+    }
+}
+
+impl std::error::Error for OctoError {}
+
+/// Compiled ROM (loaded at 0x200) and label addresses.
+#[derive(Debug)]
+pub struct Program {
+    /// Compiled bytes.
+    pub rom: Vec<u8>,
+    /// Label addresses by name.
+    pub symbols: BTreeMap<String, u16>,
+}
+
+impl Program {
+    /// Symbol table as "0xADDR name" lines, ordered by address.
+    pub fn symbol_table(&self) -> String {
+        let mut symbols: Vec<(&u16, &String)> = self
+            .symbols
+            .iter()
+            .map(|(name, address)| (address, name))
+            .collect();
+        symbols.sort();
+        symbols
+            .iter()
+            .map(|(address, name)| format!("0x{:03X} {}\n", address, name))
+            .collect()
+    }
+}
+
+#[derive(Clone, Debug)]
+struct Token {
+    text: String,
+    line: usize,
+}
+
+struct Macro {
+    args: Vec<String>,
+    body: Vec<Token>,
+}
+
+#[derive(Clone, Copy)]
+enum Condition {
+    EqByte(u8, u8),
+    NeByte(u8, u8),
+    EqReg(u8, u8),
+    NeReg(u8, u8),
+    Key(u8),
+    NotKey(u8),
+    // <, >, <=, >=: holds when VF is set (or clear) after the subtraction of setup, like Octo
+    Flag { setup: [u16; 2], set: bool },
+}
+
+impl Condition {
+    fn negate(self) -> Condition {
+        match self {
+            Condition::EqByte(x, kk) => Condition::NeByte(x, kk),
+            Condition::NeByte(x, kk) => Condition::EqByte(x, kk),
+            Condition::EqReg(x, y) => Condition::NeReg(x, y),
+            Condition::NeReg(x, y) => Condition::EqReg(x, y),
+            Condition::Key(x) => Condition::NotKey(x),
+            Condition::NotKey(x) => Condition::Key(x),
+            Condition::Flag { setup, set } => Condition::Flag { setup, set: !set },
+        }
+    }
+
+    // instructions to execute before the skip
+    fn setup(self) -> Vec<u16> {
+        match self {
+            Condition::Flag { setup, .. } => setup.to_vec(),
+            _ => Vec::new(),
+        }
+    }
+
+    // instruction which skips the next one unless condition holds
+    fn skip_unless(self) -> u16 {
+        let x = |x: u8| (x as u16) << 8;
+        let y = |y: u8| (y as u16) << 4;
+        match self {
+            Condition::EqByte(vx, kk) => 0x4000 | x(vx) | kk as u16,
+            Condition::NeByte(vx, kk) => 0x3000 | x(vx) | kk as u16,
+            Condition::EqReg(vx, vy) => 0x9000 | x(vx) | y(vy),
+            Condition::NeReg(vx, vy) => 0x5000 | x(vx) | y(vy),
+            Condition::Key(vx) => 0xE0A1 | x(vx),
+            Condition::NotKey(vx) => 0xE09E | x(vx),
+            Condition::Flag { set: true, .. } => 0x4F01,
+            Condition::Flag { set: false, .. } => 0x3F01,
+        }
+    }
+}
+
+enum Block {
+    // jump over the if body
+    If(u16),
+    // jump over the else body
+    Else(u16),
+    // loop start, jumps out of the loop
+    Loop(u16, Vec<u16>),
+}
+
+struct Compiler {
+    tokens: VecDeque<Token>,
+    memory: Vec<u8>,
+    // current address and highest address written
+    here: u16,
+    end: u16,
+    line: usize,
+
+    labels: BTreeMap<String, u16>,
+    constants: HashMap<String, f64>,
+    aliases: HashMap<String, u8>,
+    macros: HashMap<String, Macro>,
+    // (address of 12bit operand, label)
+    fixups: Vec<(u16, String, usize)>,
+    blocks: Vec<(Block, usize)>,
+    macro_expansions: usize,
+}
+
+/// Compiles Octo source into a ROM.
+pub fn compile(source: &str) -> Result<Program, OctoError> {
+    let mut compiler = Compiler {
+        tokens: tokenize(source),
+        memory: vec![0; MEMORY_SIZE],
+        here: PROGRAM_START,
+        end: PROGRAM_START,
+        line: 1,
+        labels: BTreeMap::new(),
+        constants: HashMap::new(),
+        aliases: HashMap::new(),
+        macros: HashMap::new(),
+        fixups: Vec::new(),
+        blocks: Vec::new(),
+        macro_expansions: 0,
+    };
+    compiler.compile()
+}
+
+fn tokenize(source: &str) -> VecDeque<Token> {
+    let mut tokens = VecDeque::new();
+    for (index, line) in source.lines().enumerate() {
+        let code = match line.find('#') {
+            Some(i) => &line[..i],
+            None => line,
+        };
+        for word in code.split_whitespace() {
+            // split braces and parentheses into their own tokens
+            let mut current = String::new();
+            for c in word.chars() {
+                if "{}()".contains(c) {
+                    if !current.is_empty() {
+                        tokens.push_back(Token {
+                            text: current.clone(),
+                            line: index + 1,
+                        });
+                        current.clear();
+                    }
+                    tokens.push_back(Token {
+                        text: c.to_string(),
+                        line: index + 1,
+                    });
+                } else {
+                    current.push(c);
+                }
+            }
+            if !current.is_empty() {
+                tokens.push_back(Token {
+                    text: current,
+                    line: index + 1,
+                });
+            }
+        }
+    }
+    tokens
+}
+
+fn parse_number(s: &str) -> Option<f64> {
+    let (negative, digits) = match s.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, s),
+    };
+    let value = if let Some(hex) = digits.strip_prefix("0x") {
+        i64::from_str_radix(hex, 16).ok()? as f64
+    } else if let Some(bin) = digits.strip_prefix("0b") {
+        i64::from_str_radix(bin, 2).ok()? as f64
+    } else if digits.starts_with(|c: char| c.is_ascii_digit()) {
+        digits.parse::<f64>().ok()?
+    } else {
+        return None;
+    };
+    Some(if negative { -value } else { value })
+}
+
+fn register_name(s: &str) -> Option<u8> {
+    let digits = s.strip_prefix('v').or_else(|| s.strip_prefix('V'))?;
+    if digits.len() != 1 {
+        return None;
+    }
+    u8::from_str_radix(digits, 16).ok()
+}
+
+impl Compiler {
+    fn error<T>(&self, message: String) -> Result<T, OctoError> {
+        Err(OctoError {
+            line: self.line,
+            message,
+        })
+    }
+
+    fn next(&mut self) -> Result<String, OctoError> {
+        match self.tokens.pop_front() {
+            Some(token) => {
+                self.line = token.line;
+                Ok(token.text)
+            }
+            None => self.error("unexpected end of file".to_string()),
+        }
+    }
+
+    fn peek(&self) -> Option<&str> {
+        self.tokens.front().map(|t| t.text.as_str())
+    }
+
+    fn expect(&mut self, expected: &str) -> Result<(), OctoError> {
+        let token = self.next()?;
+        if token != expected {
+            return self.error(format!("expected '{}', got '{}'", expected, token));
+        }
+        Ok(())
+    }
+
+    fn compile(&mut self) -> Result<Program, OctoError> {
+        // reserve jump to main, dropped if main directly follows it
+        self.emit_jump(0x1000, "main")?;
+
+        while !self.tokens.is_empty() {
+            self.statement()?;
+        }
+
+        if let Some((_, line)) = self.blocks.last() {
+            self.line = *line;
+            return self.error("unterminated block".to_string());
+        }
+
+        for (address, label, line) in self.fixups.iter() {
+            let target = match self.labels.get(label) {
+                Some(target) => *target,
+                None => {
+                    return Err(OctoError {
+                        line: *line,
+                        message: format!("undefined label: {}", label),
+                    })
+                }
+            };
+            let index = *address as usize;
+            self.memory[index] |= (target >> 8) as u8;
+            self.memory[index + 1] = (target & 0xFF) as u8;
+        }
+
+        Ok(Program {
+            rom: self.memory[(PROGRAM_START as usize)..(self.end as usize)].to_vec(),
+            symbols: self.labels.clone(),
+        })
+    }
+
+    // nothing but the jump to main has been emitted or referenced yet
+    fn is_only_reserved_jump(&self) -> bool {
+        self.here == PROGRAM_START + 2
+            && self.end == PROGRAM_START + 2
+            && self.labels.is_empty()
+            && self.fixups.len() == 1
+    }
+
+    fn emit_byte(&mut self, value: u8) -> Result<(), OctoError> {
+        if self.here as usize >= MEMORY_SIZE {
+            return self.error("program too large".to_string());
+        }
+        self.memory[self.here as usize] = value;
+        self.here += 1;
+        self.end = self.end.max(self.here);
+        Ok(())
+    }
+
+    fn emit(&mut self, opcode: u16) -> Result<(), OctoError> {
+        self.emit_byte((opcode >> 8) as u8)?;
+        self.emit_byte((opcode & 0xFF) as u8)
+    }
+
+    // emit instruction with 12bit address of label, resolved later if not defined yet
+    fn emit_jump(&mut self, opcode: u16, label: &str) -> Result<(), OctoError> {
+        match self.labels.get(label) {
+            Some(address) => self.emit(opcode | address),
+            None => {
+                self.fixups.push((self.here, label.to_string(), self.line));
+                self.emit(opcode)
+            }
+        }
+    }
+
+    // patch 12bit address of instruction at address
+    fn patch(&mut self, address: u16, target: u16) {
+        let index = address as usize;
+        self.memory[index] = (self.memory[index] & 0xF0) | (target >> 8) as u8;
+        self.memory[index + 1] = (target & 0xFF) as u8;
+    }
+
+    fn register(&mut self) -> Result<u8, OctoError> {
+        let token = self.next()?;
+        self.to_register(&token)
+    }
+
+    fn to_register(&self, token: &str) -> Result<u8, OctoError> {
+        match register_name(token).or_else(|| self.aliases.get(token).copied()) {
+            Some(x) => Ok(x),
+            None => self.error(format!("expected register, got '{}'", token)),
+        }
+    }
+
+    fn is_register(&self, token: &str) -> bool {
+        register_name(token).is_some() || self.aliases.contains_key(token)
+    }
+
+    // value of number, constant or defined label
+    fn value_of(&self, token: &str) -> Option<f64> {
+        parse_number(token)
+            .or_else(|| self.constants.get(token).copied())
+            .or_else(|| self.labels.get(token).map(|a| *a as f64))
+    }
+
+    fn value(&mut self) -> Result<f64, OctoError> {
+        let token = self.next()?;
+        match self.value_of(&token) {
+            Some(v) => Ok(v),
+            None => self.error(format!("undefined name: {}", token)),
+        }
+    }
+
+    fn byte(&mut self) -> Result<u8, OctoError> {
+        let v = self.value()?.floor() as i64;
+        if !(-128..=255).contains(&v) {
+            return self.error(format!("byte out of range: {}", v));
+        }
+        Ok(v as u8)
+    }
+
+    fn nibble(&mut self) -> Result<u8, OctoError> {
+        let v = self.value()?.floor() as i64;
+        if !(0..=15).contains(&v) {
+            return self.error(format!("nibble out of range: {}", v));
+        }
+        Ok(v as u8)
+    }
+
+    // instruction with address operand, labels may be defined later
+    fn address_instruction(&mut self, opcode: u16) -> Result<(), OctoError> {
+        let token = self.next()?;
+        if parse_number(&token).is_none() && !self.constants.contains_key(&token) {
+            if !is_name(&token) {
+                return self.error(format!("invalid address: {}", token));
+            }
+            return self.emit_jump(opcode, &token);
+        }
+        let v = self.value_of(&token).unwrap().floor() as i64;
+        if !(0..MEMORY_SIZE as i64).contains(&v) {
+            return self.error(format!("address out of range: {}", v));
+        }
+        self.emit(opcode | v as u16)
+    }
+
+    fn define_name(&mut self, name: &str) -> Result<(), OctoError> {
+        if !is_name(name) || self.is_register(name) || is_keyword(name) {
+            return self.error(format!("invalid name: {}", name));
+        }
+        if self.labels.contains_key(name) || self.constants.contains_key(name) {
+            return self.error(format!("duplicate name: {}", name));
+        }
+        Ok(())
+    }
+
+    fn statement(&mut self) -> Result<(), OctoError> {
+        let token = self.next()?;
+        let xy = |x: u8, y: u8| ((x as u16) << 8) | ((y as u16) << 4);
+
+        match token.as_str() {
+            ":" => {
+                let name = self.next()?;
+                self.define_name(&name)?;
+                if name == "main" && self.is_only_reserved_jump() {
+                    self.fixups.clear();
+                    self.here = PROGRAM_START;
+                    self.end = PROGRAM_START;
+                }
+                self.labels.insert(name, self.here);
+            }
+            ":const" => {
+                let name = self.next()?;
+                self.define_name(&name)?;
+                let v = self.value()?;
+                self.constants.insert(name, v);
+            }
+            ":alias" => {
+                let name = self.next()?;
+                if !is_name(&name) || register_name(&name).is_some() || is_keyword(&name) {
+                    return self.error(format!("invalid name: {}", name));
+                }
+                let x = self.register()?;
+                self.aliases.insert(name, x);
+            }
+            ":calc" => {
+                let name = self.next()?;
+                self.define_name(&name)?;
+                self.expect("{")?;
+                let v = self.expression()?;
+                self.expect("}")?;
+                self.constants.insert(name, v);
+            }
+            ":macro" => self.define_macro()?,
+            ":org" => {
+                let v = self.value()?.floor() as i64;
+                // NOTE: the ROM starts at 0x200, anything below it would be lost
+                if !(PROGRAM_START as i64..MEMORY_SIZE as i64).contains(&v) {
+                    return self.error(format!("address out of range: {}", v));
+                }
+                self.here = v as u16;
+            }
+            ":byte" => {
+                let v = if self.peek() == Some("{") {
+                    self.next()?;
+                    let v = self.expression()?;
+                    self.expect("}")?;
+                    v
+                } else {
+                    self.value()?
+                };
+                let v = v.floor() as i64;
+                if !(-128..=255).contains(&v) {
+                    return self.error(format!("byte out of range: {}", v));
+                }
+                self.emit_byte(v as u8)?;
+            }
+            ":call" => self.address_instruction(0x2000)?,
+            "clear" => self.emit(0x00E0)?,
+            "return" | ";" => self.emit(0x00EE)?,
+            "jump" => self.address_instruction(0x1000)?,
+            "jump0" => self.address_instruction(0xB000)?,
+            "bcd" => {
+                let x = self.register()?;
+                self.emit(0xF033 | xy(x, 0))?;
+            }
+            "save" => {
+                let x = self.register()?;
+                self.emit(0xF055 | xy(x, 0))?;
+            }
+            "load" => {
+                let x = self.register()?;
+                self.emit(0xF065 | xy(x, 0))?;
+            }
+            "sprite" => {
+                let x = self.register()?;
+                let y = self.register()?;
+                let n = self.nibble()?;
+                self.emit(0xD000 | xy(x, y) | n as u16)?;
+            }
+            "delay" | "buzzer" => {
+                self.expect(":=")?;
+                let x = self.register()?;
+                let opcode = if token == "delay" { 0xF015 } else { 0xF018 };
+                self.emit(opcode | xy(x, 0))?;
+            }
+            "i" => {
+                let operator = self.next()?;
+                match operator.as_str() {
+                    ":=" => {
+                        if self.peek() == Some("hex") {
+                            self.next()?;
+                            let x = self.register()?;
+                            self.emit(0xF029 | xy(x, 0))?;
+                        } else {
+                            self.address_instruction(0xA000)?;
+                        }
+                    }
+                    "+=" => {
+                        let x = self.register()?;
+                        self.emit(0xF01E | xy(x, 0))?;
+                    }
+                    _ => return self.error(format!("unknown operator: i {}", operator)),
+                }
+            }
+            "if" => {
+                let condition = self.condition()?;
+                let keyword = self.next()?;
+                match keyword.as_str() {
+                    "then" => self.emit_skip_unless(condition)?,
+                    "begin" => {
+                        self.emit_skip_unless(condition.negate())?;
+                        self.blocks.push((Block::If(self.here), self.line));
+                        self.emit(0x1000)?;
+                    }
+                    _ => {
+                        return self.error(format!("expected 'then' or 'begin', got '{}'", keyword))
+                    }
+                }
+            }
+            "else" => match self.blocks.pop() {
+                Some((Block::If(jump), line)) => {
+                    let exit = self.here;
+                    self.emit(0x1000)?;
+                    self.patch(jump, self.here);
+                    self.blocks.push((Block::Else(exit), line));
+                }
+                _ => return self.error("'else' without 'if ... begin'".to_string()),
+            },
+            "end" => match self.blocks.pop() {
+                Some((Block::If(jump), _)) | Some((Block::Else(jump), _)) => {
+                    self.patch(jump, self.here);
+                }
+                _ => return self.error("'end' without 'if ... begin'".to_string()),
+            },
+            "loop" => self
+                .blocks
+                .push((Block::Loop(self.here, Vec::new()), self.line)),
+            "while" => {
+                let condition = self.condition()?;
+                if !self
+                    .blocks
+                    .iter()
+                    .any(|(b, _)| matches!(b, Block::Loop(..)))
+                {
+                    return self.error("'while' outside 'loop'".to_string());
+                }
+                self.emit_skip_unless(condition.negate())?;
+                let here = self.here;
+                if let Some((Block::Loop(_, exits), _)) = self
+                    .blocks
+                    .iter_mut()
+                    .rev()
+                    .find(|(b, _)| matches!(b, Block::Loop(..)))
+                {
+                    exits.push(here);
+                }
+                self.emit(0x1000)?;
+            }
+            "again" => match self.blocks.pop() {
+                Some((Block::Loop(start, exits), _)) => {
+                    self.emit(0x1000 | start)?;
+                    for exit in exits {
+                        self.patch(exit, self.here);
+                    }
+                }
+                _ => return self.error("'again' without 'loop'".to_string()),
+            },
+            _ if self.is_register(&token) => {
+                let x = self.to_register(&token)?;
+                self.assignment(x)?;
+            }
+            _ if self.macros.contains_key(&token) => self.expand_macro(&token)?,
+            _ => {
+                if let Some(v) =
+                    parse_number(&token).or_else(|| self.constants.get(&token).copied())
+                {
+                    // raw byte
+                    let v = v.floor() as i64;
+                    if !(-128..=255).contains(&v) {
+                        return self.error(format!("byte out of range: {}", v));
+                    }
+                    self.emit_byte(v as u8)?;
+                } else if is_name(&token) && !is_keyword(&token) {
+                    // call subroutine by name
+                    self.emit_jump(0x2000, &token)?;
+                } else {
+                    return self.error(format!("unexpected token: {}", token));
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn assignment(&mut self, x: u8) -> Result<(), OctoError> {
+        let xy = |x: u8, y: u8| ((x as u16) << 8) | ((y as u16) << 4);
+        let operator = self.next()?;
+
+        // operators taking only a register
+        let register_opcode = match operator.as_str() {
+            "|=" => Some(0x8001),
+            "&=" => Some(0x8002),
+            "^=" => Some(0x8003),
+            "=-" => Some(0x8007),
+            ">>=" => Some(0x8006),
+            "<<=" => Some(0x800E),
+            _ => None,
+        };
+        if let Some(opcode) = register_opcode {
+            let y = self.register()?;
+            return self.emit(opcode | xy(x, y));
+        }
+
+        let operand = match self.peek() {
+            Some(operand) => operand.to_string(),
+            None => return self.error("unexpected end of file".to_string()),
+        };
+        match operator.as_str() {
+            ":=" => match operand.as_str() {
+                "random" => {
+                    self.next()?;
+                    let kk = self.byte()?;
+                    self.emit(0xC000 | xy(x, 0) | kk as u16)
+                }
+                "key" => {
+                    self.next()?;
+                    self.emit(0xF00A | xy(x, 0))
+                }
+                "delay" => {
+                    self.next()?;
+                    self.emit(0xF007 | xy(x, 0))
+                }
+                _ if self.is_register(&operand) => {
+                    let y = self.register()?;
+                    self.emit(0x8000 | xy(x, y))
+                }
+                _ => {
+                    let kk = self.byte()?;
+                    self.emit(0x6000 | xy(x, 0) | kk as u16)
+                }
+            },
+            "+=" => {
+                if self.is_register(&operand) {
+                    let y = self.register()?;
+                    self.emit(0x8004 | xy(x, y))
+                } else {
+                    let kk = self.byte()?;
+                    self.emit(0x7000 | xy(x, 0) | kk as u16)
+                }
+            }
+            "-=" => {
+                if self.is_register(&operand) {
+                    let y = self.register()?;
+                    self.emit(0x8005 | xy(x, y))
+                } else {
+                    // NOTE: compiled as addition of the negated value
+                    let kk = self.byte()?;
+                    self.emit(0x7000 | xy(x, 0) | (kk.wrapping_neg()) as u16)
+                }
+            }
+            _ => self.error(format!("unknown operator: {}", operator)),
+        }
+    }
+
+    // the skip instruction of condition, after its setup
+    fn emit_skip_unless(&mut self, condition: Condition) -> Result<(), OctoError> {
+        for opcode in condition.setup() {
+            self.emit(opcode)?;
+        }
+        self.emit(condition.skip_unless())
+    }
+
+    fn condition(&mut self) -> Result<Condition, OctoError> {
+        let x = self.register()?;
+        let operator = self.next()?;
+        match operator.as_str() {
+            "key" => return Ok(Condition::Key(x)),
+            "-key" => return Ok(Condition::NotKey(x)),
+            "==" | "!=" | "<" | ">" | "<=" | ">=" => (),
+            _ => return self.error(format!("unsupported comparison: {}", operator)),
+        }
+
+        let operand = match self.peek() {
+            Some(operand) => operand.to_string(),
+            None => return self.error("unexpected end of file".to_string()),
+        };
+        let xy = |x: u8, y: u8| ((x as u16) << 8) | ((y as u16) << 4);
+        if self.is_register(&operand) {
+            let y = self.register()?;
+            // NOTE: vf := a; vf -= b sets VF when a >= b
+            let (a, b) = match operator.as_str() {
+                "==" => return Ok(Condition::EqReg(x, y)),
+                "!=" => return Ok(Condition::NeReg(x, y)),
+                "<" | ">=" => (x, y),
+                _ => (y, x),
+            };
+            Ok(Condition::Flag {
+                setup: [0x8000 | xy(0xF, a), 0x8005 | xy(0xF, b)],
+                set: operator == ">=" || operator == "<=",
+            })
+        } else {
+            let kk = self.byte()?;
+            // NOTE: vf := kk; vf =- vx sets VF when vx >= kk, vf -= vx when kk >= vx
+            let subtract = match operator.as_str() {
+                "==" => return Ok(Condition::EqByte(x, kk)),
+                "!=" => return Ok(Condition::NeByte(x, kk)),
+                "<" | ">=" => 0x8007,
+                _ => 0x8005,
+            };
+            Ok(Condition::Flag {
+                setup: [0x6F00 | kk as u16, subtract | xy(0xF, x)],
+                set: operator == ">=" || operator == "<=",
+            })
+        }
+    }
+
+    // :macro name args... { body }
+    fn define_macro(&mut self) -> Result<(), OctoError> {
+        let name = self.next()?;
+        if !is_name(&name) || is_keyword(&name) {
+            return self.error(format!("invalid name: {}", name));
+        }
+
+        let mut args = Vec::new();
+        loop {
+            let token = self.next()?;
+            if token == "{" {
+                break;
+            }
+            args.push(token);
+        }
+
+        let mut body = Vec::new();
+        let mut depth = 1;
+        loop {
+            let token = match self.tokens.pop_front() {
+                Some(token) => token,
+                None => return self.error(format!("unterminated macro: {}", name)),
+            };
+            match token.text.as_str() {
+                "{" => depth += 1,
+                "}" => {
+                    depth -= 1;
+                    if depth == 0 {
+                        break;
+                    }
+                }
+                _ => (),
+            }
+            body.push(token);
+        }
+        self.macros.insert(name, Macro { args, body });
+        Ok(())
+    }
+
+    fn expand_macro(&mut self, name: &str) -> Result<(), OctoError> {
+        self.macro_expansions += 1;
+        if self.macro_expansions > MAX_MACRO_EXPANSIONS {
+            return self.error(format!("too many macro expansions: {}", name));
+        }
+
+        let arg_count = self.macros[name].args.len();
+        let mut values = HashMap::new();
+        for i in 0..arg_count {
+            let value = self.next()?;
+            values.insert(self.macros[name].args[i].clone(), value);
+        }
+
+        // substitute arguments, expanded tokens keep the line of the invocation
+        let line = self.line;
+        let expanded: Vec<Token> = self.macros[name]
+            .body
+            .iter()
+            .map(|t| Token {
+                text: values
+                    .get(&t.text)
+                    .cloned()
+                    .unwrap_or_else(|| t.text.clone()),
+                line,
+            })
+            .collect();
+        for token in expanded.into_iter().rev() {
+            self.tokens.push_front(token);
+        }
+        Ok(())
+    }
+
+    // NOTE: like Octo, binary operators have equal precedence and are evaluated right to left
+    fn expression(&mut self) -> Result<f64, OctoError> {
+        let lhs = self.term()?;
+        let operator = match self.peek() {
+            Some(op) if is_binary_operator(op) => op.to_string(),
+            _ => return Ok(lhs),
+        };
+        self.next()?;
+        let rhs = self.expression()?;
+        Ok(match operator.as_str() {
+            "+" => lhs + rhs,
+            "-" => lhs - rhs,
+            "*" => lhs * rhs,
+            "/" => {
+                if rhs == 0.0 {
+                    return self.error("division by zero".to_string());
+                }
+                lhs / rhs
+            }
+            "%" => {
+                if rhs == 0.0 {
+                    return self.error("division by zero".to_string());
+                }
+                lhs % rhs
+            }
+            "&" => ((lhs as i64) & (rhs as i64)) as f64,
+            "|" => ((lhs as i64) | (rhs as i64)) as f64,
+            "^" => ((lhs as i64) ^ (rhs as i64)) as f64,
+            "<<" | ">>" => {
+                let shift = match u32::try_from(rhs as i64) {
+                    Ok(shift) => shift,
+                    Err(_) => return self.error(format!("invalid shift: {}", rhs)),
+                };
+                let result = if operator == "<<" {
+                    (lhs as i64).checked_shl(shift)
+                } else {
+                    (lhs as i64).checked_shr(shift)
+                };
+                match result {
+                    Some(result) => result as f64,
+                    None => return self.error(format!("invalid shift: {}", rhs)),
+                }
+            }
+            "min" => lhs.min(rhs),
+            "max" => lhs.max(rhs),
+            _ => unreachable!(),
+        })
+    }
+
+    fn term(&mut self) -> Result<f64, OctoError> {
+        let token = self.next()?;
+        match token.as_str() {
+            "(" => {
+                let v = self.expression()?;
+                self.expect(")")?;
+                Ok(v)
+            }
+            "-" => Ok(-self.term()?),
+            "~" => Ok(!(self.term()? as i64) as f64),
+            "!" => Ok(if self.term()? == 0.0 { 1.0 } else { 0.0 }),
+            "floor" => Ok(self.term()?.floor()),
+            "HERE" => Ok(self.here as f64),
+            _ => match self.value_of(&token) {
+                Some(v) => Ok(v),
+                None => self.error(format!("undefined name: {}", token)),
+            },
+        }
+    }
+}
+
+fn is_binary_operator(s: &str) -> bool {
+    matches!(
+        s,
+        "+" | "-" | "*" | "/" | "%" | "&" | "|" | "^" | "<<" | ">>" | "min" | "max"
+    )
+}
+
+fn is_name(s: &str) -> bool {
+    let mut chars = s.chars();
+    match chars.next() {
+        Some(c) if c.is_ascii_alphabetic() || c == '_' => (),
+        _ => return false,
+    }
+    chars.all(|c| c.is_ascii_alphanumeric() || c == '_' || c == '-')
+}
+
+fn is_keyword(s: &str) -> bool {
+    matches!(
+        s,
+        "clear"
+            | "return"
+            | "jump"
+            | "jump0"
+            | "bcd"
+            | "save"
+            | "load"
+            | "sprite"
+            | "delay"
+            | "buzzer"
+            | "i"
+            | "if"
+            | "then"
+            | "begin"
+            | "else"
+            | "end"
+            | "loop"
+            | "while"
+            | "again"
+            | "key"
+            | "random"
+            | "hex"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn rom(source: &str) -> Vec<u8> {
+        compile(source).unwrap().rom
+    }
+
+    fn error(source: &str) -> OctoError {
+        compile(source).unwrap_err()
+    }
+
+    #[test]
+    fn test_instructions() {
+        let source = "
+            : main
+            clear return ;
+            v1 := 0x12  v1 := v2  v1 += 3  v1 += v2  v1 -= 1  v1 -= v2  v1 =- v2
+            v1 |= v2  v1 &= v2  v1 ^= v2  v1 >>= v2  v1 <<= v2
+            v1 := random 0x0F  v1 := key  v1 := delay  delay := v1  buzzer := v1
+            i := 0x300  i := hex v1  i += v1  bcd v1  save v1  load v1  sprite v1 v2 5
+            jump 0x204  jump0 0x300  :call 0x208
+        ";
+        assert_eq!(
+            rom(source),
+            vec![
+                0x00, 0xE0, 0x00, 0xEE, 0x00, 0xEE, //
+                0x61, 0x12, 0x81, 0x20, 0x71, 0x03, 0x81, 0x24, 0x71, 0xFF, 0x81, 0x25, 0x81, 0x27,
+                0x81, 0x21, 0x81, 0x22, 0x81, 0x23, 0x81, 0x26, 0x81, 0x2E, //
+                0xC1, 0x0F, 0xF1, 0x0A, 0xF1, 0x07, 0xF1, 0x15, 0xF1, 0x18, //
+                0xA3, 0x00, 0xF1, 0x29, 0xF1, 0x1E, 0xF1, 0x33, 0xF1, 0x55, 0xF1, 0x65, 0xD1, 0x25,
+                0x12, 0x04, 0xB3, 0x00, 0x22, 0x08,
+            ]
+        );
+    }
+
+    #[test]
+    fn test_jump_to_main() {
+        // main is not first, so 0x200 jumps to it
+        assert_eq!(
+            rom(": sub return : main sub"),
+            vec![0x12, 0x04, 0x00, 0xEE, 0x22, 0x02]
+        );
+    }
+
+    #[test]
+    fn test_labels_and_forward_references() {
+        let program = compile(": main i := data jump main : data 0xFF 0b1").unwrap();
+        assert_eq!(program.rom, vec![0xA2, 0x04, 0x12, 0x00, 0xFF, 0x01]);
+        assert_eq!(program.symbols["data"], 0x204);
+        assert_eq!(program.symbol_table(), "0x200 main\n0x204 data\n");
+    }
+
+    #[test]
+    fn test_if_then() {
+        assert_eq!(
+            rom(": main if v0 == 1 then v1 := 2 if v0 != v2 then clear if v3 key then clear if v3 -key then clear"),
+            vec![0x40, 0x01, 0x61, 0x02, 0x50, 0x20, 0x00, 0xE0, 0xE3, 0xA1, 0x00, 0xE0, 0xE3, 0x9E, 0x00, 0xE0]
+        );
+    }
+
+    #[test]
+    fn test_comparisons() {
+        assert_eq!(
+            rom(": main if v1 < v2 then clear if v1 >= 5 then clear"),
+            vec![
+                0x8F, 0x10, 0x8F, 0x25, 0x3F, 0x01, 0x00, 0xE0, // vf := v1 vf -= v2
+                0x6F, 0x05, 0x8F, 0x17, 0x4F, 0x01, 0x00, 0xE0, // vf := 5 vf =- v1
+            ]
+        );
+
+        // v2, v3 and v4 are set by 'then', 'begin' and 'while' if the comparison holds
+        let run = |source: String| {
+            let mut chip8 = crate::chip8::Chip8::new();
+            chip8.load_program(&rom(&source));
+            for _ in 0..40 {
+                chip8.step();
+            }
+            [2, 3, 4].map(|x| chip8.read_register(x))
+        };
+        for &(a, b) in &[(3, 5), (5, 5), (5, 3), (0, 255)] {
+            for &(operator, holds) in &[("<", a < b), (">", a > b), ("<=", a <= b), (">=", a >= b)]
+            {
+                for operand in &["v1".to_string(), b.to_string()] {
+                    let condition = format!("v0 {} {}", operator, operand);
+                    let source = format!(
+                        ": main v0 := {} v1 := {}
+                         if {c} then v2 := 1
+                         if {c} begin v3 := 1 else v3 := 2 end
+                         loop while {c} v4 := 1 jump halt again
+                         : halt jump halt",
+                        a,
+                        b,
+                        c = condition
+                    );
+                    let expected = if holds { [1, 1, 1] } else { [0, 2, 0] };
+                    assert_eq!(run(source), expected, "{} with v0 = {}", condition, a);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_if_begin_else_end() {
+        assert_eq!(
+            rom(": main if v0 == 1 begin v1 := 1 else v1 := 2 end"),
+            vec![
+                0x30, 0x01, // 0x200: skip if v0 == 1
+                0x12, 0x08, // 0x202: jump else
+                0x61, 0x01, // 0x204
+                0x12, 0x0A, // 0x206: jump end
+                0x61, 0x02, // 0x208
+            ]
+        );
+        assert_eq!(
+            rom(": main if v0 != v1 begin clear end"),
+            vec![0x90, 0x10, 0x12, 0x06, 0x00, 0xE0]
+        );
+    }
+
+    #[test]
+    fn test_loop_while_again() {
+        assert_eq!(
+            rom(": main loop v0 += 1 while v0 != 10 again"),
+            vec![
+                0x70, 0x01, // 0x200
+                0x40, 0x0A, // 0x202: skip if v0 != 10
+                0x12, 0x08, // 0x204: jump out
+                0x12, 0x00, // 0x206: again
+            ]
+        );
+    }
+
+    #[test]
+    fn test_const_alias_calc() {
+        assert_eq!(
+            rom(":const SPEED 3 :alias x v5 :calc DOUBLE { SPEED * 2 + 1 } : main x := SPEED x += DOUBLE"),
+            // NOTE: right to left evaluation, SPEED * (2 + 1)
+            vec![0x65, 0x03, 0x75, 0x09]
+        );
+        assert_eq!(
+            rom(":calc A { ( 3 * 2 ) + 1 } : main v0 := A"),
+            vec![0x60, 0x07]
+        );
+        assert_eq!(
+            rom(": main :calc NEXT { HERE - 0x1FE } v0 := NEXT"),
+            vec![0x60, 0x02]
+        );
+        assert_eq!(
+            rom(":calc S { 1 << 4 } :calc T { 0x80 >> 3 } : main v0 := S v1 := T"),
+            vec![0x60, 0x10, 0x61, 0x10]
+        );
+    }
+
+    #[test]
+    fn test_macro() {
+        assert_eq!(
+            rom(":macro set reg value { reg := value } : main set v1 7 set v2 8"),
+            vec![0x61, 0x07, 0x62, 0x08]
+        );
+    }
+
+    #[test]
+    fn test_org() {
+        assert_eq!(
+            rom(": main jump main :org 0x206 0xAA"),
+            vec![0x12, 0x00, 0, 0, 0, 0, 0xAA]
+        );
+        assert_eq!(
+            error(": main :org 0x1FF 0xAA").message,
+            "address out of range: 511"
+        );
+        assert_eq!(
+            error(": main :org 0x1000 0xAA").message,
+            "address out of range: 4096"
+        );
+    }
+
+    #[test]
+    fn test_comments() {
+        assert_eq!(rom("# comment\n: main # more\nclear"), vec![0x00, 0xE0]);
+    }
+
+    #[test]
+    fn test_errors() {
+        assert_eq!(
+            error(": main\n\njump nowhere"),
+            OctoError {
+                line: 3,
+                message: "undefined label: nowhere".to_string()
+            }
+        );
+        assert_eq!(error("clear").message, "undefined label: main");
+        assert_eq!(error(": main\nv0 := 256").message, "byte out of range: 256");
+        assert_eq!(error(": main\nloop").message, "unterminated block");
+        assert_eq!(error(": main\nend").message, "'end' without 'if ... begin'");
+        assert_eq!(
+            error(": main\nif v0 =< 3 then").message,
+            "unsupported comparison: =<"
+        );
+        assert_eq!(
+            error(":calc X { 1 << 64 } : main").message,
+            "invalid shift: 64"
+        );
+        assert_eq!(
+            error(":calc X { 1 >> ( 0 - 1 ) } : main").message,
+            "invalid shift: -1"
+        );
+        assert_eq!(error(": main : main").message, "duplicate name: main");
+        assert_eq!(
+            error(": main\n:macro m { m } m").message,
+            "too many macro expansions: m"
+        );
+    }
+}
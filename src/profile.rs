@@ -0,0 +1,406 @@
+//! Execution profiler.
+//!
+//! Counts the instructions executed at every address and of every type, and the time spent in
+//! subroutines, measured in instructions, which are the cycles of CHIP-8.
+//!
+//! Subroutine time follows the call stack: a CALL opens a frame for its target, and the frame is
+//! closed once the stack pointer drops below it (RET, or a program resetting the stack).
+//! Inclusive time counts everything executed in the frame and the subroutines it calls,
+//! exclusive time only its own instructions.
+use crate::chip8::Chip8;
+use crate::disasm::{self, Syntax};
+use crate::instruction::{self, Instruction};
+
+use std::collections::BTreeMap;
+use std::fmt::Write;
+
+// NOTE: number of addresses listed as hot spots in the text report
+const HOT_SPOTS: usize = 20;
+
+/// Time spent in one subroutine.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Subroutine {
+    /// Number of calls.
+    pub calls: u64,
+    /// Instructions executed inside, including nested calls.
+    pub inclusive: u64,
+    /// Instructions executed inside, excluding nested calls.
+    pub exclusive: u64,
+}
+
+// an active call
+struct Frame {
+    address: u16,
+    // instructions executed before the first one of the subroutine
+    start: u64,
+    // stack pointer inside the subroutine
+    depth: u8,
+}
+
+/// Counts executed instructions per address, instruction type and subroutine.
+pub struct Profiler {
+    instructions: u64,
+    // executions and last executed opcode per address
+    executions: Vec<u64>,
+    opcodes: Vec<u16>,
+    kinds: BTreeMap<&'static str, u64>,
+    subroutines: BTreeMap<u16, Subroutine>,
+    frames: Vec<Frame>,
+}
+
+impl Default for Profiler {
+    fn default() -> Profiler {
+        Profiler::new()
+    }
+}
+
+impl Profiler {
+    /// Profiler with nothing counted yet.
+    pub fn new() -> Profiler {
+        Profiler {
+            instructions: 0,
+            executions: vec![0; 0x1000],
+            opcodes: vec![0; 0x1000],
+            kinds: BTreeMap::new(),
+            subroutines: BTreeMap::new(),
+            frames: Vec::new(),
+        }
+    }
+
+    /// Called before each instruction.
+    pub fn on_instruction(&mut self, chip8: &Chip8) {
+        // leave the frames returned from
+        let stack_pointer = chip8.stack_pointer();
+        while self
+            .frames
+            .last()
+            .is_some_and(|frame| frame.depth > stack_pointer)
+        {
+            let frame = self.frames.pop().unwrap();
+            self.close(&frame);
+        }
+
+        let pc = chip8.program_counter() % 0x1000;
+        let opcode = chip8.read_memory_u16(pc);
+        let instruction = instruction::decode(opcode);
+        self.instructions += 1;
+        self.executions[pc as usize] += 1;
+        self.opcodes[pc as usize] = opcode;
+        *self.kinds.entry(kind(instruction)).or_insert(0) += 1;
+        if let Some(frame) = self.frames.last() {
+            self.subroutines.entry(frame.address).or_default().exclusive += 1;
+        }
+
+        if let Instruction::Call(nnn) = instruction {
+            self.subroutines.entry(nnn).or_default().calls += 1;
+            self.frames.push(Frame {
+                address: nnn,
+                start: self.instructions,
+                depth: stack_pointer.wrapping_add(1),
+            });
+        }
+    }
+
+    fn close(&mut self, frame: &Frame) {
+        // NOTE: recursive calls are part of the outermost one, counting them again would
+        // exceed the total
+        if self
+            .frames
+            .iter()
+            .any(|outer| outer.address == frame.address)
+        {
+            return;
+        }
+        self.subroutines.entry(frame.address).or_default().inclusive +=
+            self.instructions - frame.start;
+    }
+
+    /// Number of instructions executed.
+    pub fn instructions(&self) -> u64 {
+        self.instructions
+    }
+
+    /// Executions of the instruction at `address`.
+    pub fn executions(&self, address: u16) -> u64 {
+        self.executions[address as usize % 0x1000]
+    }
+
+    /// Executions per instruction type, e.g. "DRW Vx, Vy, nibble".
+    pub fn instruction_types(&self) -> &BTreeMap<&'static str, u64> {
+        &self.kinds
+    }
+
+    /// Time per subroutine address, calls still running count up to now.
+    pub fn subroutines(&self) -> BTreeMap<u16, Subroutine> {
+        let mut subroutines = self.subroutines.clone();
+        for (i, frame) in self.frames.iter().enumerate() {
+            if self.frames[..i]
+                .iter()
+                .all(|outer| outer.address != frame.address)
+            {
+                subroutines.entry(frame.address).or_default().inclusive +=
+                    self.instructions - frame.start;
+            }
+        }
+        subroutines
+    }
+
+    // executed addresses, most executed first
+    fn hot_spots(&self) -> Vec<u16> {
+        let mut addresses: Vec<u16> = (0..0x1000)
+            .filter(|address| self.executions[*address as usize] > 0)
+            .collect();
+        addresses.sort_by_key(|address| std::cmp::Reverse(self.executions[*address as usize]));
+        addresses
+    }
+
+    fn percent(&self, count: u64) -> f64 {
+        if self.instructions == 0 {
+            0.0
+        } else {
+            count as f64 * 100.0 / self.instructions as f64
+        }
+    }
+
+    /// Human readable report with the hot spots, instruction types and subroutines.
+    pub fn text_report(&self) -> String {
+        let mut report = String::new();
+        writeln!(report, "instructions executed: {}", self.instructions).unwrap();
+
+        writeln!(report, "\nhot spots:").unwrap();
+        writeln!(report, "  address       count  percent  instruction").unwrap();
+        for address in self.hot_spots().into_iter().take(HOT_SPOTS) {
+            let count = self.executions[address as usize];
+            writeln!(
+                report,
+                "  0x{:03X}  {:>12}  {:>6.2}%  {}",
+                address,
+                count,
+                self.percent(count),
+                disasm::disassemble(self.opcodes[address as usize])
+            )
+            .unwrap();
+        }
+
+        writeln!(report, "\ninstruction types:").unwrap();
+        let mut kinds: Vec<(&&str, &u64)> = self.kinds.iter().collect();
+        kinds.sort_by_key(|(_, count)| std::cmp::Reverse(**count));
+        for (kind, count) in kinds {
+            writeln!(
+                report,
+                "  {:<20}  {:>12}  {:>6.2}%",
+                kind,
+                count,
+                self.percent(*count)
+            )
+            .unwrap();
+        }
+
+        writeln!(report, "\nsubroutines:").unwrap();
+        writeln!(
+            report,
+            "  address   calls     inclusive  percent     exclusive  percent"
+        )
+        .unwrap();
+        let mut subroutines: Vec<(u16, Subroutine)> = self.subroutines().into_iter().collect();
+        subroutines.sort_by_key(|(_, subroutine)| std::cmp::Reverse(subroutine.inclusive));
+        for (address, subroutine) in subroutines {
+            writeln!(
+                report,
+                "  0x{:03X}  {:>8}  {:>12}  {:>6.2}%  {:>12}  {:>6.2}%",
+                address,
+                subroutine.calls,
+                subroutine.inclusive,
+                self.percent(subroutine.inclusive),
+                subroutine.exclusive,
+                self.percent(subroutine.exclusive)
+            )
+            .unwrap();
+        }
+        report
+    }
+
+    /// Same report as JSON.
+    // NOTE: mnemonics and instruction types never contain characters which need escaping.
+    pub fn json_report(&self) -> String {
+        let addresses: Vec<String> = (0..0x1000)
+            .filter(|address| self.executions[*address] > 0)
+            .map(|address| {
+                format!(
+                    "{{\"address\":{},\"opcode\":{},\"instruction\":\"{}\",\"count\":{}}}",
+                    address,
+                    self.opcodes[address],
+                    disasm::disassemble(self.opcodes[address]),
+                    self.executions[address]
+                )
+            })
+            .collect();
+        let kinds: Vec<String> = self
+            .kinds
+            .iter()
+            .map(|(kind, count)| format!("\"{}\":{}", kind, count))
+            .collect();
+        let subroutines: Vec<String> = self
+            .subroutines()
+            .iter()
+            .map(|(address, subroutine)| {
+                format!(
+                    "{{\"address\":{},\"calls\":{},\"inclusive\":{},\"exclusive\":{}}}",
+                    address, subroutine.calls, subroutine.inclusive, subroutine.exclusive
+                )
+            })
+            .collect();
+        format!(
+            "{{\"instructions\":{},\"addresses\":[{}],\"instruction_types\":{{{}}},\"subroutines\":[{}]}}\n",
+            self.instructions,
+            addresses.join(","),
+            kinds.join(","),
+            subroutines.join(",")
+        )
+    }
+
+    /// Disassembly of `rom` with the executions of every instruction in front.
+    // NOTE: executed addresses are code, even where control flow could not be followed statically.
+    pub fn annotate(&self, rom: &[u8], syntax: Syntax) -> String {
+        let mut code = disasm::trace_code(rom);
+        let end = 0x200 + rom.len().min(0xE00);
+        code.extend((0x200..end as u16).filter(|address| self.executions(*address) > 0));
+
+        let mut listing = String::new();
+        for line in disasm::disassemble_rom_with_code(rom, syntax, &code).lines() {
+            let count = line
+                .strip_prefix("0x")
+                .and_then(|line| line.split(':').next())
+                .and_then(|address| u16::from_str_radix(address, 16).ok())
+                .map(|address| self.executions(address))
+                .filter(|count| *count > 0);
+            match count {
+                Some(count) => writeln!(
+                    listing,
+                    "{:>12} {:>6.2}%  {}",
+                    count,
+                    self.percent(count),
+                    line
+                )
+                .unwrap(),
+                None => writeln!(listing, "{:>20}  {}", "", line).unwrap(),
+            }
+        }
+        listing
+    }
+}
+
+// instruction type, in the notation of Cowgod's Chip-8 Technical Reference
+fn kind(instruction: Instruction) -> &'static str {
+    match instruction {
+        Instruction::Sys(_) => "SYS addr",
+        Instruction::Cls => "CLS",
+        Instruction::Ret => "RET",
+        Instruction::Jp(_) => "JP addr",
+        Instruction::Call(_) => "CALL addr",
+        Instruction::SeByte(..) => "SE Vx, byte",
+        Instruction::SneByte(..) => "SNE Vx, byte",
+        Instruction::SeReg(..) => "SE Vx, Vy",
+        Instruction::LdByte(..) => "LD Vx, byte",
+        Instruction::AddByte(..) => "ADD Vx, byte",
+        Instruction::LdReg(..) => "LD Vx, Vy",
+        Instruction::Or(..) => "OR Vx, Vy",
+        Instruction::And(..) => "AND Vx, Vy",
+        Instruction::Xor(..) => "XOR Vx, Vy",
+        Instruction::AddReg(..) => "ADD Vx, Vy",
+        Instruction::Sub(..) => "SUB Vx, Vy",
+        Instruction::Shr(..) => "SHR Vx, Vy",
+        Instruction::Subn(..) => "SUBN Vx, Vy",
+        Instruction::Shl(..) => "SHL Vx, Vy",
+        Instruction::SneReg(..) => "SNE Vx, Vy",
+        Instruction::LdI(_) => "LD I, addr",
+        Instruction::JpV0(_) => "JP V0, addr",
+        Instruction::Rnd(..) => "RND Vx, byte",
+        Instruction::Drw(..) => "DRW Vx, Vy, nibble",
+        Instruction::Skp(_) => "SKP Vx",
+        Instruction::Sknp(_) => "SKNP Vx",
+        Instruction::LdVxDt(_) => "LD Vx, DT",
+        Instruction::LdVxK(_) => "LD Vx, K",
+        Instruction::LdDtVx(_) => "LD DT, Vx",
+        Instruction::LdStVx(_) => "LD ST, Vx",
+        Instruction::AddI(_) => "ADD I, Vx",
+        Instruction::LdF(_) => "LD F, Vx",
+        Instruction::LdB(_) => "LD B, Vx",
+        Instruction::Store(_) => "LD [I], Vx",
+        Instruction::Load(_) => "LD Vx, [I]",
+        Instruction::Unknown(_) => "unknown",
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // loop: CALL outer; JP loop; outer: CALL inner; CALL inner; RET; inner: ADD V0, 1; RET
+    const PROGRAM: [u8; 16] = [
+        0x22, 0x04, 0x12, 0x00, 0x22, 0x0C, 0x22, 0x0C, 0x00, 0xEE, 0x00, 0x00, 0x70, 0x01, 0x00,
+        0xEE,
+    ];
+
+    fn profile(instructions: usize) -> Profiler {
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&PROGRAM);
+        let mut profiler = Profiler::new();
+        for _ in 0..instructions {
+            profiler.on_instruction(&chip8);
+            chip8.step();
+        }
+        profiler
+    }
+
+    #[test]
+    fn counts() {
+        // one iteration of the loop is 9 instructions
+        let profiler = profile(90);
+        assert_eq!(profiler.instructions(), 90);
+        assert_eq!(profiler.executions(0x200), 10);
+        assert_eq!(profiler.executions(0x20C), 20);
+        assert_eq!(profiler.executions(0x20A), 0);
+        assert_eq!(profiler.instruction_types()["CALL addr"], 30);
+        assert_eq!(profiler.instruction_types()["RET"], 30);
+        assert_eq!(profiler.instruction_types()["ADD Vx, byte"], 20);
+    }
+
+    #[test]
+    fn subroutine_time() {
+        let profiler = profile(90);
+        let subroutines = profiler.subroutines();
+        // CALL, CALL, RET and twice ADD, RET
+        let outer = subroutines[&0x204];
+        assert_eq!(outer.calls, 10);
+        assert_eq!(outer.inclusive, 70);
+        assert_eq!(outer.exclusive, 30);
+        let inner = subroutines[&0x20C];
+        assert_eq!(inner.calls, 20);
+        assert_eq!(inner.inclusive, 40);
+        assert_eq!(inner.exclusive, 40);
+
+        // stopped inside inner: CALL outer, CALL inner, ADD
+        let subroutines = profile(93).subroutines();
+        assert_eq!(subroutines[&0x204].inclusive, 72);
+        assert_eq!(subroutines[&0x20C].inclusive, 41);
+    }
+
+    #[test]
+    fn reports() {
+        let profiler = profile(90);
+        let text = profiler.text_report();
+        assert!(text.starts_with("instructions executed: 90\n"));
+        assert!(text.contains("  0x20C            20   22.22%  ADD V0, 0x01\n"));
+        assert!(text.contains("  0x204        10            70   77.78%            30   33.33%\n"));
+
+        let json = profiler.json_report();
+        assert!(json.starts_with("{\"instructions\":90,\"addresses\":[{\"address\":512,"));
+        assert!(json.contains("{\"address\":516,\"calls\":10,\"inclusive\":70,\"exclusive\":30}"));
+        assert!(json.contains("\"RET\":30"));
+
+        let listing = profiler.annotate(&PROGRAM, Syntax::Cowgod);
+        assert!(listing.contains("          20  22.22%  0x20C: 7001        ADD V0, 0x01\n"));
+        assert!(listing.contains("                      0x20A: 00 00       DB 0x00, 0x00\n"));
+    }
+}
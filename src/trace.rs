@@ -0,0 +1,506 @@
+//! Per-instruction execution trace.
+//!
+//! Each record is the machine state *before* the instruction at PC is executed.
+//!
+//! Text format, one line per instruction, all numbers are uppercase hex. Every field but the
+//! trailing mnemonic has fixed width, so traces diff line by line:
+//!
+//! ```text
+//! PPPP OOOO V=00 11 22 33 44 55 66 77 88 99 AA BB CC DD EE FF I=IIII SP=SS DT=DD ST=SS MNEMONIC
+//! 0200 6005 V=00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 00 I=0000 SP=00 DT=00 ST=00 LD V0, 0x05
+//! ```
+//!
+//! Binary format, 25 bytes per instruction, multi-byte fields big-endian: PC (2), opcode (2),
+//! V0-VF (16), I (2), SP (1), DT (1), ST (1).
+//!
+//! Reference traces for lockstep comparison use the text format, the mnemonic is optional and
+//! ignored, blank lines and lines starting with `#` are skipped.
+use crate::chip8::{Chip8, Fault};
+use crate::disasm;
+use crate::instruction::{self, Instruction};
+
+use std::collections::VecDeque;
+use std::error::Error;
+use std::fs::File;
+use std::io::prelude::*;
+use std::io::BufWriter;
+use std::num::ParseIntError;
+use std::str::FromStr;
+
+/// Size of a record in the binary trace format.
+pub const RECORD_SIZE: usize = 25;
+
+/// Machine state at one instruction.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct TraceRecord {
+    /// Address of the instruction.
+    pub program_counter: u16,
+    /// Instruction about to execute.
+    pub opcode: u16,
+    /// V0 to VF.
+    pub register: [u8; 16],
+    /// I.
+    pub index_register: u16,
+    /// Number of return addresses on the stack.
+    pub stack_pointer: u8,
+    /// Delay timer.
+    pub delay_timer: u8,
+    /// Sound timer.
+    pub sound_timer: u8,
+}
+
+impl TraceRecord {
+    /// State before the next instruction of `chip8`.
+    pub fn capture(chip8: &Chip8) -> TraceRecord {
+        let mut register = [0; 16];
+        for (x, value) in register.iter_mut().enumerate() {
+            *value = chip8.read_register(x as u8);
+        }
+        let program_counter = chip8.program_counter();
+        TraceRecord {
+            program_counter,
+            opcode: chip8.read_memory_u16(program_counter),
+            register,
+            index_register: chip8.index_register(),
+            stack_pointer: chip8.stack_pointer(),
+            delay_timer: chip8.delay_timer(),
+            sound_timer: chip8.sound_timer(),
+        }
+    }
+
+    /// Line of the text trace format.
+    pub fn to_line(self) -> String {
+        let register: Vec<String> = self.register.iter().map(|v| format!("{:02X}", v)).collect();
+        format!(
+            "{:04X} {:04X} V={} I={:04X} SP={:02X} DT={:02X} ST={:02X} {}",
+            self.program_counter,
+            self.opcode,
+            register.join(" "),
+            self.index_register,
+            self.stack_pointer,
+            self.delay_timer,
+            self.sound_timer,
+            disasm::disassemble(self.opcode)
+        )
+    }
+
+    /// Parses a text trace line.
+    pub fn parse(line: &str) -> Result<TraceRecord, String> {
+        let mut fields = line.split_whitespace();
+        let program_counter = parse_field(&mut fields, "", "PC", u16::from_str_radix)?;
+        let opcode = parse_field(&mut fields, "", "opcode", u16::from_str_radix)?;
+        let mut register = [0; 16];
+        for (x, value) in register.iter_mut().enumerate() {
+            let prefix = if x == 0 { "V=" } else { "" };
+            let name = format!("V{:X}", x);
+            *value = parse_field(&mut fields, prefix, &name, u8::from_str_radix)?;
+        }
+        Ok(TraceRecord {
+            program_counter,
+            opcode,
+            register,
+            index_register: parse_field(&mut fields, "I=", "I", u16::from_str_radix)?,
+            stack_pointer: parse_field(&mut fields, "SP=", "SP", u8::from_str_radix)?,
+            delay_timer: parse_field(&mut fields, "DT=", "DT", u8::from_str_radix)?,
+            sound_timer: parse_field(&mut fields, "ST=", "ST", u8::from_str_radix)?,
+        })
+    }
+
+    /// Names of the fields which differ from other.
+    pub fn differences(&self, other: &TraceRecord) -> Vec<String> {
+        let mut names = Vec::new();
+        if self.program_counter != other.program_counter {
+            names.push("PC".to_string());
+        }
+        if self.opcode != other.opcode {
+            names.push("opcode".to_string());
+        }
+        for x in 0..16 {
+            if self.register[x] != other.register[x] {
+                names.push(format!("V{:X}", x));
+            }
+        }
+        if self.index_register != other.index_register {
+            names.push("I".to_string());
+        }
+        if self.stack_pointer != other.stack_pointer {
+            names.push("SP".to_string());
+        }
+        if self.delay_timer != other.delay_timer {
+            names.push("DT".to_string());
+        }
+        if self.sound_timer != other.sound_timer {
+            names.push("ST".to_string());
+        }
+        names
+    }
+
+    /// Record of the binary trace format, numbers are big endian.
+    pub fn to_bytes(self) -> [u8; RECORD_SIZE] {
+        let mut bytes = [0; RECORD_SIZE];
+        bytes[0..2].copy_from_slice(&self.program_counter.to_be_bytes());
+        bytes[2..4].copy_from_slice(&self.opcode.to_be_bytes());
+        bytes[4..20].copy_from_slice(&self.register);
+        bytes[20..22].copy_from_slice(&self.index_register.to_be_bytes());
+        bytes[22] = self.stack_pointer;
+        bytes[23] = self.delay_timer;
+        bytes[24] = self.sound_timer;
+        bytes
+    }
+}
+
+// next "<prefix><hex>" field of a text trace line
+fn parse_field<'a, T>(
+    fields: &mut impl Iterator<Item = &'a str>,
+    prefix: &str,
+    name: &str,
+    from_str_radix: fn(&str, u32) -> Result<T, ParseIntError>,
+) -> Result<T, String> {
+    let s = fields.next().ok_or(format!("missing {}", name))?;
+    let s = s
+        .strip_prefix(prefix)
+        .ok_or(format!("expected {}{}: {}", prefix, name, s))?;
+    from_str_radix(s, 16).map_err(|_| format!("invalid {}: {}", name, s))
+}
+
+/// Output format of the tracer.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceFormat {
+    /// One line per instruction.
+    Text,
+    /// Fixed size records of [`RECORD_SIZE`] bytes.
+    Binary,
+}
+
+impl FromStr for TraceFormat {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<TraceFormat, String> {
+        match s {
+            "text" => Ok(TraceFormat::Text),
+            "binary" => Ok(TraceFormat::Binary),
+            _ => Err(format!("unknown trace format: {}", s)),
+        }
+    }
+}
+
+/// Inclusive range of traced addresses.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct AddressRange {
+    /// First address.
+    pub start: u16,
+    /// Last address.
+    pub end: u16,
+}
+
+impl AddressRange {
+    /// Whether `address` is in the range.
+    pub fn contains(&self, address: u16) -> bool {
+        self.start <= address && address <= self.end
+    }
+}
+
+// parse "START-END" in hex
+impl FromStr for AddressRange {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<AddressRange, String> {
+        let parse = |s: &str| {
+            let s = s.trim_start_matches("0x").trim_start_matches("0X");
+            u16::from_str_radix(s, 16).map_err(|_| format!("invalid address: {}", s))
+        };
+        let mut split = s.splitn(2, '-');
+        match (split.next(), split.next()) {
+            (Some(start), Some(end)) => {
+                let range = AddressRange {
+                    start: parse(start)?,
+                    end: parse(end)?,
+                };
+                if range.start > range.end {
+                    return Err(format!("empty address range: {}", s));
+                }
+                Ok(range)
+            }
+            _ => Err(format!("address range must be START-END: {}", s)),
+        }
+    }
+}
+
+/// Writes the state before every instruction to a file.
+pub struct Tracer {
+    writer: Box<dyn Write>,
+    format: TraceFormat,
+    range: Option<AddressRange>,
+}
+
+impl Tracer {
+    /// Traces into `path`, "-" for stdout.
+    pub fn create(
+        path: &str,
+        format: TraceFormat,
+        range: Option<AddressRange>,
+    ) -> Result<Tracer, Box<dyn Error>> {
+        let writer: Box<dyn Write> = if path == "-" {
+            Box::new(BufWriter::new(std::io::stdout()))
+        } else {
+            Box::new(BufWriter::new(File::create(path)?))
+        };
+        Ok(Tracer {
+            writer,
+            format,
+            range,
+        })
+    }
+
+    /// Called before each instruction.
+    pub fn on_instruction(&mut self, chip8: &Chip8) -> Result<(), Box<dyn Error>> {
+        if let Some(range) = self.range {
+            if !range.contains(chip8.program_counter()) {
+                return Ok(());
+            }
+        }
+
+        let record = TraceRecord::capture(chip8);
+        match self.format {
+            TraceFormat::Text => writeln!(self.writer, "{}", record.to_line())?,
+            TraceFormat::Binary => self.writer.write_all(&record.to_bytes())?,
+        }
+        Ok(())
+    }
+
+    /// Flushes the trace.
+    pub fn finish(mut self) -> Result<(), Box<dyn Error>> {
+        self.writer.flush()?;
+        Ok(())
+    }
+}
+
+/// Parses a reference trace in text format.
+pub fn parse_reference(text: &str) -> Result<Vec<TraceRecord>, String> {
+    text.lines()
+        .enumerate()
+        .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+        .map(|(n, line)| TraceRecord::parse(line).map_err(|e| format!("line {}: {}", n + 1, e)))
+        .collect()
+}
+
+/// First instruction where execution does not match the reference.
+pub struct Divergence {
+    /// Number of instructions executed before the divergence.
+    pub index: usize,
+    /// State in the reference.
+    pub expected: TraceRecord,
+    /// State of the emulator.
+    pub actual: TraceRecord,
+    /// Preceding records, oldest first.
+    pub context: Vec<TraceRecord>,
+}
+
+/// Instruction on which the machine faulted while following the reference.
+pub struct LockstepFault {
+    /// Number of instructions executed before the fault.
+    pub index: usize,
+    /// State before the faulting instruction, it matches the reference.
+    pub record: TraceRecord,
+    /// Why the machine stopped.
+    pub fault: Fault,
+    /// Preceding records, oldest first.
+    pub context: Vec<TraceRecord>,
+}
+
+/// Why execution stopped following the reference.
+pub enum Mismatch {
+    /// The state differs from the reference.
+    Divergence(Divergence),
+    /// The machine faulted, it cannot execute the rest of the reference.
+    Fault(LockstepFault),
+}
+
+/// Executes `chip8` in lockstep with the reference trace until states differ or it faults.
+// NOTE: timers tick after every instructions_per_frame instructions, no keys are pressed and
+// RND results are taken from the reference so random programs can be compared.
+pub fn run_lockstep(
+    chip8: &mut Chip8,
+    reference: &[TraceRecord],
+    instructions_per_frame: usize,
+    context_size: usize,
+) -> Option<Mismatch> {
+    let mut context = VecDeque::with_capacity(context_size + 1);
+    for (index, expected) in reference.iter().enumerate() {
+        let actual = TraceRecord::capture(chip8);
+        if actual != *expected {
+            return Some(Mismatch::Divergence(Divergence {
+                index,
+                expected: *expected,
+                actual,
+                context: context.into_iter().collect(),
+            }));
+        }
+
+        chip8.step();
+        if let Some(fault) = chip8.fault() {
+            return Some(Mismatch::Fault(LockstepFault {
+                index,
+                record: actual,
+                fault,
+                context: context.into_iter().collect(),
+            }));
+        }
+
+        context.push_back(actual);
+        if context.len() > context_size {
+            context.pop_front();
+        }
+
+        if let Instruction::Rnd(x, _) = instruction::decode(actual.opcode) {
+            if let Some(next) = reference.get(index + 1) {
+                chip8.write_register(x, next.register[x as usize]);
+            }
+        }
+        if (index + 1) % instructions_per_frame == 0 {
+            chip8.tick_timers();
+        }
+    }
+    None
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn record() -> TraceRecord {
+        let mut register = [0; 16];
+        for (x, value) in register.iter_mut().enumerate() {
+            *value = (x as u8) * 0x11;
+        }
+        TraceRecord {
+            program_counter: 0x200,
+            opcode: 0x6005,
+            register,
+            index_register: 0x2A0,
+            stack_pointer: 1,
+            delay_timer: 0x3C,
+            sound_timer: 0,
+        }
+    }
+
+    #[test]
+    fn text_format() {
+        assert_eq!(
+            record().to_line(),
+            "0200 6005 V=00 11 22 33 44 55 66 77 88 99 AA BB CC DD EE FF \
+             I=02A0 SP=01 DT=3C ST=00 LD V0, 0x05"
+        );
+    }
+
+    #[test]
+    fn parse_text_format() {
+        assert_eq!(TraceRecord::parse(&record().to_line()), Ok(record()));
+        // mnemonic is optional
+        let line =
+            "0200 6005 V=00 11 22 33 44 55 66 77 88 99 AA BB CC DD EE FF I=02A0 SP=01 DT=3C ST=00";
+        assert_eq!(TraceRecord::parse(line), Ok(record()));
+        assert!(TraceRecord::parse("0200 6005 V=00 11").is_err());
+        // byte fields must fit in a byte
+        assert_eq!(
+            TraceRecord::parse(&line.replace(" 33 ", " 1FF ")),
+            Err("invalid V3: 1FF".to_string())
+        );
+        assert_eq!(
+            TraceRecord::parse(&line.replace("SP=01", "SP=101")),
+            Err("invalid SP: 101".to_string())
+        );
+        assert_eq!(
+            TraceRecord::parse(&line.replace("DT=3C", "DT=13C")),
+            Err("invalid DT: 13C".to_string())
+        );
+
+        let reference = parse_reference(&format!("# header\n\n{}\n", line)).unwrap();
+        assert_eq!(reference, vec![record()]);
+        assert_eq!(
+            parse_reference("0200 XXXX").unwrap_err(),
+            "line 1: invalid opcode: XXXX"
+        );
+    }
+
+    #[test]
+    fn lockstep() {
+        // LD V0, 0x05; ADD V0, 0x01; LD V1, V0; JP 0x206
+        let program = vec![0x60, 0x05, 0x70, 0x01, 0x81, 0x00, 0x12, 0x06];
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&program);
+        let mut reference = Vec::new();
+        for _ in 0..5 {
+            reference.push(TraceRecord::capture(&chip8));
+            chip8.step();
+        }
+
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&program);
+        assert!(run_lockstep(&mut chip8, &reference, 10, 2).is_none());
+
+        reference[3].register[1] = 0x07;
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&program);
+        let divergence = match run_lockstep(&mut chip8, &reference, 10, 2) {
+            Some(Mismatch::Divergence(divergence)) => divergence,
+            _ => panic!("expected a divergence"),
+        };
+        assert_eq!(divergence.index, 3);
+        assert_eq!(divergence.actual.register[1], 0x06);
+        assert_eq!(
+            divergence.expected.differences(&divergence.actual),
+            vec!["V1"]
+        );
+        assert_eq!(divergence.context, reference[1..3].to_vec());
+    }
+
+    #[test]
+    fn lockstep_fault() {
+        // LD V0, 0x05; RET with an empty stack
+        let program = vec![0x60, 0x05, 0x00, 0xEE];
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&program);
+        let mut reference = Vec::new();
+        for _ in 0..3 {
+            reference.push(TraceRecord::capture(&chip8));
+            chip8.step();
+        }
+
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&program);
+        let fault = match run_lockstep(&mut chip8, &reference, 10, 2) {
+            Some(Mismatch::Fault(fault)) => fault,
+            _ => panic!("expected a fault"),
+        };
+        assert_eq!(fault.index, 1);
+        assert_eq!(fault.record, reference[1]);
+        assert_eq!(fault.record.program_counter, 0x202);
+        assert_eq!(fault.fault, Fault::StackUnderflow);
+        assert_eq!(fault.context, reference[..1].to_vec());
+    }
+
+    #[test]
+    fn binary_format() {
+        let bytes = record().to_bytes();
+        assert_eq!(&bytes[0..4], &[0x02, 0x00, 0x60, 0x05]);
+        assert_eq!(bytes[4 + 0xF], 0xFF);
+        assert_eq!(&bytes[20..], &[0x02, 0xA0, 0x01, 0x3C, 0x00]);
+    }
+
+    #[test]
+    fn address_range() {
+        let range: AddressRange = "200-2fe".parse().unwrap();
+        assert_eq!(
+            range,
+            AddressRange {
+                start: 0x200,
+                end: 0x2FE
+            }
+        );
+        assert!(range.contains(0x2FE));
+        assert!(!range.contains(0x300));
+        assert!("300-200".parse::<AddressRange>().is_err());
+        assert!("200".parse::<AddressRange>().is_err());
+    }
+}
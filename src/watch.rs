@@ -0,0 +1,402 @@
+//! Watchpoints: break when an instruction accesses memory, changes a register, or leaves a
+//! register or I at a watched value.
+//!
+//! Checked after each instruction, against the state before it, so a hit names the instruction
+//! responsible. Memory accesses are those of the instruction's operands (DRW reads, `LD B` and
+//! `LD [I], Vx` writes, `LD Vx, [I]` reads); opcode fetches do not count.
+//!
+//! Syntax, numbers are hex:
+//!
+//! ```text
+//! ADDR[-END] [r|w|rw]   memory access (default: w)
+//! vX                    VX changes
+//! vX == VALUE           VX becomes VALUE
+//! i START-END           I moves into the range
+//! ```
+use crate::chip8::{Chip8, CpuState};
+use crate::disasm::disassemble;
+use crate::instruction::{self, Instruction};
+use crate::trace::AddressRange;
+
+use std::fmt;
+use std::str::FromStr;
+
+/// Kind of memory access a watchpoint triggers on.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Access {
+    /// Reads only.
+    Read,
+    /// Writes only.
+    Write,
+    /// Reads and writes.
+    ReadWrite,
+}
+
+impl Access {
+    fn matches(self, access: Access) -> bool {
+        self == Access::ReadWrite || self == access
+    }
+}
+
+/// Condition which stops execution.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Watchpoint {
+    /// Access to an address in the range.
+    Memory(AddressRange, Access),
+    /// Vx changes.
+    RegisterChange(u8),
+    /// Vx reaches the value.
+    RegisterValue(u8, u8),
+    /// I enters the range.
+    IndexIn(AddressRange),
+}
+
+fn parse_hex(s: &str) -> Result<u16, String> {
+    let digits = s.trim_start_matches("0x").trim_start_matches("0X");
+    u16::from_str_radix(digits, 16).map_err(|_| format!("invalid number: {}", s))
+}
+
+fn parse_register(s: &str) -> Option<u8> {
+    let digit = s.strip_prefix('v').or_else(|| s.strip_prefix('V'))?;
+    match u8::from_str_radix(digit, 16) {
+        Ok(x) if digit.len() == 1 => Some(x),
+        _ => None,
+    }
+}
+
+// "ADDR" or "START-END"
+fn parse_range(s: &str) -> Result<AddressRange, String> {
+    let range = if s.contains('-') {
+        s.parse()?
+    } else {
+        let address = parse_hex(s)?;
+        AddressRange {
+            start: address,
+            end: address,
+        }
+    };
+    if range.end > 0xFFF {
+        return Err(format!("address out of range: {}", s));
+    }
+    Ok(range)
+}
+
+impl FromStr for Watchpoint {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Watchpoint, String> {
+        let args: Vec<&str> = s.split_whitespace().collect();
+        match args.as_slice() {
+            [register] if parse_register(register).is_some() => Ok(Watchpoint::RegisterChange(
+                parse_register(register).unwrap(),
+            )),
+            [register, "==", value] if parse_register(register).is_some() => {
+                let value = parse_hex(value)?;
+                if value > 0xFF {
+                    return Err(format!("invalid byte: {:X}", value));
+                }
+                Ok(Watchpoint::RegisterValue(
+                    parse_register(register).unwrap(),
+                    value as u8,
+                ))
+            }
+            ["i", range] | ["I", range] => Ok(Watchpoint::IndexIn(parse_range(range)?)),
+            [range] => Ok(Watchpoint::Memory(parse_range(range)?, Access::Write)),
+            [range, access] => {
+                let access = match *access {
+                    "r" => Access::Read,
+                    "w" => Access::Write,
+                    "rw" => Access::ReadWrite,
+                    _ => return Err(format!("access must be r, w or rw: {}", access)),
+                };
+                Ok(Watchpoint::Memory(parse_range(range)?, access))
+            }
+            _ => Err(format!("invalid watchpoint: {}", s)),
+        }
+    }
+}
+
+fn format_range(range: AddressRange) -> String {
+    if range.start == range.end {
+        format!("0x{:03X}", range.start)
+    } else {
+        format!("0x{:03X}-0x{:03X}", range.start, range.end)
+    }
+}
+
+impl fmt::Display for Watchpoint {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Watchpoint::Memory(range, access) => {
+                let access = match access {
+                    Access::Read => "read",
+                    Access::Write => "write",
+                    Access::ReadWrite => "read/write",
+                };
+                write!(f, "{} of {}", access, format_range(range))
+            }
+            Watchpoint::RegisterChange(x) => write!(f, "V{:X} changes", x),
+            Watchpoint::RegisterValue(x, value) => write!(f, "V{:X} == 0x{:02X}", x, value),
+            Watchpoint::IndexIn(range) => write!(f, "I in {}", format_range(range)),
+        }
+    }
+}
+
+// memory operands of an instruction: (access, first address, length)
+pub(crate) fn memory_access(
+    instruction: Instruction,
+    state: &CpuState,
+) -> Option<(Access, u16, u16)> {
+    let i = state.index_register;
+    match instruction {
+        Instruction::Drw(_, _, n) => Some((Access::Read, i, n as u16)),
+        Instruction::LdB(_) => Some((Access::Write, i, 3)),
+        Instruction::Store(x) => Some((Access::Write, i, x as u16 + 1)),
+        Instruction::Load(x) => Some((Access::Read, i, x as u16 + 1)),
+        _ => None,
+    }
+}
+
+/// A triggered watchpoint.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Hit {
+    /// Number of the watchpoint, as listed.
+    pub number: usize,
+    /// Address of the instruction responsible.
+    pub address: u16,
+    /// Opcode of the instruction responsible.
+    pub opcode: u16,
+    /// What happened, e.g. the old and new value.
+    pub detail: String,
+}
+
+impl fmt::Display for Hit {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(
+            f,
+            "watchpoint {} hit by 0x{:03X} {}: {}",
+            self.number,
+            self.address,
+            disassemble(self.opcode),
+            self.detail
+        )
+    }
+}
+
+/// Numbered list of watchpoints, numbers start at 1.
+#[derive(Default)]
+pub struct Watchpoints {
+    // NOTE: removed watchpoints leave a hole, so numbers stay stable
+    watchpoints: Vec<Option<Watchpoint>>,
+    // state and opcode before the last instruction
+    previous: Option<(CpuState, u16)>,
+}
+
+impl Watchpoints {
+    /// Empty list.
+    pub fn new() -> Watchpoints {
+        Watchpoints::default()
+    }
+
+    /// Returns the number of the new watchpoint.
+    pub fn add(&mut self, watchpoint: Watchpoint) -> usize {
+        self.watchpoints.push(Some(watchpoint));
+        self.watchpoints.len()
+    }
+
+    /// Removes watchpoint `number`, the numbers of the others stay the same.
+    pub fn remove(&mut self, number: usize) -> Option<Watchpoint> {
+        self.watchpoints.get_mut(number.checked_sub(1)?)?.take()
+    }
+
+    /// (number, watchpoint) in order of creation.
+    pub fn iter(&self) -> impl Iterator<Item = (usize, &Watchpoint)> {
+        self.watchpoints
+            .iter()
+            .enumerate()
+            .filter_map(|(i, w)| w.as_ref().map(|w| (i + 1, w)))
+    }
+
+    /// Whether no watchpoint is set.
+    pub fn is_empty(&self) -> bool {
+        self.iter().next().is_none()
+    }
+
+    /// Remembers the state before the next instruction, call this right before executing it.
+    pub fn record(&mut self, chip8: &Chip8) {
+        let state = chip8.cpu_state();
+        let opcode = chip8.read_memory_u16(state.program_counter);
+        self.previous = Some((state, opcode));
+    }
+
+    /// Watchpoints triggered by the instruction executed since `record`.
+    pub fn check(&self, chip8: &Chip8) -> Vec<Hit> {
+        match self.previous {
+            Some((before, opcode)) => self.check_instruction(before, opcode, chip8),
+            None => Vec::new(),
+        }
+    }
+
+    /// Watchpoints triggered by `opcode` executed from state `before`, resulting in `chip8`.
+    pub fn check_instruction(&self, before: CpuState, opcode: u16, chip8: &Chip8) -> Vec<Hit> {
+        let after = chip8.cpu_state();
+        let access = memory_access(instruction::decode(opcode), &before);
+
+        let mut hits = Vec::new();
+        for (number, watchpoint) in self.iter() {
+            let detail = match *watchpoint {
+                Watchpoint::Memory(range, watched) => access.and_then(|(access, start, len)| {
+                    let touched = (0..len)
+                        .map(|offset| start.wrapping_add(offset) % 0x1000)
+                        .find(|&address| range.contains(address))?;
+                    if !watched.matches(access) {
+                        return None;
+                    }
+                    let verb = if access == Access::Read {
+                        "read"
+                    } else {
+                        "wrote"
+                    };
+                    Some(format!(
+                        "{} 0x{:03X} (0x{:02X})",
+                        verb,
+                        touched,
+                        chip8.read_memory(touched)
+                    ))
+                }),
+                Watchpoint::RegisterChange(x) => {
+                    let (old, new) = (before.registers[x as usize], after.registers[x as usize]);
+                    if old != new {
+                        Some(format!("V{:X} 0x{:02X} -> 0x{:02X}", x, old, new))
+                    } else {
+                        None
+                    }
+                }
+                // NOTE: only when the value is reached, not while it stays
+                Watchpoint::RegisterValue(x, value) => {
+                    let (old, new) = (before.registers[x as usize], after.registers[x as usize]);
+                    if old != value && new == value {
+                        Some(format!("V{:X} = 0x{:02X}", x, new))
+                    } else {
+                        None
+                    }
+                }
+                Watchpoint::IndexIn(range) => {
+                    let (old, new) = (before.index_register, after.index_register);
+                    if !range.contains(old) && range.contains(new) {
+                        Some(format!("I = 0x{:03X}", new))
+                    } else {
+                        None
+                    }
+                }
+            };
+            if let Some(detail) = detail {
+                hits.push(Hit {
+                    number,
+                    address: before.program_counter,
+                    opcode,
+                    detail,
+                });
+            }
+        }
+        hits
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn load(opcodes: &[u16]) -> Chip8 {
+        let program: Vec<u8> = opcodes.iter().flat_map(|op| op.to_be_bytes()).collect();
+        let mut chip8 = Chip8::new();
+        chip8.load_program(&program);
+        chip8
+    }
+
+    // run until a watchpoint hits, like the debugger
+    fn run(chip8: &mut Chip8, watchpoints: &mut Watchpoints, max: usize) -> Vec<Hit> {
+        for _ in 0..max {
+            watchpoints.record(chip8);
+            chip8.step();
+            let hits = watchpoints.check(chip8);
+            if !hits.is_empty() {
+                return hits;
+            }
+        }
+        Vec::new()
+    }
+
+    #[test]
+    fn parse() {
+        let range = |start, end| AddressRange { start, end };
+        assert_eq!(
+            "3F0".parse(),
+            Ok(Watchpoint::Memory(range(0x3F0, 0x3F0), Access::Write))
+        );
+        assert_eq!(
+            "0x300-0x30F rw".parse(),
+            Ok(Watchpoint::Memory(range(0x300, 0x30F), Access::ReadWrite))
+        );
+        assert_eq!("vA".parse(), Ok(Watchpoint::RegisterChange(0xA)));
+        assert_eq!("V3 == 10".parse(), Ok(Watchpoint::RegisterValue(3, 0x10)));
+        assert_eq!(
+            "i 300-3FF".parse(),
+            Ok(Watchpoint::IndexIn(range(0x300, 0x3FF)))
+        );
+        assert!("300 x".parse::<Watchpoint>().is_err());
+        assert!("1000".parse::<Watchpoint>().is_err());
+        assert!("v3 == 100".parse::<Watchpoint>().is_err());
+    }
+
+    #[test]
+    fn memory_write() {
+        // LD I, 0x300; LD V0, [I]; LD V0, 7; LD I, 0x3F0; LD B, V0
+        let mut chip8 = load(&[0xA300, 0xF065, 0x6007, 0xA3F0, 0xF033]);
+        let mut watchpoints = Watchpoints::new();
+        watchpoints.add("3F0-3FF".parse().unwrap());
+        let hits = run(&mut chip8, &mut watchpoints, 10);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].number, 1);
+        assert_eq!(hits[0].address, 0x208);
+        assert_eq!(
+            hits[0].to_string(),
+            "watchpoint 1 hit by 0x208 LD B, V0: wrote 0x3F0 (0x00)"
+        );
+    }
+
+    #[test]
+    fn memory_read() {
+        // LD I, 0x300; LD [I], V0 (write, ignored); LD I, 0x2FE; LD V3, [I]
+        let mut chip8 = load(&[0xA300, 0xF055, 0xA2FE, 0xF365]);
+        let mut watchpoints = Watchpoints::new();
+        watchpoints.add("300 r".parse().unwrap());
+        let hits = run(&mut chip8, &mut watchpoints, 10);
+        assert_eq!(hits.len(), 1);
+        assert_eq!(hits[0].address, 0x206);
+        assert_eq!(hits[0].detail, "read 0x300 (0x00)");
+    }
+
+    #[test]
+    fn registers_and_index() {
+        // LD V3, 5; ADD V3, 1; LD V3, 6; LD I, 0x350
+        let mut chip8 = load(&[0x6305, 0x7301, 0x6306, 0xA350]);
+        let mut watchpoints = Watchpoints::new();
+        watchpoints.add("v3 == 6".parse().unwrap());
+        watchpoints.add("i 300-3FF".parse().unwrap());
+
+        let hits = run(&mut chip8, &mut watchpoints, 10);
+        assert_eq!((hits[0].number, hits[0].address), (1, 0x202));
+        // V3 stays 6, not hit again
+        let hits = run(&mut chip8, &mut watchpoints, 10);
+        assert_eq!((hits[0].number, hits[0].address), (2, 0x206));
+
+        let number = watchpoints.add("v3".parse().unwrap());
+        assert_eq!(watchpoints.remove(1), Some(Watchpoint::RegisterValue(3, 6)));
+        assert_eq!(watchpoints.remove(1), None);
+        assert_eq!(
+            watchpoints.iter().map(|(n, _)| n).collect::<Vec<_>>(),
+            [2, number]
+        );
+    }
+}
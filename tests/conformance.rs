@@ -0,0 +1,218 @@
+// conformance harness, runs test ROMs headless and compares the display against golden images
+//
+// cases live in tests/conformance: Cowgod assembly sources and golden images, which are 32 lines
+// of 64 pixels, '#' lit and '.' unlit. run with UPDATE_GOLDEN=1 to rewrite the golden images.
+use chip8_emulator::asm;
+use chip8_emulator::{Chip8, Quirks};
+
+use std::path::PathBuf;
+
+const WIDTH: usize = 64;
+const HEIGHT: usize = 32;
+
+const INSTRUCTIONS_PER_FRAME: usize = 15;
+
+// key state change applied before the given frame
+struct KeyEvent {
+    frame: usize,
+    key: usize,
+    pressed: bool,
+}
+
+struct Case {
+    // golden image name
+    name: &'static str,
+    source: &'static str,
+    frames: usize,
+    quirks: Quirks,
+    keys: &'static [KeyEvent],
+}
+
+fn case_path(name: &str) -> PathBuf {
+    PathBuf::from(env!("CARGO_MANIFEST_DIR"))
+        .join("tests")
+        .join("conformance")
+        .join(name)
+}
+
+fn to_image(display: &[bool]) -> String {
+    display
+        .chunks(WIDTH)
+        .map(|row| {
+            let mut line: String = row.iter().map(|v| if *v { '#' } else { '.' }).collect();
+            line.push('\n');
+            line
+        })
+        .collect()
+}
+
+// '+' lit only in actual, '-' lit only in golden image
+fn visual_diff(expected: &str, actual: &str) -> String {
+    expected
+        .lines()
+        .zip(actual.lines())
+        .map(|(e, a)| {
+            let mut line: String = e
+                .chars()
+                .zip(a.chars())
+                .map(|pair| match pair {
+                    ('.', '#') => '+',
+                    ('#', '.') => '-',
+                    (_, a) => a,
+                })
+                .collect();
+            line.push('\n');
+            line
+        })
+        .collect()
+}
+
+fn run(case: &Case) -> String {
+    let rom = asm::assemble_file(case_path(case.source).to_str().unwrap())
+        .unwrap_or_else(|e| panic!("{}", e));
+
+    let mut chip8 = Chip8::new();
+    chip8.set_quirks(case.quirks);
+    chip8.load_program(&rom);
+    for frame in 0..case.frames {
+        for event in case.keys.iter().filter(|event| event.frame == frame) {
+            chip8.keyboard[event.key] = event.pressed;
+        }
+        chip8
+            .step_frame(INSTRUCTIONS_PER_FRAME)
+            .unwrap_or_else(|fault| panic!("{}: {}", case.source, fault));
+    }
+    to_image(&chip8.display)
+}
+
+fn check(case: Case) {
+    let actual = run(&case);
+    let golden = case_path(&format!("{}.txt", case.name));
+    if std::env::var_os("UPDATE_GOLDEN").is_some() {
+        std::fs::write(&golden, &actual).unwrap();
+        return;
+    }
+
+    let expected = std::fs::read_to_string(&golden)
+        .unwrap_or_else(|e| panic!("failed to read {}: {}", golden.display(), e));
+    assert_eq!(expected.lines().count(), HEIGHT, "{}", golden.display());
+    if expected != actual {
+        panic!(
+            "{} does not match {} ('+' unexpectedly lit, '-' unexpectedly unlit):\n{}",
+            case.source,
+            golden.display(),
+            visual_diff(&expected, &actual)
+        );
+    }
+}
+
+#[test]
+fn font() {
+    check(Case {
+        name: "font",
+        source: "font.asm",
+        frames: 10,
+        quirks: Quirks::default(),
+        keys: &[],
+    });
+}
+
+#[test]
+fn flags() {
+    for (name, quirks) in [
+        ("flags", Quirks::default()),
+        ("flags", Quirks::cosmac()),
+        ("flags", Quirks::schip()),
+    ] {
+        check(Case {
+            name,
+            source: "flags.asm",
+            frames: 60,
+            quirks,
+            keys: &[],
+        });
+    }
+}
+
+#[test]
+fn collision() {
+    check(Case {
+        name: "collision",
+        source: "collision.asm",
+        frames: 20,
+        quirks: Quirks::default(),
+        keys: &[],
+    });
+}
+
+#[test]
+fn keypad() {
+    const KEYS: &[KeyEvent] = &[
+        KeyEvent {
+            frame: 5,
+            key: 0xA,
+            pressed: true,
+        },
+        KeyEvent {
+            frame: 6,
+            key: 0xA,
+            pressed: false,
+        },
+        KeyEvent {
+            frame: 10,
+            key: 0x5,
+            pressed: true,
+        },
+    ];
+    check(Case {
+        name: "keypad",
+        source: "keypad.asm",
+        frames: 20,
+        quirks: Quirks::default(),
+        keys: KEYS,
+    });
+    // only the first key has been pressed
+    check(Case {
+        name: "keypad-waiting",
+        source: "keypad.asm",
+        frames: 8,
+        quirks: Quirks::default(),
+        keys: KEYS,
+    });
+}
+
+#[test]
+fn timers() {
+    check(Case {
+        name: "timers",
+        source: "timers.asm",
+        frames: 40,
+        quirks: Quirks::default(),
+        keys: &[],
+    });
+    // delay timer still running
+    check(Case {
+        name: "timers-running",
+        source: "timers.asm",
+        frames: 20,
+        quirks: Quirks::default(),
+        keys: &[],
+    });
+}
+
+#[test]
+fn quirks() {
+    for (name, quirks) in [
+        ("quirks-default", Quirks::default()),
+        ("quirks-cosmac", Quirks::cosmac()),
+        ("quirks-schip", Quirks::schip()),
+    ] {
+        check(Case {
+            name,
+            source: "quirks.asm",
+            frames: 20,
+            quirks,
+            keys: &[],
+        });
+    }
+}
@@ -0,0 +1,42 @@
+; sprite collision and wrapping, draws VF after each sprite in the top row
+start:  LD VA, 1
+        LD VB, 1
+
+        ; box on an empty screen: 0
+        LD I, box
+        LD V3, 20
+        LD V4, 12
+        DRW V3, V4, 4
+        LD V0, VF
+        CALL show
+
+        ; overlapping box: 1
+        LD I, box
+        LD V3, 22
+        LD V4, 14
+        DRW V3, V4, 4
+        LD V0, VF
+        CALL show
+
+        ; hollow sprite next to the boxes, blank pixels do not collide: 0
+        LD I, hollow
+        LD V3, 34
+        LD V4, 12
+        DRW V3, V4, 4
+        LD V0, VF
+        CALL show
+
+        ; box wraps around the bottom right corner: 0
+        LD I, box
+        LD V3, 62
+        LD V4, 30
+        DRW V3, V4, 4
+        LD V0, VF
+        CALL show
+
+        JP halt
+
+box:    DB 0xF0, 0xF0, 0xF0, 0xF0
+hollow: DB 0xF0, 0x90, 0x90, 0xF0
+
+        INCLUDE "common.asm"
@@ -0,0 +1,32 @@
+; shared routines for conformance ROMs
+;
+; registers: V0 argument, VA/VB cursor x/y, VD/VE scratch
+
+; draw V0 as a hex digit at the cursor and advance it
+show:   LD F, V0
+        DRW VA, VB, 5
+        ADD VA, 5
+        RET
+
+; draw V0 as two hex digits followed by a gap
+showbyte:
+        LD VD, V0
+        SHR V0
+        SHR V0
+        SHR V0
+        SHR V0
+        CALL show
+        LD V0, VD
+        LD VE, 0x0F
+        AND V0, VE
+        CALL show
+        ADD VA, 2
+        RET
+
+; move the cursor to the start of the next row
+newline:
+        LD VA, 1
+        ADD VB, 6
+        RET
+
+halt:   JP halt
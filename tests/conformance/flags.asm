@@ -0,0 +1,108 @@
+; arithmetic results and VF, each test draws "RESULT FLAG"
+start:  LD VA, 1
+        LD VB, 1
+
+        ; ADD with carry: 10 01
+        LD V3, 0xF0
+        LD V4, 0x20
+        ADD V3, V4
+        LD V7, VF
+        LD V0, V3
+        CALL showbyte
+        LD V0, V7
+        CALL showbyte
+
+        ; ADD without carry: 30 00
+        LD VF, 0x55
+        LD V3, 0x10
+        LD V4, 0x20
+        ADD V3, V4
+        LD V7, VF
+        LD V0, V3
+        CALL showbyte
+        LD V0, V7
+        CALL showbyte
+        CALL newline
+
+        ; SUB equal operands do not borrow: 00 01
+        LD V3, 0x10
+        LD V4, 0x10
+        SUB V3, V4
+        LD V7, VF
+        LD V0, V3
+        CALL showbyte
+        LD V0, V7
+        CALL showbyte
+
+        ; SUB with borrow: E0 00
+        LD V3, 0x10
+        LD V4, 0x30
+        SUB V3, V4
+        LD V7, VF
+        LD V0, V3
+        CALL showbyte
+        LD V0, V7
+        CALL showbyte
+        CALL newline
+
+        ; SUBN: 20 01
+        LD V3, 0x10
+        LD V4, 0x30
+        SUBN V3, V4
+        LD V7, VF
+        LD V0, V3
+        CALL showbyte
+        LD V0, V7
+        CALL showbyte
+
+        ; SHR shifts out the low bit: 02 01
+        LD V3, 0x05
+        SHR V3, V3
+        LD V7, VF
+        LD V0, V3
+        CALL showbyte
+        LD V0, V7
+        CALL showbyte
+        CALL newline
+
+        ; SHL shifts out the high bit: 02 01
+        LD V3, 0x81
+        SHL V3, V3
+        LD V7, VF
+        LD V0, V3
+        CALL showbyte
+        LD V0, V7
+        CALL showbyte
+
+        ; VF as destination keeps the flag: 01 01
+        LD VF, 0xFF
+        LD V4, 0x01
+        ADD VF, V4
+        LD V0, VF
+        CALL showbyte
+        LD V0, VF
+        CALL showbyte
+        CALL newline
+
+        ; SHR VF keeps the flag: 01 01
+        LD VF, 0x03
+        SHR VF, VF
+        LD V7, VF
+        LD V0, V7
+        CALL showbyte
+        LD V0, V7
+        CALL showbyte
+
+        ; SUB VF as destination keeps the flag: 00 00
+        LD VF, 0x01
+        LD V4, 0x02
+        SUB VF, V4
+        LD V7, VF
+        LD V0, V7
+        CALL showbyte
+        LD V0, V7
+        CALL showbyte
+
+        JP halt
+
+        INCLUDE "common.asm"
@@ -0,0 +1,14 @@
+; draws the built-in font, 0-7 on the first row and 8-F on the second
+start:  LD VA, 1
+        LD VB, 1
+        LD V3, 0
+loop:   LD V0, V3
+        CALL show
+        ADD V3, 1
+        SNE V3, 8
+        CALL newline
+        SE V3, 16
+        JP loop
+        JP halt
+
+        INCLUDE "common.asm"
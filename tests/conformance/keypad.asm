@@ -0,0 +1,14 @@
+; waits for a key, draws it, then draws 5 once key 5 is held
+start:  LD VA, 1
+        LD VB, 1
+        LD V0, K
+        CALL show
+
+wait:   LD V3, 5
+        SKP V3
+        JP wait
+        LD V0, V3
+        CALL show
+        JP halt
+
+        INCLUDE "common.asm"
@@ -0,0 +1,40 @@
+; behaviors which differ between quirk presets
+start:  LD VA, 1
+        LD VB, 1
+
+        ; VF after OR: 05 (default, schip), 00 (cosmac)
+        LD VF, 0x05
+        LD V3, 0x01
+        LD V4, 0x02
+        OR V3, V4
+        LD V0, VF
+        CALL showbyte
+
+        ; SHR V3, V4: 40 (default, cosmac), 00 (schip)
+        LD V3, 0x01
+        LD V4, 0x80
+        SHR V3, V4
+        LD V0, V3
+        CALL showbyte
+        CALL newline
+
+        ; second FX65 reads the next byte: 22 (default, cosmac), 11 (schip)
+        LD I, data
+        LD V0, [I]
+        LD V0, [I]
+        CALL showbyte
+
+        ; BNNN with NNN in 0x2XX: AA (default, cosmac), BB (schip)
+        LD V8, 0xBB
+        LD V0, 0
+        LD V2, 2
+        JP V0, target
+target: LD V8, 0xAA
+        JP done
+done:   LD V0, V8
+        CALL showbyte
+        JP halt
+
+data:   DB 0x11, 0x22
+
+        INCLUDE "common.asm"
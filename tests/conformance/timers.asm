@@ -0,0 +1,14 @@
+; draws D once the delay timer runs out after 30 frames
+start:  LD VA, 1
+        LD VB, 1
+        LD V3, 30
+        LD DT, V3
+
+wait:   LD V0, DT
+        SE V0, 0
+        JP wait
+        LD V0, 0xD
+        CALL show
+        JP halt
+
+        INCLUDE "common.asm"